@@ -0,0 +1,110 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Order, Trade, journal::Command, risk::RejectReason};
+
+//one audit line: what was asked, how it ended and what it traded
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    seq: u64,
+    timestamp: u64,
+    market_id: u32,
+    event_seq: u64, // last engine event of the market once the command went through
+    command: &'a Command,
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order: Option<&'a Order>,
+    trades: &'a [Trade],
+}
+
+#[derive(Deserialize)]
+struct SeqOnly {
+    seq: u64,
+}
+
+//append only compliance record of every command, accepted or rejected, as json lines.
+//unlike the journal it is never replayed, so it can be shared by all markets of a process
+pub struct AuditLog {
+    file: Mutex<(File, u64)>, // file and next sequence number
+}
+
+impl AuditLog {
+    //new records continue after the last sequence number on disk
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let (next_seq, valid_len) = match File::open(&path) {
+            Ok(file) => Self::last_seq(file).map(|(last, valid_len)| (last + 1, valid_len))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (1, 0),
+            Err(e) => return Err(e),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        // drop a torn last line so new records start on a clean line
+        file.set_len(valid_len)?;
+        Ok(AuditLog {
+            file: Mutex::new((file, next_seq)),
+        })
+    }
+
+    //last sequence number plus the byte length of the complete lines
+    fn last_seq(file: File) -> io::Result<(u64, u64)> {
+        let mut reader = BufReader::new(file);
+        let (mut last, mut valid_len) = (0, 0);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                // a crash while writing leaves a torn last line without a complete seq
+                break;
+            }
+            if let Ok(record) = serde_json::from_str::<SeqOnly>(&line) {
+                last = record.seq;
+            }
+            valid_len += read as u64;
+        }
+        Ok((last, valid_len))
+    }
+
+    pub fn record(
+        &self,
+        timestamp: u64,
+        market_id: u32,
+        event_seq: u64,
+        command: &Command,
+        result: &Result<(Option<Order>, Vec<Trade>), RejectReason>,
+    ) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let (order, trades) = match result {
+            Ok((order, trades)) => (order.as_ref(), trades.as_slice()),
+            Err(_) => (None, &[][..]),
+        };
+        let record = AuditRecord {
+            seq: file.1,
+            timestamp,
+            market_id,
+            event_seq,
+            command,
+            outcome: if result.is_ok() {
+                "accepted"
+            } else {
+                "rejected"
+            },
+            reason: result.as_ref().err().map(ToString::to_string),
+            order,
+            trades,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        // the journal carries durability, audit lines are written through but not synced
+        file.0.write_all(&line)?;
+        file.1 += 1;
+        Ok(())
+    }
+}
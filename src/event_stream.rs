@@ -0,0 +1,98 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use deadpool_redis::{Pool, redis};
+use log::warn;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    events::EventEnvelope,
+    redis_worker::{RETRY_DELAY, WorkerError},
+};
+
+pub fn events_stream(market_id: u32) -> String {
+    format!("probo:market:{}:events", market_id)
+}
+
+//how much history a market stream keeps, trimming is approximate so redis can do it cheaply
+#[derive(Clone, Copy, Debug)]
+pub enum RetentionPolicy {
+    MaxLen(usize),
+    MaxAge(Duration),
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy::MaxLen(1_000_000)
+    }
+}
+
+//durable event log per market, consumers read it with XREADGROUP and resume from their last ack
+pub struct EventStream {
+    pool: Pool,
+    retention: RetentionPolicy,
+}
+
+impl EventStream {
+    pub fn new(pool: Pool, retention: RetentionPolicy) -> Self {
+        EventStream { pool, retention }
+    }
+
+    //appends engine events as they come, whatever queued up meanwhile goes out in one pipeline.
+    //a batch is retried until redis takes it, falling so far behind that events were dropped
+    //is an error since consumers would never see them
+    pub async fn run(
+        self,
+        mut events: broadcast::Receiver<EventEnvelope>,
+    ) -> Result<(), WorkerError> {
+        loop {
+            let mut batch = match events.recv().await {
+                Ok(envelope) => vec![envelope],
+                Err(RecvError::Lagged(missed)) => return Err(WorkerError::Lagged(missed)),
+                Err(RecvError::Closed) => return Ok(()),
+            };
+            while let Ok(envelope) = events.try_recv() {
+                batch.push(envelope);
+            }
+            while let Err(e) = self.append(&batch).await {
+                warn!(
+                    "could not append events, retrying in {:?}: {}",
+                    RETRY_DELAY, e
+                );
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+    }
+
+    pub async fn append(&self, events: &[EventEnvelope]) -> Result<(), WorkerError> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.pool.get().await?;
+        let mut pipe = redis::pipe();
+        for envelope in events {
+            let cmd = pipe.cmd("XADD").arg(events_stream(envelope.market_id));
+            match self.retention {
+                RetentionPolicy::MaxLen(len) => cmd.arg("MAXLEN").arg("~").arg(len),
+                RetentionPolicy::MaxAge(age) => {
+                    // stream ids start with the redis clock in ms, anything older than this id goes
+                    let now_ms = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis();
+                    let min_id = now_ms.saturating_sub(age.as_millis());
+                    cmd.arg("MINID").arg("~").arg(format!("{}-0", min_id))
+                }
+            };
+            cmd.arg("*")
+                .arg("seq")
+                .arg(envelope.seq)
+                .arg("type")
+                .arg(envelope.event.kind())
+                .arg("data")
+                .arg(serde_json::to_string(envelope).expect("events serialize"))
+                .ignore();
+        }
+        let _: () = pipe.query_async(&mut conn).await?;
+        Ok(())
+    }
+}
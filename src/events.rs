@@ -0,0 +1,94 @@
+use serde::Serialize;
+
+use crate::{OptionType, Order, OrderType, Trade};
+
+//everything that happens in a market, in the order it happened
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineEvent {
+    OrderAccepted {
+        order: Order,
+    },
+    OrderRejected {
+        user_id: Option<u32>,
+        reason: String,
+    },
+    OrderAmended {
+        order: Order,
+    },
+    OrderFilled {
+        order_id: u64,
+        user_id: u32,
+        price: f64,
+        filled_quantity: u32,
+        remaining_quantity: u32,
+    },
+    OrderCancelled {
+        order_id: u64,
+        user_id: u32,
+        remaining_quantity: u32,
+    },
+    Trade {
+        trade: Trade,
+    },
+    // a yes buy and a no buy matched, new share pairs were created
+    Mint {
+        trade_id: u64,
+        yes_user_id: u32,
+        no_user_id: u32,
+        quantity: u32,
+    },
+    Settlement {
+        outcome: OptionType,
+    },
+    // level 2 change of a book, book_seq counts these per market without gaps
+    BookDelta {
+        book_seq: u64,
+        option: OptionType,
+        side: OrderType,
+        price: f64,
+        quantity: u32, // aggregate at the price now, 0 when the level is gone
+    },
+}
+
+impl EngineEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EngineEvent::OrderAccepted { .. } => "order_accepted",
+            EngineEvent::OrderRejected { .. } => "order_rejected",
+            EngineEvent::OrderAmended { .. } => "order_amended",
+            EngineEvent::OrderFilled { .. } => "order_filled",
+            EngineEvent::OrderCancelled { .. } => "order_cancelled",
+            EngineEvent::Trade { .. } => "trade",
+            EngineEvent::Mint { .. } => "mint",
+            EngineEvent::Settlement { .. } => "settlement",
+            EngineEvent::BookDelta { .. } => "book_delta",
+        }
+    }
+
+    //owner of the order the event is about, trades and mints name both sides in the fills instead
+    pub fn user_id(&self) -> Option<u32> {
+        match self {
+            EngineEvent::OrderAccepted { order } | EngineEvent::OrderAmended { order } => {
+                Some(order.user_id)
+            }
+            EngineEvent::OrderRejected { user_id, .. } => *user_id,
+            EngineEvent::OrderFilled { user_id, .. }
+            | EngineEvent::OrderCancelled { user_id, .. } => Some(*user_id),
+            EngineEvent::Trade { .. }
+            | EngineEvent::Mint { .. }
+            | EngineEvent::Settlement { .. }
+            | EngineEvent::BookDelta { .. } => None,
+        }
+    }
+}
+
+//sequence numbers only advance on state changes, so replaying the journal reproduces them.
+//rejections change nothing and carry the sequence number of the last state change
+#[derive(Clone, Debug, Serialize)]
+pub struct EventEnvelope {
+    pub market_id: u32,
+    pub seq: u64,
+    pub timestamp: u64,
+    pub event: EngineEvent,
+}
@@ -0,0 +1,1359 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast::{
+        self,
+        error::{RecvError, TryRecvError},
+    },
+};
+
+use crate::{
+    OptionType, OrderType,
+    events::{EngineEvent, EventEnvelope},
+    journal::Command,
+    router::EngineRouter,
+    service::{EngineApi, ServiceError},
+};
+
+const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: u8 = 0x01;
+const DEFAULT_HEARTBEAT_SECS: u64 = 30;
+// order events kept per logged out counterparty, past that they are reconciled at logon
+const MISSED_LIMIT: usize = 100_000;
+
+// header tags the session writes itself, everything else is body
+const HEADER_TAGS: [u32; 7] = [35, 49, 56, 34, 52, 43, 122];
+
+#[derive(Debug, Error)]
+pub enum FixError {
+    #[error("garbled message: {0}")]
+    Garbled(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+//one fix message without BeginString, BodyLength and CheckSum, which encode adds
+#[derive(Clone, Debug)]
+pub struct FixMessage {
+    fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        FixMessage {
+            fields: vec![(35, msg_type.to_string())],
+        }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(35).unwrap_or_default()
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.get(34).and_then(|v| v.parse().ok()).unwrap_or(0)
+    }
+
+    fn flag(&self, tag: u32) -> bool {
+        self.get(tag) == Some("Y")
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let body: String = self
+            .fields
+            .iter()
+            .map(|(tag, value)| format!("{}={}\x01", tag, value))
+            .collect();
+        let mut raw = format!("8={}\x019={}\x01{}", BEGIN_STRING, body.len(), body).into_bytes();
+        let checksum = raw.iter().map(|b| *b as u32).sum::<u32>() % 256;
+        raw.extend(format!("10={:03}\x01", checksum).bytes());
+        raw
+    }
+
+    pub fn decode(raw: &[u8]) -> Result<Self, FixError> {
+        let text = std::str::from_utf8(raw).map_err(|e| FixError::Garbled(e.to_string()))?;
+        let checksum_at = text
+            .rfind("10=")
+            .ok_or_else(|| FixError::Garbled("no checksum".to_string()))?;
+        let expected = raw[..checksum_at].iter().map(|b| *b as u32).sum::<u32>() % 256;
+        let checksum = text[checksum_at + 3..].trim_end_matches('\x01');
+        if checksum.parse::<u32>().ok() != Some(expected) {
+            return Err(FixError::Garbled(format!("bad checksum {}", checksum)));
+        }
+
+        let mut fields = Vec::new();
+        for field in text[..checksum_at].split('\x01').filter(|f| !f.is_empty()) {
+            let (tag, value) = field
+                .split_once('=')
+                .ok_or_else(|| FixError::Garbled(format!("bad field {}", field)))?;
+            let tag = tag
+                .parse()
+                .map_err(|_| FixError::Garbled(format!("bad tag {}", tag)))?;
+            fields.push((tag, value.to_string()));
+        }
+        match fields.as_slice() {
+            [(8, begin), (9, _), (35, _), ..] if begin == BEGIN_STRING => {}
+            _ => return Err(FixError::Garbled("bad header".to_string())),
+        }
+        fields.drain(..2);
+        Ok(FixMessage { fields })
+    }
+}
+
+//cuts the first complete message off the buffer, None until one has fully arrived
+pub fn next_frame(buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, FixError> {
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    if !buf.starts_with(b"8=") {
+        // skip whatever precedes the next BeginString
+        let start = buf.windows(2).position(|w| w == b"8=").unwrap_or(buf.len());
+        buf.drain(..start);
+        return next_frame(buf);
+    }
+    let Some(first_soh) = buf.iter().position(|b| *b == SOH) else {
+        return Ok(None);
+    };
+    let length_start = first_soh + 1;
+    let Some(length_len) = buf[length_start..].iter().position(|b| *b == SOH) else {
+        return Ok(None);
+    };
+    let length_field = std::str::from_utf8(&buf[length_start..length_start + length_len])
+        .map_err(|e| FixError::Garbled(e.to_string()))?;
+    let body_length: usize = length_field
+        .strip_prefix("9=")
+        .and_then(|l| l.parse().ok())
+        .ok_or_else(|| FixError::Garbled(format!("bad body length {}", length_field)))?;
+    let end = length_start + length_len + 1 + body_length + "10=000\x01".len();
+    if buf.len() < end {
+        return Ok(None);
+    }
+    Ok(Some(buf.drain(..end).collect()))
+}
+
+//sequence numbers, sent messages and orders of one counterparty, kept on disk across restarts
+pub struct SessionStore {
+    seqnums: PathBuf,
+    messages: PathBuf,
+    orders: PathBuf,
+    offsets: BTreeMap<u64, u64>, // seq of a sent message -> where its line starts
+    pub next_out: u64,
+    pub next_in: u64,
+}
+
+impl SessionStore {
+    pub fn open(dir: &Path, counterparty: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let seqnums = dir.join(format!("{}.seqnums", counterparty));
+        let (next_out, next_in) = match fs::read_to_string(&seqnums) {
+            Ok(raw) => {
+                let mut parts = raw.split_whitespace().map(|p| p.parse::<u64>());
+                match (parts.next(), parts.next()) {
+                    (Some(Ok(out)), Some(Ok(inbound))) => (out, inbound),
+                    _ => return Err(io::Error::other(format!("corrupt {:?}", seqnums))),
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (1, 1),
+            Err(e) => return Err(e),
+        };
+        let messages = dir.join(format!("{}.messages", counterparty));
+        Ok(SessionStore {
+            seqnums,
+            offsets: index(&messages)?,
+            messages,
+            orders: dir.join(format!("{}.orders", counterparty)),
+            next_out,
+            next_in,
+        })
+    }
+
+    //starting over from 1 drops the messages kept for resends
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.next_out = 1;
+        self.next_in = 1;
+        File::create(&self.messages)?;
+        self.offsets.clear();
+        self.save()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let tmp = self.seqnums.with_extension("tmp");
+        fs::write(&tmp, format!("{} {}\n", self.next_out, self.next_in))?;
+        fs::rename(tmp, &self.seqnums)
+    }
+
+    //keeps a sent message for resends, one per line since fix never contains a newline
+    pub fn record(&mut self, raw: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.messages)?;
+        let offset = file.metadata()?.len();
+        file.write_all(raw)?;
+        file.write_all(b"\n")?;
+        self.offsets.insert(self.next_out, offset);
+        self.next_out += 1;
+        self.save()
+    }
+
+    //reads only the lines of the requested messages
+    pub fn sent(&self, begin: u64, end: u64) -> io::Result<BTreeMap<u64, FixMessage>> {
+        let mut sent = BTreeMap::new();
+        if begin > end || self.offsets.range(begin..=end).next().is_none() {
+            return Ok(sent);
+        }
+        let mut file = BufReader::new(File::open(&self.messages)?);
+        let mut line = Vec::new();
+        for (seq, offset) in self.offsets.range(begin..=end) {
+            file.seek(SeekFrom::Start(*offset))?;
+            line.clear();
+            file.read_until(b'\n', &mut line)?;
+            if let Ok(message) = FixMessage::decode(line.trim_ascii_end()) {
+                sent.insert(*seq, message);
+            }
+        }
+        Ok(sent)
+    }
+
+    //open orders as the changes on disk leave them, a torn last line is left out
+    fn load_orders(&self) -> io::Result<HashMap<(u32, u64), FixOrder>> {
+        let file = match File::open(&self.orders) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e),
+        };
+        let mut orders = HashMap::new();
+        for line in BufReader::new(file).lines() {
+            match serde_json::from_str(&line?) {
+                Ok(OrderChange::Open(order)) => {
+                    orders.insert((order.market_id, order.order_id), order);
+                }
+                Ok(OrderChange::Closed(market_id, order_id)) => {
+                    orders.remove(&(market_id, order_id));
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(orders)
+    }
+
+    //rewrites the file with only the open orders
+    fn compact_orders<'a>(&self, orders: impl Iterator<Item = &'a FixOrder>) -> io::Result<()> {
+        let mut raw = Vec::new();
+        for order in orders {
+            serde_json::to_writer(&mut raw, &OrderChange::Open(order.clone()))?;
+            raw.push(b'\n');
+        }
+        let tmp = self.orders.with_extension("orders.tmp");
+        fs::write(&tmp, raw)?;
+        fs::rename(tmp, &self.orders)
+    }
+
+    fn save_order(&self, change: &OrderChange) -> io::Result<()> {
+        let mut line = serde_json::to_vec(change)?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.orders)?
+            .write_all(&line)
+    }
+}
+
+//where each message of the file starts, by sequence number. a torn line is left out
+fn index(messages: &Path) -> io::Result<BTreeMap<u64, u64>> {
+    let file = match File::open(messages) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut offsets = BTreeMap::new();
+    let mut offset = 0;
+    for line in BufReader::new(file).split(b'\n') {
+        let line = line?;
+        if let Ok(message) = FixMessage::decode(&line) {
+            offsets.insert(message.seq(), offset);
+        }
+        offset += line.len() as u64 + 1;
+    }
+    Ok(offsets)
+}
+
+pub struct FixConfig {
+    pub comp_id: String,
+    pub sessions: HashMap<String, u32>, // counterparty comp id -> user id
+    pub store_dir: PathBuf,
+}
+
+//order events of a counterparty since it logged out, reported when it logs on again
+struct Missed {
+    events: Vec<EventEnvelope>,
+    complete: bool, // false once one got lost, the orders are then reconciled with the engine
+}
+
+impl Missed {
+    fn push(&mut self, envelope: EventEnvelope) {
+        if self.events.len() >= MISSED_LIMIT {
+            self.lose();
+        } else if self.complete {
+            self.events.push(envelope);
+        }
+    }
+
+    fn lose(&mut self) {
+        self.complete = false;
+        self.events = Vec::new();
+    }
+}
+
+struct Acceptor {
+    engine: Arc<EngineRouter>,
+    config: FixConfig,
+    active: Mutex<HashSet<String>>, // counterparties logged on right now
+    missed: Mutex<HashMap<String, Missed>>, // only for those that logged out since the start
+}
+
+impl Acceptor {
+    //keeps the events of counterparties that logged out of this process
+    async fn record_missed(self: Arc<Self>) {
+        let mut events = self.engine.subscribe();
+        loop {
+            match events.recv().await {
+                Ok(envelope) => {
+                    let Some(user_id) = envelope.event.user_id() else {
+                        continue;
+                    };
+                    let active = self.active.lock().unwrap();
+                    let mut missed = self.missed.lock().unwrap();
+                    for (counterparty, id) in &self.config.sessions {
+                        if *id == user_id
+                            && !active.contains(counterparty)
+                            && let Some(missed) = missed.get_mut(counterparty)
+                        {
+                            missed.push(envelope.clone());
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(lost)) => {
+                    warn!("fix acceptor lost {} events of logged out sessions", lost);
+                    self.missed
+                        .lock()
+                        .unwrap()
+                        .values_mut()
+                        .for_each(Missed::lose);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+pub async fn serve(addr: &str, engine: Arc<EngineRouter>, config: FixConfig) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("fix acceptor listening on {}", listener.local_addr()?);
+    let acceptor = Arc::new(Acceptor {
+        engine,
+        config,
+        active: Mutex::new(HashSet::new()),
+        missed: Mutex::new(HashMap::new()),
+    });
+    tokio::spawn(acceptor.clone().record_missed());
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let mut connection = Connection::new(acceptor, stream);
+            if let Err(e) = connection.run().await {
+                info!("fix connection {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+// "<market id>-YES" or "<market id>-NO"
+fn parse_symbol(symbol: &str) -> Option<(u32, OptionType)> {
+    let (market_id, option) = symbol.split_once('-')?;
+    let option = match option {
+        "YES" => OptionType::Yes,
+        "NO" => OptionType::No,
+        _ => return None,
+    };
+    Some((market_id.parse().ok()?, option))
+}
+
+fn symbol(market_id: u32, option: OptionType) -> String {
+    match option {
+        OptionType::Yes => format!("{}-YES", market_id),
+        OptionType::No => format!("{}-NO", market_id),
+    }
+}
+
+fn side_code(side: &OrderType) -> &'static str {
+    match side {
+        OrderType::Buy => "1",
+        OrderType::Sell => "2",
+    }
+}
+
+// SendingTime, utc with milliseconds
+fn sending_time() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let secs = now.as_secs();
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    // days since the epoch to a civil date, proleptic gregorian
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        now.subsec_millis()
+    )
+}
+
+//an order as the counterparty knows it
+#[derive(Clone, Serialize, Deserialize)]
+struct FixOrder {
+    market_id: u32,
+    order_id: u64,
+    cl_ord_id: String,
+    orig_cl_ord_id: Option<String>,
+    pending_cl_ord_id: Option<String>, // of a cancel or replace in flight
+    option: OptionType,
+    side: OrderType,
+    price: f64,
+    leaves: u32,
+    cum: u32,
+    notional: f64,
+}
+
+impl FixOrder {
+    fn status(&self) -> &'static str {
+        match (self.leaves, self.cum) {
+            (0, _) => "2",
+            (_, 0) => "0",
+            _ => "1",
+        }
+    }
+
+    fn report(&self, exec_id: String, exec_type: &str, status: &str) -> FixMessage {
+        let mut report = FixMessage::new("8")
+            .with(37, self.order_id)
+            .with(11, &self.cl_ord_id);
+        if let Some(orig) = &self.orig_cl_ord_id {
+            report = report.with(41, orig);
+        }
+        let avg_px = if self.cum > 0 {
+            self.notional / self.cum as f64
+        } else {
+            0.0
+        };
+        report
+            .with(17, exec_id)
+            .with(150, exec_type)
+            .with(39, status)
+            .with(55, symbol(self.market_id, self.option))
+            .with(54, side_code(&self.side))
+            .with(44, self.price)
+            .with(38, self.leaves + self.cum)
+            .with(151, self.leaves)
+            .with(14, self.cum)
+            .with(6, avg_px)
+    }
+}
+
+//one line of <counterparty>.orders
+#[derive(Serialize, Deserialize)]
+enum OrderChange {
+    Open(FixOrder),
+    Closed(u32, u64),
+}
+
+struct Session {
+    counterparty: String,
+    user_id: u32,
+    store: SessionStore,
+    heartbeat: Duration, // zero when the counterparty asked for no heartbeats
+    resend_pending: bool,
+    orders: HashMap<(u32, u64), FixOrder>,
+    cl_ord_ids: HashMap<String, (u32, u64)>, // of open orders only
+    seen: HashMap<u32, u64>,                 // last event seq handled per market
+    caught_up: bool,                         // told what happened while it was logged out
+}
+
+impl Session {
+    //saves the new state of an order, or that it ended. ended orders are forgotten
+    fn changed(&mut self, key: (u32, u64)) -> io::Result<()> {
+        match self.orders.get(&key) {
+            Some(order) => self.store.save_order(&OrderChange::Open(order.clone())),
+            None => {
+                self.cl_ord_ids.retain(|_, order| *order != key);
+                self.store.save_order(&OrderChange::Closed(key.0, key.1))
+            }
+        }
+    }
+}
+
+struct Connection {
+    acceptor: Arc<Acceptor>,
+    stream: TcpStream,
+    buf: Vec<u8>,
+    session: Option<Session>,
+    last_received: Instant,
+    last_sent: Instant,
+    test_request_sent: bool,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(session) = &self.session {
+            self.acceptor
+                .active
+                .lock()
+                .unwrap()
+                .remove(&session.counterparty);
+        }
+    }
+}
+
+impl Connection {
+    fn new(acceptor: Arc<Acceptor>, stream: TcpStream) -> Self {
+        Connection {
+            acceptor,
+            stream,
+            buf: Vec::new(),
+            session: None,
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+            test_request_sent: false,
+        }
+    }
+
+    async fn run(&mut self) -> Result<(), FixError> {
+        let mut events = self.acceptor.engine.subscribe();
+        let result = self.exchange(&mut events).await;
+        self.log_out(&mut events);
+        result
+    }
+
+    //from here on the order events of the counterparty are kept for its next logon, with the
+    //ones still queued for this connection. a session that never caught up leaves what is
+    //kept as it was
+    fn log_out(&mut self, events: &mut broadcast::Receiver<EventEnvelope>) {
+        let Some(session) = self.session.take() else {
+            return;
+        };
+        let counterparty = session.counterparty;
+        let mut active = self.acceptor.active.lock().unwrap();
+        let mut missed = self.acceptor.missed.lock().unwrap();
+        if session.caught_up {
+            missed.insert(
+                counterparty.clone(),
+                Missed {
+                    events: Vec::new(),
+                    complete: true,
+                },
+            );
+        }
+        active.remove(&counterparty);
+        drop(active);
+        let Some(missed) = missed.get_mut(&counterparty) else {
+            return;
+        };
+        loop {
+            match events.try_recv() {
+                Ok(envelope) if envelope.event.user_id() == Some(session.user_id) => {
+                    missed.push(envelope);
+                }
+                Ok(_) => {}
+                Err(TryRecvError::Lagged(_)) => missed.lose(),
+                Err(_) => return,
+            }
+        }
+    }
+
+    async fn exchange(
+        &mut self,
+        events: &mut broadcast::Receiver<EventEnvelope>,
+    ) -> Result<(), FixError> {
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
+        let mut chunk = [0u8; 4096];
+        loop {
+            tokio::select! {
+                read = self.stream.read(&mut chunk) => {
+                    let n = read?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    self.buf.extend_from_slice(&chunk[..n]);
+                    while let Some(raw) = next_frame(&mut self.buf)? {
+                        if !self.on_frame(&raw).await? {
+                            return Ok(());
+                        }
+                    }
+                }
+                event = events.recv() => match event {
+                    Ok(envelope) => self.on_event(envelope).await?,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("fix session missed {} engine events", missed);
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                _ = tick.tick() => {
+                    if !self.on_tick().await? {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    //false once the connection should be closed
+    async fn on_frame(&mut self, raw: &[u8]) -> Result<bool, FixError> {
+        let message = match FixMessage::decode(raw) {
+            Ok(message) => message,
+            Err(e) => {
+                // garbled messages are ignored, the gap shows up with the next one
+                warn!("dropping fix message: {}", e);
+                return Ok(true);
+            }
+        };
+        self.last_received = Instant::now();
+        self.test_request_sent = false;
+
+        let Some(session) = self.session.as_mut() else {
+            if message.msg_type() != "A" {
+                return Ok(false);
+            }
+            return self.on_logon(&message).await;
+        };
+
+        let seq = message.seq();
+        let expected = session.store.next_in;
+        if message.msg_type() == "4" && !message.flag(123) {
+            // sequence reset in reset mode ignores the sequence number
+            if let Some(new_seq) = message.get(36).and_then(|s| s.parse().ok()) {
+                session.store.next_in = new_seq;
+                session.store.save()?;
+            }
+            return Ok(true);
+        }
+        if seq > expected {
+            if message.msg_type() == "2" {
+                self.on_resend_request(&message).await?;
+            }
+            if !self.session().resend_pending {
+                self.session_mut().resend_pending = true;
+                self.send(FixMessage::new("2").with(7, expected).with(16, 0))
+                    .await?;
+            }
+            return Ok(true);
+        }
+        if seq < expected {
+            if message.flag(43) {
+                return Ok(true);
+            }
+            let text = format!(
+                "MsgSeqNum too low, expecting {} but received {}",
+                expected, seq
+            );
+            self.send(FixMessage::new("5").with(58, text)).await?;
+            return Ok(false);
+        }
+        session.store.next_in += 1;
+        session.resend_pending = false;
+        session.store.save()?;
+
+        match message.msg_type() {
+            "0" | "3" | "A" => {}
+            "1" => {
+                let mut heartbeat = FixMessage::new("0");
+                if let Some(id) = message.get(112) {
+                    heartbeat = heartbeat.with(112, id);
+                }
+                self.send(heartbeat).await?;
+            }
+            "2" => self.on_resend_request(&message).await?,
+            "4" => {
+                let session = self.session_mut();
+                if let Some(new_seq) = message.get(36).and_then(|s| s.parse().ok())
+                    && new_seq > session.store.next_in
+                {
+                    session.store.next_in = new_seq;
+                    session.store.save()?;
+                }
+            }
+            "5" => {
+                self.send(FixMessage::new("5")).await?;
+                return Ok(false);
+            }
+            "D" => self.on_new_order(&message).await?,
+            "F" => self.on_cancel(&message).await?,
+            "G" => self.on_replace(&message).await?,
+            other => {
+                let reject = FixMessage::new("3")
+                    .with(45, seq)
+                    .with(372, other)
+                    .with(373, 11)
+                    .with(58, "unsupported message type");
+                self.send(reject).await?;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn on_logon(&mut self, logon: &FixMessage) -> Result<bool, FixError> {
+        let counterparty = logon.get(49).unwrap_or_default().to_string();
+        let config = &self.acceptor.config;
+        let Some(user_id) = config.sessions.get(&counterparty).copied() else {
+            warn!("fix logon from unknown comp id {:?}", counterparty);
+            return Ok(false);
+        };
+        if logon.get(56) != Some(config.comp_id.as_str()) {
+            warn!("fix logon from {} for another comp id", counterparty);
+            return Ok(false);
+        }
+        if !self
+            .acceptor
+            .active
+            .lock()
+            .unwrap()
+            .insert(counterparty.clone())
+        {
+            warn!("fix session {} is already logged on", counterparty);
+            return Ok(false);
+        }
+
+        let mut store = SessionStore::open(&config.store_dir, &counterparty)?;
+        let reset = logon.flag(141);
+        if reset {
+            store.reset()?;
+        }
+        let heartbeat = logon
+            .get(108)
+            .and_then(|h| h.parse().ok())
+            .unwrap_or(DEFAULT_HEARTBEAT_SECS);
+        let expected = store.next_in;
+        let orders = store.load_orders()?;
+        store.compact_orders(orders.values())?;
+        let mut cl_ord_ids = HashMap::new();
+        for (key, order) in &orders {
+            cl_ord_ids.insert(order.cl_ord_id.clone(), *key);
+            if let Some(orig) = &order.orig_cl_ord_id {
+                cl_ord_ids.insert(orig.clone(), *key);
+            }
+        }
+        self.session = Some(Session {
+            counterparty,
+            user_id,
+            store,
+            heartbeat: Duration::from_secs(heartbeat),
+            resend_pending: false,
+            orders,
+            cl_ord_ids,
+            seen: HashMap::new(),
+            caught_up: false,
+        });
+
+        let seq = logon.seq();
+        if seq < expected {
+            let text = format!(
+                "MsgSeqNum too low, expecting {} but received {}",
+                expected, seq
+            );
+            self.send(FixMessage::new("5").with(58, text)).await?;
+            return Ok(false);
+        }
+        let mut reply = FixMessage::new("A").with(98, 0).with(108, heartbeat);
+        if reset {
+            reply = reply.with(141, "Y");
+        }
+        self.send(reply).await?;
+        if seq > expected {
+            self.session_mut().resend_pending = true;
+            self.send(FixMessage::new("2").with(7, expected).with(16, 0))
+                .await?;
+        } else {
+            let session = self.session_mut();
+            session.store.next_in += 1;
+            session.store.save()?;
+        }
+        self.catch_up().await?;
+        Ok(true)
+    }
+
+    //application messages go out again as possible duplicates, admin messages become a gap fill
+    async fn on_resend_request(&mut self, request: &FixMessage) -> Result<(), FixError> {
+        let begin: u64 = request.get(7).and_then(|s| s.parse().ok()).unwrap_or(1);
+        let last_sent = self.session().store.next_out - 1;
+        let end = match request.get(16).and_then(|s| s.parse().ok()) {
+            Some(0) | None => last_sent,
+            Some(end) => end.min(last_sent),
+        };
+        let sent = self.session().store.sent(begin, end)?;
+
+        let mut gap_start = None;
+        for seq in begin..=end {
+            match sent.get(&seq) {
+                Some(message)
+                    if !matches!(message.msg_type(), "0" | "1" | "2" | "4" | "5" | "A") =>
+                {
+                    if let Some(start) = gap_start.take() {
+                        self.gap_fill(start, seq).await?;
+                    }
+                    let mut resent = FixMessage::new(message.msg_type())
+                        .with(49, &self.acceptor.config.comp_id)
+                        .with(56, &self.session().counterparty)
+                        .with(34, seq)
+                        .with(43, "Y")
+                        .with(52, sending_time())
+                        .with(122, message.get(52).unwrap_or_default());
+                    for (tag, value) in &message.fields {
+                        if !HEADER_TAGS.contains(tag) {
+                            resent = resent.with(*tag, value);
+                        }
+                    }
+                    self.write(&resent.encode()).await?;
+                }
+                _ => {
+                    gap_start.get_or_insert(seq);
+                }
+            }
+        }
+        if let Some(start) = gap_start {
+            self.gap_fill(start, end + 1).await?;
+        }
+        Ok(())
+    }
+
+    async fn gap_fill(&mut self, seq: u64, new_seq: u64) -> Result<(), FixError> {
+        let fill = FixMessage::new("4")
+            .with(49, &self.acceptor.config.comp_id)
+            .with(56, &self.session().counterparty)
+            .with(34, seq)
+            .with(43, "Y")
+            .with(52, sending_time())
+            .with(123, "Y")
+            .with(36, new_seq);
+        self.write(&fill.encode()).await
+    }
+
+    //reports what happened to the orders while the session was logged out: the events kept
+    //since its last logout or, when some got lost or the process restarted since, how the
+    //orders the engine still has open differ from the saved ones. resting orders only ever
+    //fill as makers, at their own price
+    async fn catch_up(&mut self) -> Result<(), FixError> {
+        let counterparty = self.session().counterparty.clone();
+        let missed = self.acceptor.missed.lock().unwrap().remove(&counterparty);
+        self.session_mut().caught_up = true;
+        if let Some(Missed {
+            mut events,
+            complete: true,
+        }) = missed
+        {
+            // the recorder and the connection that logged out can both have kept an event
+            events.sort_by_key(|envelope| (envelope.market_id, envelope.seq));
+            events.dedup_by_key(|envelope| (envelope.market_id, envelope.seq));
+            for envelope in events {
+                self.on_event(envelope).await?;
+            }
+            return Ok(());
+        }
+
+        let engine = self.acceptor.engine.clone();
+        let user_id = self.session().user_id;
+        let mut open = HashMap::new();
+        for (market_id, _) in engine.markets().await {
+            match engine.open_orders(market_id, user_id).await {
+                Ok(orders) => {
+                    open.extend(orders.into_iter().map(|o| ((market_id, o.id), o)));
+                }
+                Err(ServiceError::Stopped) => {
+                    return Err(io::Error::other("matching engine stopped").into());
+                }
+                Err(e) => {
+                    warn!("fix session {} keeps its saved orders: {}", counterparty, e);
+                    return Ok(());
+                }
+            }
+        }
+        let mut gone: Vec<_> = self
+            .session()
+            .orders
+            .keys()
+            .filter(|key| !open.contains_key(key))
+            .copied()
+            .collect();
+        gone.sort();
+        for key in gone {
+            // filled or cancelled, the engine no longer tells which
+            let session = self.session_mut();
+            let mut fix = session.orders.remove(&key).expect("saved order");
+            session.changed(key)?;
+            fix.leaves = 0;
+            let exec_id = format!("C{}", session.store.next_out);
+            let report = fix
+                .report(exec_id, "4", "4")
+                .with(58, "order is no longer open");
+            self.send(report).await?;
+        }
+        let mut open: Vec<_> = open.into_iter().collect();
+        open.sort_by_key(|(key, _)| *key);
+        for (key, order) in open {
+            let session = self.session_mut();
+            let exec_id = format!("C{}", session.store.next_out);
+            let report = match session.orders.get_mut(&key) {
+                // entered elsewhere, known under its engine id
+                None => {
+                    let fix = FixOrder {
+                        market_id: key.0,
+                        order_id: order.id,
+                        cl_ord_id: order.id.to_string(),
+                        orig_cl_ord_id: None,
+                        pending_cl_ord_id: None,
+                        option: order.option,
+                        side: order.order_type.clone(),
+                        price: order.price,
+                        leaves: order.quantity,
+                        cum: 0,
+                        notional: 0.0,
+                    };
+                    let report = fix.report(exec_id, "0", "0");
+                    session.cl_ord_ids.insert(fix.cl_ord_id.clone(), key);
+                    session.orders.insert(key, fix);
+                    report
+                }
+                Some(fix) if order.quantity < fix.leaves => {
+                    let filled = fix.leaves - order.quantity;
+                    fix.cum += filled;
+                    fix.notional += order.price * filled as f64;
+                    fix.price = order.price;
+                    fix.leaves = order.quantity;
+                    fix.pending_cl_ord_id = None;
+                    fix.report(exec_id, "F", fix.status())
+                        .with(31, order.price)
+                        .with(32, filled)
+                }
+                Some(fix) => {
+                    fix.price = order.price;
+                    fix.leaves = order.quantity;
+                    fix.pending_cl_ord_id = None;
+                    session.changed(key)?;
+                    continue;
+                }
+            };
+            session.changed(key)?;
+            self.send(report).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_tick(&mut self) -> Result<bool, FixError> {
+        let Some(session) = &self.session else {
+            return Ok(true);
+        };
+        // HeartBtInt 0 turns heartbeats and test requests off
+        if session.heartbeat.is_zero() {
+            return Ok(true);
+        }
+        let heartbeat = session.heartbeat;
+        if self.last_sent.elapsed() >= heartbeat {
+            self.send(FixMessage::new("0")).await?;
+        }
+        // silent for a heartbeat and a bit: ask once, then give up
+        let silent = self.last_received.elapsed();
+        if silent >= heartbeat * 2 && self.test_request_sent {
+            warn!("fix session {} timed out", self.session().counterparty);
+            return Ok(false);
+        }
+        if silent >= heartbeat + heartbeat / 5 && !self.test_request_sent {
+            self.test_request_sent = true;
+            self.send(FixMessage::new("1").with(112, sending_time()))
+                .await?;
+        }
+        Ok(true)
+    }
+
+    async fn on_new_order(&mut self, request: &FixMessage) -> Result<(), FixError> {
+        let cl_ord_id = request.get(11).unwrap_or_default().to_string();
+        let parsed = (|| {
+            if cl_ord_id.is_empty() {
+                return Err("ClOrdID is required".to_string());
+            }
+            if self.session().cl_ord_ids.contains_key(&cl_ord_id) {
+                return Err(format!("duplicate ClOrdID {}", cl_ord_id));
+            }
+            if request.get(40) != Some("2") {
+                return Err("only limit orders are supported".to_string());
+            }
+            let (market_id, option) = request
+                .get(55)
+                .and_then(parse_symbol)
+                .ok_or("Symbol must look like 1-YES or 1-NO")?;
+            let order_type = match request.get(54) {
+                Some("1") => OrderType::Buy,
+                Some("2") => OrderType::Sell,
+                _ => return Err("Side must be 1 or 2".to_string()),
+            };
+            let price = request
+                .get(44)
+                .and_then(|p| p.parse().ok())
+                .ok_or("Price is required")?;
+            let quantity = request
+                .get(38)
+                .and_then(|q| q.parse().ok())
+                .ok_or("OrderQty is required")?;
+            Ok((market_id, option, order_type, price, quantity))
+        })();
+        let (market_id, option, order_type, price, quantity) = match parsed {
+            Ok(parsed) => parsed,
+            Err(text) => return self.reject_order(request, &cl_ord_id, text).await,
+        };
+
+        let command = Command::PlaceOrder {
+            user_id: self.session().user_id,
+            option,
+            order_type: order_type.clone(),
+            price,
+            quantity,
+        };
+        match self.acceptor.engine.submit(market_id, command).await {
+            // the reports follow from the engine events, which are read after this returns
+            Ok((Some(order), _)) => {
+                let session = self.session_mut();
+                session
+                    .cl_ord_ids
+                    .insert(cl_ord_id.clone(), (market_id, order.id));
+                session.orders.insert(
+                    (market_id, order.id),
+                    FixOrder {
+                        market_id,
+                        order_id: order.id,
+                        cl_ord_id,
+                        orig_cl_ord_id: None,
+                        pending_cl_ord_id: None,
+                        option,
+                        side: order_type,
+                        price,
+                        leaves: quantity,
+                        cum: 0,
+                        notional: 0.0,
+                    },
+                );
+                session.changed((market_id, order.id))?;
+                Ok(())
+            }
+            Ok((None, _)) => Ok(()),
+            Err(ServiceError::Stopped) => Err(io::Error::other("matching engine stopped").into()),
+            Err(e) => self.reject_order(request, &cl_ord_id, e.to_string()).await,
+        }
+    }
+
+    async fn reject_order(
+        &mut self,
+        request: &FixMessage,
+        cl_ord_id: &str,
+        text: String,
+    ) -> Result<(), FixError> {
+        let exec_id = format!("R{}", self.session().store.next_out);
+        let report = FixMessage::new("8")
+            .with(37, "NONE")
+            .with(11, cl_ord_id)
+            .with(17, exec_id)
+            .with(150, "8")
+            .with(39, "8")
+            .with(55, request.get(55).unwrap_or_default())
+            .with(54, request.get(54).unwrap_or_default())
+            .with(38, request.get(38).unwrap_or("0"))
+            .with(151, 0)
+            .with(14, 0)
+            .with(6, 0)
+            .with(58, text);
+        self.send(report).await
+    }
+
+    //the order a cancel or replace refers to, with the new ClOrdID parked on it
+    async fn pending_order(
+        &mut self,
+        request: &FixMessage,
+        response_to: u8,
+    ) -> Result<Option<(u32, u64)>, FixError> {
+        let cl_ord_id = request.get(11).unwrap_or_default().to_string();
+        let orig = request.get(41).unwrap_or_default().to_string();
+        let session = self.session_mut();
+        let key = session.cl_ord_ids.get(&orig).copied();
+        match key.and_then(|key| session.orders.get_mut(&key)) {
+            Some(order) if order.pending_cl_ord_id.is_none() => {
+                order.pending_cl_ord_id = Some(cl_ord_id);
+                Ok(key)
+            }
+            Some(_) => {
+                self.cancel_reject(
+                    request,
+                    response_to,
+                    "a cancel or replace is already pending",
+                )
+                .await?;
+                Ok(None)
+            }
+            None => {
+                self.cancel_reject(request, response_to, "unknown order")
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn on_cancel(&mut self, request: &FixMessage) -> Result<(), FixError> {
+        let Some(key) = self.pending_order(request, 1).await? else {
+            return Ok(());
+        };
+        let command = Command::CancelOrder {
+            user_id: self.session().user_id,
+            order_id: self.session().orders[&key].order_id,
+        };
+        self.submit_pending(request, key, command, 1).await
+    }
+
+    async fn on_replace(&mut self, request: &FixMessage) -> Result<(), FixError> {
+        let Some(key) = self.pending_order(request, 2).await? else {
+            return Ok(());
+        };
+        let order = &self.session().orders[&key];
+        let price = request
+            .get(44)
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(order.price);
+        // OrderQty is the new total, the engine amends what is still open
+        let total: u32 = request
+            .get(38)
+            .and_then(|q| q.parse().ok())
+            .unwrap_or(order.leaves + order.cum);
+        let cum = order.cum;
+        if total <= cum {
+            self.session_mut()
+                .orders
+                .get_mut(&key)
+                .unwrap()
+                .pending_cl_ord_id = None;
+            return self
+                .cancel_reject(request, 2, "OrderQty must exceed the filled quantity")
+                .await;
+        }
+        let command = Command::AmendOrder {
+            user_id: self.session().user_id,
+            order_id: key.1,
+            price,
+            quantity: total - cum,
+        };
+        self.submit_pending(request, key, command, 2).await
+    }
+
+    async fn submit_pending(
+        &mut self,
+        request: &FixMessage,
+        key: (u32, u64),
+        command: Command,
+        response_to: u8,
+    ) -> Result<(), FixError> {
+        match self.acceptor.engine.submit(key.0, command).await {
+            Ok(_) => Ok(()),
+            Err(ServiceError::Stopped) => Err(io::Error::other("matching engine stopped").into()),
+            Err(e) => {
+                if let Some(order) = self.session_mut().orders.get_mut(&key) {
+                    order.pending_cl_ord_id = None;
+                }
+                self.cancel_reject(request, response_to, &e.to_string())
+                    .await
+            }
+        }
+    }
+
+    async fn cancel_reject(
+        &mut self,
+        request: &FixMessage,
+        response_to: u8,
+        text: &str,
+    ) -> Result<(), FixError> {
+        let orig = request.get(41).unwrap_or_default();
+        let session = self.session();
+        let order = session
+            .cl_ord_ids
+            .get(orig)
+            .and_then(|key| session.orders.get(key));
+        let (order_id, status) = match order {
+            Some(order) => (order.order_id.to_string(), order.status()),
+            None => ("NONE".to_string(), "8"),
+        };
+        let reject = FixMessage::new("9")
+            .with(37, order_id)
+            .with(11, request.get(11).unwrap_or_default())
+            .with(41, orig)
+            .with(39, status)
+            .with(434, response_to)
+            .with(58, text);
+        self.send(reject).await
+    }
+
+    //execution reports for everything that happens to the session user's orders
+    async fn on_event(&mut self, envelope: EventEnvelope) -> Result<(), FixError> {
+        let Some(session) = self.session.as_mut() else {
+            return Ok(());
+        };
+        if envelope.event.user_id() != Some(session.user_id) {
+            return Ok(());
+        }
+        let market_id = envelope.market_id;
+        // events kept while it was logged out come first, the queued copies are skipped
+        let seen = session.seen.entry(market_id).or_default();
+        if envelope.seq <= *seen {
+            return Ok(());
+        }
+        *seen = envelope.seq;
+        let exec_id = format!("{}-{}", market_id, envelope.seq);
+        let (order_id, report) = match envelope.event {
+            EngineEvent::OrderAccepted { order } => {
+                // orders entered elsewhere are reported under their engine id
+                let fix = session
+                    .orders
+                    .entry((market_id, order.id))
+                    .or_insert_with(|| FixOrder {
+                        market_id,
+                        order_id: order.id,
+                        cl_ord_id: order.id.to_string(),
+                        orig_cl_ord_id: None,
+                        pending_cl_ord_id: None,
+                        option: order.option,
+                        side: order.order_type.clone(),
+                        price: order.price,
+                        leaves: order.quantity,
+                        cum: 0,
+                        notional: 0.0,
+                    });
+                session
+                    .cl_ord_ids
+                    .entry(fix.cl_ord_id.clone())
+                    .or_insert((market_id, order.id));
+                (order.id, fix.report(exec_id, "0", "0"))
+            }
+            EngineEvent::OrderFilled {
+                order_id,
+                price,
+                filled_quantity,
+                remaining_quantity,
+                ..
+            } => {
+                let Some(fix) = session.orders.get_mut(&(market_id, order_id)) else {
+                    return Ok(());
+                };
+                if remaining_quantity >= fix.leaves {
+                    // already counted when the orders were reconciled at logon
+                    return Ok(());
+                }
+                fix.cum += filled_quantity;
+                fix.notional += price * filled_quantity as f64;
+                fix.leaves = remaining_quantity;
+                let report = fix
+                    .report(exec_id, "F", fix.status())
+                    .with(31, price)
+                    .with(32, filled_quantity);
+                if remaining_quantity == 0 {
+                    session.orders.remove(&(market_id, order_id));
+                }
+                (order_id, report)
+            }
+            EngineEvent::OrderCancelled { order_id, .. } => {
+                let Some(mut fix) = session.orders.remove(&(market_id, order_id)) else {
+                    return Ok(());
+                };
+                if let Some(cl_ord_id) = fix.pending_cl_ord_id.take() {
+                    fix.orig_cl_ord_id = Some(std::mem::replace(&mut fix.cl_ord_id, cl_ord_id));
+                }
+                fix.leaves = 0;
+                (order_id, fix.report(exec_id, "4", "4"))
+            }
+            EngineEvent::OrderAmended { order } => {
+                let Some(fix) = session.orders.get_mut(&(market_id, order.id)) else {
+                    return Ok(());
+                };
+                if let Some(cl_ord_id) = fix.pending_cl_ord_id.take() {
+                    fix.orig_cl_ord_id = Some(std::mem::replace(&mut fix.cl_ord_id, cl_ord_id));
+                    session
+                        .cl_ord_ids
+                        .insert(fix.cl_ord_id.clone(), (market_id, order.id));
+                }
+                fix.price = order.price;
+                fix.leaves = order.quantity;
+                (order.id, fix.report(exec_id, "5", fix.status()))
+            }
+            _ => return Ok(()),
+        };
+        session.changed((market_id, order_id))?;
+        self.send(report).await
+    }
+
+    fn session(&self) -> &Session {
+        self.session.as_ref().expect("logged on")
+    }
+
+    fn session_mut(&mut self) -> &mut Session {
+        self.session.as_mut().expect("logged on")
+    }
+
+    //stamps the header with the next outgoing sequence number and keeps the message for resends
+    async fn send(&mut self, message: FixMessage) -> Result<(), FixError> {
+        let comp_id = self.acceptor.config.comp_id.clone();
+        let session = self.session_mut();
+        let mut fields = message.fields.into_iter();
+        let msg_type = fields.next().map(|(_, v)| v).unwrap_or_default();
+        let mut stamped = FixMessage::new(&msg_type)
+            .with(49, comp_id)
+            .with(56, &session.counterparty)
+            .with(34, session.store.next_out)
+            .with(52, sending_time());
+        stamped.fields.extend(fields);
+        let raw = stamped.encode();
+        session.store.record(&raw)?;
+        self.write(&raw).await
+    }
+
+    async fn write(&mut self, raw: &[u8]) -> Result<(), FixError> {
+        self.stream.write_all(raw).await?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+}
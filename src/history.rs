@@ -0,0 +1,180 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use log::warn;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{OptionType, Trade, events::EngineEvent, events::EventEnvelope};
+
+// per market, the oldest trades go first
+const TAPE_LIMIT: usize = 100_000;
+// per market, outcome and interval
+const CANDLE_LIMIT: usize = 10_000;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+//candle width, written the way it is asked for: 1m, 5m, 1h, 1d
+#[derive(Clone, Debug)]
+pub struct Interval {
+    pub name: String,
+    pub nanos: u64,
+}
+
+impl Interval {
+    //None for an unknown unit, a zero count or a width that overflows
+    pub fn parse(name: &str) -> Option<Self> {
+        let (count, unit) = if let Some(count) = name.strip_suffix('s') {
+            (count, 1)
+        } else if let Some(count) = name.strip_suffix('m') {
+            (count, 60)
+        } else if let Some(count) = name.strip_suffix('h') {
+            (count, 60 * 60)
+        } else if let Some(count) = name.strip_suffix('d') {
+            (count, 24 * 60 * 60)
+        } else {
+            return None;
+        };
+        let count: u64 = count.parse().ok().filter(|c| *c > 0)?;
+        Some(Interval {
+            name: name.to_string(),
+            nanos: count.checked_mul(unit)?.checked_mul(NANOS_PER_SECOND)?,
+        })
+    }
+
+    pub fn defaults() -> Vec<Self> {
+        ["1m", "5m", "1h", "1d"]
+            .iter()
+            .filter_map(|name| Self::parse(name))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Candle {
+    pub start: u64, // nanoseconds since epoch, a multiple of the interval
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: u64, // shares
+    pub trades: u64,
+}
+
+#[derive(Default)]
+struct MarketHistory {
+    tape: VecDeque<Trade>,
+    candles: HashMap<(OptionType, usize), VecDeque<Candle>>, // outcome, interval -> oldest first
+}
+
+//trade tape and ohlcv candles of every market, fed from the engine events and on startup
+//from the trades the journal replays to
+pub struct TradeHistory {
+    intervals: Vec<Interval>,
+    markets: Mutex<HashMap<u32, MarketHistory>>,
+}
+
+impl TradeHistory {
+    pub fn new(intervals: Vec<Interval>) -> Self {
+        TradeHistory {
+            intervals,
+            markets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    //a trade prices both of its legs, each outcome's candles get the price in that outcome
+    pub fn record(&self, trade: &Trade) {
+        let mut markets = self.markets.lock().unwrap();
+        let market = markets.entry(trade.market_id).or_default();
+        market.tape.push_back(trade.clone());
+        if market.tape.len() > TAPE_LIMIT {
+            market.tape.pop_front();
+        }
+        for (option, price) in trade.legs() {
+            for (i, interval) in self.intervals.iter().enumerate() {
+                let series = market.candles.entry((option, i)).or_default();
+                let start = trade.timestamp - trade.timestamp % interval.nanos;
+                match series.back_mut() {
+                    Some(candle) if candle.start == start => {
+                        candle.high = candle.high.max(price);
+                        candle.low = candle.low.min(price);
+                        candle.close = price;
+                        candle.volume += trade.quantity as u64;
+                        candle.trades += 1;
+                    }
+                    _ => {
+                        series.push_back(Candle {
+                            start,
+                            open: price,
+                            high: price,
+                            low: price,
+                            close: price,
+                            volume: trade.quantity as u64,
+                            trades: 1,
+                        });
+                        if series.len() > CANDLE_LIMIT {
+                            series.pop_front();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn spawn(self: Arc<Self>, events: broadcast::Receiver<EventEnvelope>) {
+        tokio::spawn(self.run(events));
+    }
+
+    async fn run(self: Arc<Self>, mut events: broadcast::Receiver<EventEnvelope>) {
+        loop {
+            match events.recv().await {
+                Ok(EventEnvelope {
+                    event: EngineEvent::Trade { trade },
+                    ..
+                }) => self.record(&trade),
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("trade history missed {} events", missed);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    //the latest candles, oldest first. None for an interval that is not kept
+    pub fn candles(
+        &self,
+        market_id: u32,
+        option: OptionType,
+        interval: &str,
+        limit: usize,
+    ) -> Option<Vec<Candle>> {
+        let i = self.intervals.iter().position(|iv| iv.name == interval)?;
+        let markets = self.markets.lock().unwrap();
+        let series = markets
+            .get(&market_id)
+            .and_then(|market| market.candles.get(&(option, i)));
+        Some(series.map_or_else(Vec::new, |series| {
+            let skip = series.len().saturating_sub(limit);
+            series.iter().skip(skip).cloned().collect()
+        }))
+    }
+
+    //newest first, a page starts right after the trade id it is given
+    pub fn trades(&self, market_id: u32, before: Option<u64>, limit: usize) -> Vec<Trade> {
+        let markets = self.markets.lock().unwrap();
+        let Some(market) = markets.get(&market_id) else {
+            return Vec::new();
+        };
+        market
+            .tape
+            .iter()
+            .rev()
+            .filter(|trade| before.is_none_or(|before| trade.id < before))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
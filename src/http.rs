@@ -0,0 +1,590 @@
+use std::{collections::HashSet, io, sync::Arc};
+
+use axum::{
+    Json, Router,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+
+use crate::{
+    OptionType, Order, OrderType, Trade,
+    history::{Candle, TradeHistory},
+    journal::Command,
+    metrics,
+    portfolio::PositionPnl,
+    publisher::Levels,
+    risk::RejectReason,
+    router::EngineRouter,
+    service::{BookDepth, BookQueue, EngineApi, ServiceError},
+    stats::MarketTicker,
+    ws::{self, AuthTokens, MarketDataHub},
+};
+
+type Engine = State<Arc<EngineRouter>>;
+
+#[derive(Deserialize)]
+pub struct PlaceOrderRequest {
+    pub option: OptionType,
+    pub order_type: OrderType,
+    pub price: f64,
+    pub quantity: u32,
+    #[serde(default)]
+    pub client_order_id: Option<String>, // a retry with the same id gets the first answer back
+}
+
+#[derive(Deserialize)]
+pub struct AmendOrderRequest {
+    pub price: f64,
+    pub quantity: u32,
+}
+
+#[derive(Serialize)]
+pub struct OrderResponse {
+    pub order: Option<Order>,
+    pub trades: Vec<Trade>,
+}
+
+#[derive(Serialize)]
+pub struct PriceResponse {
+    pub option: OptionType,
+    pub best_bid: Option<f64>, // own book only
+    pub best_ask: Option<f64>,
+    pub consolidated: ConsolidatedPrice,
+}
+
+//best prices once the other outcome's book is counted in at complement prices
+#[derive(Serialize)]
+pub struct ConsolidatedPrice {
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    pub implied_probability: Option<f64>, // mid price out of the 10 a winning share pays
+}
+
+impl ConsolidatedPrice {
+    pub fn new((bids, asks): &BookDepth) -> Self {
+        let best_bid = bids.keys().next_back().map(|p| *p as f64 / 100.0);
+        let best_ask = asks.keys().next().map(|p| *p as f64 / 100.0);
+        let implied_probability = match (best_bid, best_ask) {
+            (Some(bid), Some(ask)) => Some((bid + ask) / 2.0 / 10.0),
+            _ => None,
+        };
+        ConsolidatedPrice {
+            best_bid,
+            best_ask,
+            implied_probability,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Level {
+    pub price: f64,
+    pub quantity: u32,
+}
+
+//best prices first on both sides
+#[derive(Clone, Debug, Serialize)]
+pub struct BookResponse {
+    pub option: OptionType,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+impl BookResponse {
+    pub fn new(option: OptionType, bids: &Levels, asks: &Levels) -> Self {
+        let level = |(price, quantity): (&u64, &u32)| Level {
+            price: *price as f64 / 100.0,
+            quantity: *quantity,
+        };
+        BookResponse {
+            option,
+            bids: bids.iter().rev().map(level).collect(),
+            asks: asks.iter().map(level).collect(),
+        }
+    }
+}
+
+//both books with the book_seq of the last level 2 delta they include
+#[derive(Serialize)]
+pub struct BookSnapshotResponse {
+    pub market_id: u32,
+    pub book_seq: u64,
+    pub books: Vec<BookResponse>,
+}
+
+#[derive(Serialize)]
+pub struct QueuedOrder {
+    pub id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u32>, // admins only
+    pub quantity: u32,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize)]
+pub struct QueueLevel {
+    pub price: f64,
+    pub quantity: u32,
+    pub orders: Vec<QueuedOrder>, // first in line first
+}
+
+//level 3 book, best prices first on both sides
+#[derive(Serialize)]
+pub struct QueueResponse {
+    pub option: OptionType,
+    pub bids: Vec<QueueLevel>,
+    pub asks: Vec<QueueLevel>,
+}
+
+impl QueueResponse {
+    pub fn new(option: OptionType, (bids, asks): BookQueue, show_users: bool) -> Self {
+        let level = |(price, orders): (u64, Vec<Order>)| QueueLevel {
+            price: price as f64 / 100.0,
+            quantity: orders.iter().map(|o| o.quantity).sum(),
+            orders: orders
+                .into_iter()
+                .map(|o| QueuedOrder {
+                    id: o.id,
+                    user_id: show_users.then_some(o.user_id),
+                    quantity: o.quantity,
+                    timestamp: o.timestamp,
+                })
+                .collect(),
+        };
+        QueueResponse {
+            option,
+            bids: bids.into_iter().rev().map(level).collect(),
+            asks: asks.into_iter().map(level).collect(),
+        }
+    }
+}
+
+//engine errors as http responses with a json body
+pub struct ApiError(ServiceError);
+
+impl From<ServiceError> for ApiError {
+    fn from(e: ServiceError) -> Self {
+        ApiError(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self.0 {
+            ServiceError::Rejected(reason) => match reason {
+                RejectReason::PriceOutOfRange(_) | RejectReason::ZeroQuantity => {
+                    StatusCode::BAD_REQUEST
+                }
+                RejectReason::NotOrderOwner(_) => StatusCode::FORBIDDEN,
+                RejectReason::UnknownOrder(_) => StatusCode::NOT_FOUND,
+                RejectReason::MarketClosed => StatusCode::CONFLICT,
+                RejectReason::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+                RejectReason::JournalWrite(_) => StatusCode::SERVICE_UNAVAILABLE,
+                RejectReason::MaxOrderQuantity { .. }
+                | RejectReason::MaxOrderNotional { .. }
+                | RejectReason::MaxOpenOrders { .. }
+                | RejectReason::MaxNetPosition { .. }
+                | RejectReason::PriceDeviation { .. }
+                | RejectReason::InsufficientBalance { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            },
+            ServiceError::UnknownMarket(_) => StatusCode::NOT_FOUND,
+            ServiceError::Stopped => StatusCode::SERVICE_UNAVAILABLE,
+            ServiceError::MarketExists(_) | ServiceError::UnknownShard(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        let body = serde_json::json!({ "error": self.0.to_string() });
+        let mut response = (status, Json(body)).into_response();
+        if let ServiceError::Rejected(RejectReason::RateLimited { retry_after }) = &self.0 {
+            // whole seconds, rounded up so a client never comes back too early
+            let secs = retry_after.as_millis().div_ceil(1000).max(1);
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from_str(&secs.to_string()).unwrap(),
+            );
+        }
+        response
+    }
+}
+
+//401 with what was wrong with the credentials
+struct Unauthorized(&'static str);
+
+impl IntoResponse for Unauthorized {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "error": self.0 });
+        (StatusCode::UNAUTHORIZED, Json(body)).into_response()
+    }
+}
+
+//user named by the Authorization: Bearer header, none without the header. a token that is
+//not known is an error rather than an anonymous request
+fn token_user(tokens: &AuthTokens, headers: &HeaderMap) -> Result<Option<u32>, Unauthorized> {
+    let Some(value) = headers.get(header::AUTHORIZATION) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| tokens.get(token))
+        .map(|user_id| Some(*user_id))
+        .ok_or(Unauthorized("invalid token"))
+}
+
+//the user whose orders a request is about, it has to come with a valid bearer token
+struct AuthUser(u32);
+
+impl FromRequestParts<Trading> for AuthUser {
+    type Rejection = Unauthorized;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Trading) -> Result<Self, Unauthorized> {
+        token_user(&state.tokens, &parts.headers)?
+            .map(AuthUser)
+            .ok_or(Unauthorized("missing bearer token"))
+    }
+}
+
+//order entry and the orders of the authenticated user
+#[derive(Clone)]
+struct Trading {
+    engine: Arc<EngineRouter>,
+    tokens: Arc<AuthTokens>,
+}
+
+fn trading_routes(trading: Trading) -> Router {
+    Router::new()
+        .route(
+            "/markets/{market_id}/orders",
+            get(open_orders).post(place_order),
+        )
+        .route(
+            "/markets/{market_id}/orders/{order_id}",
+            get(get_order).patch(amend_order).delete(cancel_order),
+        )
+        .route("/portfolio/{user_id}", get(portfolio))
+        .with_state(trading)
+}
+
+pub fn routes(engine: Arc<EngineRouter>) -> Router {
+    Router::new()
+        .route("/markets/{market_id}/price/{option}", get(market_price))
+        .route("/markets/{market_id}/book", get(book_snapshot))
+        .route("/markets/{market_id}/book/{option}", get(order_book))
+        .route(
+            "/markets/{market_id}/book/{option}/consolidated",
+            get(consolidated_book),
+        )
+        .route("/tickers", get(tickers))
+        .route("/metrics", get(prometheus))
+        .with_state(engine)
+}
+
+#[derive(Deserialize)]
+pub struct CandleQuery {
+    pub interval: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct TapeQuery {
+    pub before: Option<u64>, // trade id, the page holds older trades only
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct TapeResponse {
+    pub trades: Vec<Trade>,
+    pub next_before: Option<u64>, // where the next page starts, none on the last one
+}
+
+const PAGE_LIMIT: usize = 100;
+const MAX_PAGE_LIMIT: usize = 1000;
+
+#[derive(Clone)]
+struct HistoryState {
+    engine: Arc<EngineRouter>,
+    history: Arc<TradeHistory>,
+}
+
+impl HistoryState {
+    async fn check_market(&self, market_id: u32) -> Result<(), ApiError> {
+        let markets = self.engine.markets().await;
+        if markets.iter().any(|(id, _)| *id == market_id) {
+            Ok(())
+        } else {
+            Err(ServiceError::UnknownMarket(market_id).into())
+        }
+    }
+}
+
+fn history_routes(state: HistoryState) -> Router {
+    Router::new()
+        .route("/markets/{market_id}/candles/{option}", get(candles))
+        .route("/markets/{market_id}/trades", get(trade_tape))
+        .with_state(state)
+}
+
+//who is asking for the level 3 book, only admins get to see whose orders rest there
+#[derive(Clone)]
+struct Surveillance {
+    engine: Arc<EngineRouter>,
+    tokens: Arc<AuthTokens>,
+    admins: Arc<HashSet<u32>>,
+}
+
+fn surveillance_routes(surveillance: Surveillance) -> Router {
+    Router::new()
+        .route(
+            "/markets/{market_id}/book/{option}/orders",
+            get(order_queue),
+        )
+        .with_state(surveillance)
+}
+
+//rest api plus the websocket feeds under /ws, admins are users allowed to see the owners of
+//resting orders. history keeps following the engine from here on
+pub async fn serve(
+    addr: &str,
+    engine: Arc<EngineRouter>,
+    tokens: AuthTokens,
+    admins: HashSet<u32>,
+    history: Arc<TradeHistory>,
+) -> io::Result<()> {
+    history.clone().spawn(engine.subscribe());
+    let hub = MarketDataHub::spawn(engine.clone());
+    let trading = Trading {
+        engine: engine.clone(),
+        tokens: Arc::new(tokens.clone()),
+    };
+    let surveillance = Surveillance {
+        engine: engine.clone(),
+        tokens: trading.tokens.clone(),
+        admins: Arc::new(admins),
+    };
+    let history = HistoryState {
+        engine: engine.clone(),
+        history,
+    };
+    let app = routes(engine)
+        .merge(trading_routes(trading))
+        .merge(surveillance_routes(surveillance))
+        .merge(history_routes(history))
+        .merge(ws::routes(hub, tokens));
+    let listener = TcpListener::bind(addr).await?;
+    info!("http api listening on {}", listener.local_addr()?);
+    axum::serve(listener, app).await
+}
+
+async fn place_order(
+    State(Trading { engine, .. }): State<Trading>,
+    AuthUser(user_id): AuthUser,
+    Path(market_id): Path<u32>,
+    Json(request): Json<PlaceOrderRequest>,
+) -> Result<(StatusCode, Json<OrderResponse>), ApiError> {
+    let command = Command::PlaceOrder {
+        user_id,
+        option: request.option,
+        order_type: request.order_type,
+        price: request.price,
+        quantity: request.quantity,
+    };
+    let (order, trades) = match request.client_order_id {
+        Some(client_order_id) => {
+            engine
+                .submit_once(market_id, client_order_id, command)
+                .await?
+        }
+        None => engine.submit(market_id, command).await?,
+    };
+    Ok((StatusCode::CREATED, Json(OrderResponse { order, trades })))
+}
+
+async fn amend_order(
+    State(Trading { engine, .. }): State<Trading>,
+    AuthUser(user_id): AuthUser,
+    Path((market_id, order_id)): Path<(u32, u64)>,
+    Json(request): Json<AmendOrderRequest>,
+) -> Result<Json<OrderResponse>, ApiError> {
+    let command = Command::AmendOrder {
+        user_id,
+        order_id,
+        price: request.price,
+        quantity: request.quantity,
+    };
+    let (order, trades) = engine.submit(market_id, command).await?;
+    Ok(Json(OrderResponse { order, trades }))
+}
+
+//the order as it rested when it was cancelled
+async fn cancel_order(
+    State(Trading { engine, .. }): State<Trading>,
+    AuthUser(user_id): AuthUser,
+    Path((market_id, order_id)): Path<(u32, u64)>,
+) -> Result<Json<Order>, ApiError> {
+    let command = Command::CancelOrder { user_id, order_id };
+    let (order, _) = engine.submit(market_id, command).await?;
+    Ok(Json(
+        order.expect("a cancel hands back the cancelled order"),
+    ))
+}
+
+async fn get_order(
+    State(Trading { engine, .. }): State<Trading>,
+    AuthUser(user_id): AuthUser,
+    Path((market_id, order_id)): Path<(u32, u64)>,
+) -> Result<Json<Order>, ApiError> {
+    Ok(Json(engine.order(market_id, user_id, order_id).await?))
+}
+
+async fn open_orders(
+    State(Trading { engine, .. }): State<Trading>,
+    AuthUser(user_id): AuthUser,
+    Path(market_id): Path<u32>,
+) -> Result<Json<Vec<Order>>, ApiError> {
+    Ok(Json(engine.open_orders(market_id, user_id).await?))
+}
+
+//positions of the user in every market with their pnl, nobody else gets to see them
+async fn portfolio(
+    State(Trading { engine, .. }): State<Trading>,
+    AuthUser(caller): AuthUser,
+    Path(user_id): Path<u32>,
+) -> Result<Json<Vec<PositionPnl>>, Response> {
+    if caller != user_id {
+        let body = serde_json::json!({ "error": "portfolio of another user" });
+        return Err((StatusCode::FORBIDDEN, Json(body)).into_response());
+    }
+    let mut positions = Vec::new();
+    for (market_id, _) in engine.markets().await {
+        match engine.portfolio(market_id, user_id).await {
+            Ok(market) => positions.extend(market),
+            // moved or gone since the market list was taken
+            Err(ServiceError::UnknownMarket(_)) => {}
+            Err(e) => return Err(ApiError(e).into_response()),
+        }
+    }
+    Ok(Json(positions))
+}
+
+async fn market_price(
+    State(engine): Engine,
+    Path((market_id, option)): Path<(u32, OptionType)>,
+) -> Result<Json<PriceResponse>, ApiError> {
+    let (best_bid, best_ask) = engine.market_price(market_id, option).await?;
+    let consolidated = engine.consolidated_book(market_id, option).await?;
+    Ok(Json(PriceResponse {
+        option,
+        best_bid,
+        best_ask,
+        consolidated: ConsolidatedPrice::new(&consolidated),
+    }))
+}
+
+async fn consolidated_book(
+    State(engine): Engine,
+    Path((market_id, option)): Path<(u32, OptionType)>,
+) -> Result<Json<BookResponse>, ApiError> {
+    let (bids, asks) = engine.consolidated_book(market_id, option).await?;
+    Ok(Json(BookResponse::new(option, &bids, &asks)))
+}
+
+async fn order_book(
+    State(engine): Engine,
+    Path((market_id, option)): Path<(u32, OptionType)>,
+) -> Result<Json<BookResponse>, ApiError> {
+    let (bids, asks) = engine.order_book(market_id, option).await?;
+    Ok(Json(BookResponse::new(option, &bids, &asks)))
+}
+
+async fn book_snapshot(
+    State(engine): Engine,
+    Path(market_id): Path<u32>,
+) -> Result<Json<BookSnapshotResponse>, ApiError> {
+    let snapshot = engine.book_snapshot(market_id).await?;
+    let (yes_bids, yes_asks) = &snapshot.yes;
+    let (no_bids, no_asks) = &snapshot.no;
+    Ok(Json(BookSnapshotResponse {
+        market_id,
+        book_seq: snapshot.book_seq,
+        books: vec![
+            BookResponse::new(OptionType::Yes, yes_bids, yes_asks),
+            BookResponse::new(OptionType::No, no_bids, no_asks),
+        ],
+    }))
+}
+
+async fn candles(
+    State(state): State<HistoryState>,
+    Path((market_id, option)): Path<(u32, OptionType)>,
+    Query(query): Query<CandleQuery>,
+) -> Result<Json<Vec<Candle>>, Response> {
+    state
+        .check_market(market_id)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let limit = query.limit.unwrap_or(PAGE_LIMIT).min(MAX_PAGE_LIMIT);
+    match state
+        .history
+        .candles(market_id, option, &query.interval, limit)
+    {
+        Some(candles) => Ok(Json(candles)),
+        None => {
+            let message = format!("candles are not kept for interval {}", query.interval);
+            let body = serde_json::json!({ "error": message });
+            Err((StatusCode::BAD_REQUEST, Json(body)).into_response())
+        }
+    }
+}
+
+async fn trade_tape(
+    State(state): State<HistoryState>,
+    Path(market_id): Path<u32>,
+    Query(query): Query<TapeQuery>,
+) -> Result<Json<TapeResponse>, ApiError> {
+    state.check_market(market_id).await?;
+    let limit = query.limit.unwrap_or(PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+    let trades = state.history.trades(market_id, query.before, limit);
+    // a full page may have more behind it
+    let next_before = (trades.len() == limit)
+        .then(|| trades.last().map(|trade| trade.id))
+        .flatten();
+    Ok(Json(TapeResponse {
+        trades,
+        next_before,
+    }))
+}
+
+//anyone may see the queue, user ids need the bearer token of an admin
+async fn order_queue(
+    State(surveillance): State<Surveillance>,
+    Path((market_id, option)): Path<(u32, OptionType)>,
+    headers: HeaderMap,
+) -> Response {
+    let user_id = match token_user(&surveillance.tokens, &headers) {
+        Ok(user_id) => user_id,
+        Err(e) => return e.into_response(),
+    };
+    let show_users = user_id.is_some_and(|id| surveillance.admins.contains(&id));
+    match surveillance.engine.order_queue(market_id, option).await {
+        Ok(queue) => Json(QueueResponse::new(option, queue, show_users)).into_response(),
+        Err(e) => ApiError(e).into_response(),
+    }
+}
+
+//every market with its 24h statistics in one go
+async fn tickers(State(engine): Engine) -> Json<Vec<MarketTicker>> {
+    Json(engine.tickers().await)
+}
+
+async fn prometheus(State(engine): Engine) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(&engine).await,
+    )
+}
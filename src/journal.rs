@@ -0,0 +1,145 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{OptionType, OrderType, risk::RiskLimits};
+
+//every state changing request the engine accepts, in the form it is journaled and replayed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Command {
+    PlaceOrder {
+        user_id: u32,
+        option: OptionType,
+        order_type: OrderType,
+        price: f64,
+        quantity: u32,
+    },
+    // the book side and price come from the resting order, journals that still carry them
+    // read the same, the extra fields are ignored
+    CancelOrder {
+        user_id: u32,
+        order_id: u64,
+    },
+    AmendOrder {
+        user_id: u32,
+        order_id: u64,
+        price: f64,
+        quantity: u32,
+    },
+    Admin(AdminCommand),
+}
+
+impl Command {
+    //user the command is sent on behalf of, admin commands have none
+    pub fn user_id(&self) -> Option<u32> {
+        match self {
+            Command::PlaceOrder { user_id, .. }
+            | Command::CancelOrder { user_id, .. }
+            | Command::AmendOrder { user_id, .. } => Some(*user_id),
+            Command::Admin(_) => None,
+        }
+    }
+
+    //name used as a label in metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Command::PlaceOrder { .. } => "place_order",
+            Command::CancelOrder { .. } => "cancel_order",
+            Command::AmendOrder { .. } => "amend_order",
+            Command::Admin(_) => "admin",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum AdminCommand {
+    ResolveMarket { outcome: OptionType },
+    SetRiskLimits(RiskLimits),
+}
+
+//one journal line, the timestamp is the engine clock the command was applied with
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub command: Command,
+    // idempotency key the client sent it with, a retry carrying it again is not applied twice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_order_id: Option<String>,
+}
+
+//append only log of accepted commands, one json entry per line
+pub struct Journal {
+    file: File,
+    next_seq: u64,
+}
+
+impl Journal {
+    //opens or creates the journal, new entries continue after the last sequence number on disk
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let (next_seq, valid_len) = match Self::read(&path) {
+            Ok((entries, valid_len)) => {
+                (entries.last().map_or(1, |entry| entry.seq + 1), valid_len)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (1, 0),
+            Err(e) => return Err(e),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        // drop a torn last line so new entries start on a clean line
+        file.set_len(valid_len)?;
+        Ok(Journal { file, next_seq })
+    }
+
+    //written and synced before the command is applied to the engine
+    pub fn append(
+        &mut self,
+        timestamp: u64,
+        command: &Command,
+        client_order_id: Option<&str>,
+    ) -> io::Result<u64> {
+        let entry = JournalEntry {
+            seq: self.next_seq,
+            timestamp,
+            command: command.clone(),
+            client_order_id: client_order_id.map(String::from),
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        self.next_seq += 1;
+        Ok(entry.seq)
+    }
+
+    pub fn read_entries(path: impl AsRef<Path>) -> io::Result<Vec<JournalEntry>> {
+        Self::read(path).map(|(entries, _)| entries)
+    }
+
+    //entries plus the byte length of the well formed part of the file
+    fn read(path: impl AsRef<Path>) -> io::Result<(Vec<JournalEntry>, u64)> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut entries = Vec::new();
+        let mut valid_len = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            if !line.ends_with('\n') {
+                // crashed while writing this entry, it was never applied
+                break;
+            }
+            if !line.trim().is_empty() {
+                entries.push(serde_json::from_str(&line)?);
+            }
+            valid_len += read as u64;
+        }
+        Ok((entries, valid_len))
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use crate::{Order, OrderType, Trade, portfolio::SETTLEMENT_PAYOUT, risk::RejectReason};
+
+//most one share of an order can lose: the price for a buy, payout minus price for a sale.
+//sales are treated as shorts even when the user holds the shares, which is the safe side
+pub fn collateral(order_type: &OrderType, price: f64, quantity: u32) -> f64 {
+    let per_share = match order_type {
+        OrderType::Buy => price,
+        OrderType::Sell => SETTLEMENT_PAYOUT - price,
+    };
+    per_share * quantity as f64
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Account {
+    balance: f64,
+    held: f64, // open orders plus filled positions of unresolved markets
+}
+
+//what an open order holds, the unfilled part can be released again
+#[derive(Clone, Debug)]
+pub struct Hold {
+    user_id: u32,
+    order_type: OrderType,
+    price: f64,
+    remaining: u32,
+}
+
+#[derive(Default)]
+struct LedgerState {
+    accounts: HashMap<u32, Account>,
+    orders: HashMap<(u32, u64), Hold>,  // by market and order id
+    exposure: HashMap<(u32, u32), f64>, // filled holds by market and user, kept until settlement
+}
+
+//cash balances shared by every market, each order holds its worst case loss up front so the
+//same money can never back orders in two markets at once. engines call it from their own
+//task, the lock is only ever held for a few map updates
+#[derive(Default)]
+pub struct AccountLedger {
+    state: Mutex<LedgerState>,
+}
+
+impl AccountLedger {
+    pub fn new() -> Self {
+        AccountLedger::default()
+    }
+
+    pub fn deposit(&self, user_id: u32, amount: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.accounts.entry(user_id).or_default().balance += amount;
+    }
+
+    //total balance and what is left of it for new orders
+    pub fn balance(&self, user_id: u32) -> (f64, f64) {
+        let state = self.state.lock().unwrap();
+        let account = state.accounts.get(&user_id).copied().unwrap_or_default();
+        (account.balance, account.balance - account.held)
+    }
+
+    pub fn reserve(&self, user_id: u32, amount: f64) -> Result<(), RejectReason> {
+        let mut state = self.state.lock().unwrap();
+        let account = state.accounts.entry(user_id).or_default();
+        let available = account.balance - account.held;
+        if amount > available + 1e-9 {
+            return Err(RejectReason::InsufficientBalance {
+                required: amount,
+                available,
+            });
+        }
+        account.held += amount;
+        Ok(())
+    }
+
+    pub fn release(&self, user_id: u32, amount: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.accounts.entry(user_id).or_default().held -= amount;
+    }
+
+    //start tracking an order whose full quantity has been reserved
+    pub fn open_order(&self, market_id: u32, order: &Order) {
+        let mut state = self.state.lock().unwrap();
+        state.orders.insert(
+            (market_id, order.id),
+            Hold {
+                user_id: order.user_id,
+                order_type: order.order_type.clone(),
+                price: order.price,
+                remaining: order.quantity,
+            },
+        );
+    }
+
+    //hold an order that was already resting before the ledger came in, even if it does not fit
+    pub fn adopt_order(&self, market_id: u32, order: &Order) {
+        self.state
+            .lock()
+            .unwrap()
+            .accounts
+            .entry(order.user_id)
+            .or_default()
+            .held += collateral(&order.order_type, order.price, order.quantity);
+        self.open_order(market_id, order);
+    }
+
+    //re-price the hold of an amended order, only an increase has to fit the balance.
+    //returns the previous hold so a failed amend can put it back
+    pub fn amend_order(
+        &self,
+        market_id: u32,
+        order_id: u64,
+        price: f64,
+        quantity: u32,
+    ) -> Result<Option<Hold>, RejectReason> {
+        let mut state = self.state.lock().unwrap();
+        let Some(previous) = state.orders.get(&(market_id, order_id)).cloned() else {
+            return Ok(None);
+        };
+        let delta = collateral(&previous.order_type, price, quantity)
+            - collateral(&previous.order_type, previous.price, previous.remaining);
+        let account = state.accounts.entry(previous.user_id).or_default();
+        let available = account.balance - account.held;
+        if delta > available + 1e-9 {
+            return Err(RejectReason::InsufficientBalance {
+                required: delta,
+                available,
+            });
+        }
+        account.held += delta;
+        state.orders.insert(
+            (market_id, order_id),
+            Hold {
+                price,
+                remaining: quantity,
+                ..previous.clone()
+            },
+        );
+        Ok(Some(previous))
+    }
+
+    //a replayed amend was checked when it was accepted, its hold is re-priced as it is
+    pub fn reprice_order(&self, market_id: u32, order_id: u64, price: f64, quantity: u32) {
+        let mut state = self.state.lock().unwrap();
+        let Some(hold) = state.orders.get_mut(&(market_id, order_id)) else {
+            return;
+        };
+        let delta = collateral(&hold.order_type, price, quantity)
+            - collateral(&hold.order_type, hold.price, hold.remaining);
+        hold.price = price;
+        hold.remaining = quantity;
+        let user_id = hold.user_id;
+        state.accounts.entry(user_id).or_default().held += delta;
+    }
+
+    pub fn restore_order(&self, market_id: u32, order_id: u64, previous: Hold) {
+        let mut state = self.state.lock().unwrap();
+        let current = state.orders.insert((market_id, order_id), previous.clone());
+        if let Some(current) = current {
+            let delta = collateral(&current.order_type, current.price, current.remaining)
+                - collateral(&previous.order_type, previous.price, previous.remaining);
+            state.accounts.entry(previous.user_id).or_default().held -= delta;
+        }
+    }
+
+    //filled quantity stays held as exposure of the market until it settles
+    pub fn fill(&self, market_id: u32, trade: &Trade) {
+        let mut state = self.state.lock().unwrap();
+        for order_id in [trade.buy_order_id, trade.sell_order_id] {
+            let key = (market_id, order_id);
+            let Some(hold) = state.orders.get_mut(&key) else {
+                continue;
+            };
+            let filled = trade.quantity.min(hold.remaining);
+            hold.remaining -= filled;
+            let (user_id, amount) = (
+                hold.user_id,
+                collateral(&hold.order_type, hold.price, filled),
+            );
+            if hold.remaining == 0 {
+                state.orders.remove(&key);
+            }
+            *state.exposure.entry((market_id, user_id)).or_default() += amount;
+        }
+    }
+
+    //what the fills of a market hold per user, goes into the snapshots of the market
+    pub fn exposure(&self, market_id: u32) -> BTreeMap<u32, f64> {
+        let state = self.state.lock().unwrap();
+        state
+            .exposure
+            .iter()
+            .filter(|((market, _), _)| *market == market_id)
+            .map(|((_, user_id), amount)| (*user_id, *amount))
+            .collect()
+    }
+
+    //holds the fills of a market recovered from a snapshot again
+    pub fn restore_exposure(&self, market_id: u32, exposure: &BTreeMap<u32, f64>) {
+        let mut state = self.state.lock().unwrap();
+        for (user_id, amount) in exposure {
+            state.accounts.entry(*user_id).or_default().held += amount;
+            *state.exposure.entry((market_id, *user_id)).or_default() += amount;
+        }
+    }
+
+    //cancelled or dropped, the unfilled part is free again
+    pub fn close_order(&self, market_id: u32, order_id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(hold) = state.orders.remove(&(market_id, order_id)) {
+            let amount = collateral(&hold.order_type, hold.price, hold.remaining);
+            state.accounts.entry(hold.user_id).or_default().held -= amount;
+        }
+    }
+
+    //the market resolved, release its exposure and book what every user made or lost in it
+    pub fn settle_market(&self, market_id: u32, pnl: impl IntoIterator<Item = (u32, f64)>) {
+        let mut state = self.state.lock().unwrap();
+        let LedgerState {
+            accounts, exposure, ..
+        } = &mut *state;
+        exposure.retain(|(market, user_id), amount| {
+            if *market != market_id {
+                return true;
+            }
+            accounts.entry(*user_id).or_default().held -= *amount;
+            false
+        });
+        for (user_id, pnl) in pnl {
+            accounts.entry(user_id).or_default().balance += pnl;
+        }
+    }
+}
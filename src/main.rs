@@ -1,21 +1,70 @@
 use std::{
-    collections::{BTreeMap, VecDeque},
-    time::{SystemTime, UNIX_EPOCH},
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    env, io,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+use log::{error, trace};
+use serde::{Deserialize, Serialize};
+
+mod audit;
+mod event_stream;
+mod events;
+mod fix;
+mod history;
+mod http;
+mod journal;
+mod ledger;
+mod metrics;
+mod portfolio;
+mod publisher;
+mod rate_limit;
+mod redis_worker;
+mod repl;
+mod risk;
+mod router;
+mod scenario;
+mod service;
+mod snapshot;
+mod stats;
+mod wire;
+mod wire_server;
+mod ws;
+
+use audit::AuditLog;
+use event_stream::RetentionPolicy;
+use events::{EngineEvent, EventEnvelope};
+use history::{Interval, TradeHistory};
+use journal::{AdminCommand, Command, Journal};
+use ledger::AccountLedger;
+use metrics::MarketGauges;
+use portfolio::{Portfolio, PositionPnl};
+use rate_limit::{
+    AccountClass, BucketLimit, ClassLimits, RateLimitConfig, RateLimiter, RequestKind,
+};
+use redis_worker::RedisWorker;
+use risk::{RejectReason, RiskContext, RiskLimits};
+use router::EngineRouter;
+use service::{BookDepth, BookQueue, BookSnapshot, EngineApi, ServiceError};
+use snapshot::SnapshotPolicy;
+use stats::{MarketTicker, RollingStats};
+use ws::AuthTokens;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 enum OptionType {
     Yes,
     No,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 enum OrderType {
     Buy,
     Sell,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Order {
     id: u64,
     user_id: u32,
@@ -26,19 +75,104 @@ struct Order {
     timestamp: u64,
 }
 
-#[derive(Clone, Debug)]
+//client order ids are only unique per user
+fn client_order_key(user_id: Option<u32>, client_order_id: &str) -> String {
+    format!("{}:{}", user_id.unwrap_or_default(), client_order_id)
+}
+
+//how the two orders of a trade were paired
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+enum MatchType {
+    Direct,        // same outcome book, buy against sell
+    Complementary, // opposite outcome book, buy against sell
+    Mint,          // yes buy against no buy, a new share pair is created
+}
+
+#[derive(Clone, Debug, Serialize)]
 struct Trade {
+    id: u64,
+    timestamp: u64, // execution time, nanoseconds since epoch
+    market_id: u32,
     buy_order_id: u64,
     sell_order_id: u64,
-    option: OptionType,
-    price: f64,
+    maker_user_id: u32,
+    taker_user_id: u32,
+    aggressor_side: OrderType,
+    match_type: MatchType,
+    option: OptionType, // outcome of the taker leg
+    price: f64,         // taker leg price in `option`
+    maker_option: OptionType,
+    maker_price: f64, // maker leg price in `maker_option`
     quantity: u32,
 }
 
+impl Trade {
+    //fill of the incoming order against a resting one, id, time and market are stamped by the engine
+    //
+    //pricing rule: the maker always trades at its own resting price in its own outcome.
+    //for counter book matches the taker leg is the complement of the maker price (10 - maker price),
+    //bounded by the taker's own limit, so any price improvement goes to the taker.
+    //eg resting No buy 2.7 against incoming Yes buy 7.4 -> No leg 2.7, Yes leg 7.3
+    fn new(order: &Order, maker: &Order, quantity: u32) -> Self {
+        let (buy_order_id, sell_order_id) = match order.order_type {
+            OrderType::Buy => (order.id, maker.id),
+            OrderType::Sell => (maker.id, order.id),
+        };
+        // a sell only ever meets bids of the counter book, so two sells are never paired
+        let match_type = match (
+            order.option == maker.option,
+            &order.order_type,
+            &maker.order_type,
+        ) {
+            (true, _, _) => MatchType::Direct,
+            (false, OrderType::Buy, OrderType::Buy) => MatchType::Mint,
+            (false, _, _) => MatchType::Complementary,
+        };
+        let price = if match_type == MatchType::Direct {
+            maker.price
+        } else {
+            let complement = OrderBook::complement_price(maker.price);
+            match order.order_type {
+                OrderType::Buy => complement.min(order.price),
+                OrderType::Sell => complement.max(order.price),
+            }
+        };
+        Trade {
+            id: 0,
+            timestamp: 0,
+            market_id: 0,
+            buy_order_id,
+            sell_order_id,
+            maker_user_id: maker.user_id,
+            taker_user_id: order.user_id,
+            aggressor_side: order.order_type.clone(),
+            match_type,
+            option: order.option,
+            price,
+            maker_option: maker.option,
+            maker_price: maker.price,
+            quantity,
+        }
+    }
+
+    //outcome and price of each leg, the maker leg only when it is in the other outcome
+    pub fn legs(&self) -> impl Iterator<Item = (OptionType, f64)> {
+        let counter_leg =
+            (self.maker_option != self.option).then_some((self.maker_option, self.maker_price));
+        std::iter::once((self.option, self.price)).chain(counter_leg)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct OrderBook {
     option: OptionType,
     bids: BTreeMap<u64, VecDeque<Order>>,
     asks: BTreeMap<u64, VecDeque<Order>>,
+    // levels touched by the command being applied, published as level 2 deltas once it is done
+    #[serde(skip)]
+    changed_bids: BTreeSet<u64>,
+    #[serde(skip)]
+    changed_asks: BTreeSet<u64>,
 }
 
 impl OrderBook {
@@ -47,33 +181,81 @@ impl OrderBook {
             option,
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
+            changed_bids: BTreeSet::new(),
+            changed_asks: BTreeSet::new(),
         }
     }
 
+    //drops every resting order, each level goes out as emptied
+    fn clear(&mut self) {
+        self.changed_bids
+            .extend(std::mem::take(&mut self.bids).into_keys());
+        self.changed_asks
+            .extend(std::mem::take(&mut self.asks).into_keys());
+    }
+
+    //side, price in cents and aggregate quantity now of every touched level, 0 once it is gone
+    fn take_changes(&mut self) -> Vec<(OrderType, u64, u32)> {
+        let level = |side: &BTreeMap<u64, VecDeque<Order>>, price: &u64| {
+            side.get(price)
+                .map_or(0, |queue| queue.iter().map(|o| o.quantity).sum())
+        };
+        let bids = std::mem::take(&mut self.changed_bids)
+            .into_iter()
+            .map(|price| (OrderType::Buy, price, level(&self.bids, &price)));
+        let asks = std::mem::take(&mut self.changed_asks)
+            .into_iter()
+            .map(|price| (OrderType::Sell, price, level(&self.asks, &price)));
+        bids.chain(asks).collect()
+    }
+
     fn price_to_cents(price: f64) -> u64 {
         (price * 100.0).round() as u64
     }
 
+    //price of the other outcome, a yes at 7.3 is a no at 2.7
+    fn complement_price(price: f64) -> f64 {
+        (1000 - Self::price_to_cents(price)) as f64 / 100.0
+    }
+
     fn add_order(&mut self, order: Order) {
-        println!("remove order called");
+        trace!("order {} rests at {:.2}", order.id, order.price);
         let price_cents = Self::price_to_cents(order.price);
-        let orders = match order.order_type {
-            OrderType::Buy => &mut self.bids,
-            OrderType::Sell => &mut self.asks,
+        let (orders, changed) = match order.order_type {
+            OrderType::Buy => (&mut self.bids, &mut self.changed_bids),
+            OrderType::Sell => (&mut self.asks, &mut self.changed_asks),
         };
+        changed.insert(price_cents);
         orders
             .entry(price_cents)
             .or_insert_with(VecDeque::new)
             .push_back(order);
     }
 
+    //shrink a resting order in place so it keeps its place in the queue
+    fn reduce_order(&mut self, order_type: &OrderType, price: f64, order_id: u64, quantity: u32) {
+        let price_cents = Self::price_to_cents(price);
+        let (orders, changed) = match order_type {
+            OrderType::Buy => (&mut self.bids, &mut self.changed_bids),
+            OrderType::Sell => (&mut self.asks, &mut self.changed_asks),
+        };
+        changed.insert(price_cents);
+        if let Some(order) = orders
+            .get_mut(&price_cents)
+            .and_then(|queue| queue.iter_mut().find(|o| o.id == order_id))
+        {
+            order.quantity = quantity;
+        }
+    }
+
     fn remove_order(&mut self, order_type: OrderType, price: f64, order_id: u64) {
         let price_cents = Self::price_to_cents(price);
-        let orders = match order_type {
-            OrderType::Buy => &mut self.bids,
-            OrderType::Sell => &mut self.asks,
+        let (orders, changed) = match order_type {
+            OrderType::Buy => (&mut self.bids, &mut self.changed_bids),
+            OrderType::Sell => (&mut self.asks, &mut self.changed_asks),
         };
         if let Some(queue) = orders.get_mut(&price_cents) {
+            changed.insert(price_cents);
             queue.retain(|o| o.id != order_id);
             if queue.is_empty() {
                 orders.remove(&price_cents);
@@ -82,20 +264,86 @@ impl OrderBook {
     }
 }
 
-//structs for matching engine
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+enum MarketStatus {
+    Open,
+    Resolved(OptionType),
+}
+
+//engine clock, every command is stamped once and the stamp is journaled with it
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+//structs for matching engine, serializing it gives a snapshot of the complete state
+#[derive(Serialize, Deserialize)]
 struct MatchingEngine {
+    market_id: u32,
+    status: MarketStatus,
     yes_book: OrderBook,
     no_book: OrderBook,
+    open_orders: BTreeMap<u64, Order>, // resting orders by id, quantity kept in sync with the books
+    portfolio: Portfolio,
+    risk_limits: RiskLimits,
+    #[serde(skip)]
+    rate_limiter: Option<Arc<RateLimiter>>, // shared with the other markets, none means unthrottled
+    last_trade_price: BTreeMap<OptionType, f64>,
+    #[serde(default)]
+    stats: RollingStats,
     next_order_id: u64,
+    next_trade_id: u64,
+    journal_seq: u64, // last journal entry applied to this state
+    event_seq: u64,
+    #[serde(default)]
+    book_seq: u64, // last level 2 delta, a snapshot of the books is as of this one
+    #[serde(default)]
+    client_orders: BTreeMap<String, Option<Order>>, // user:client order id -> what it was answered
+    #[serde(skip)]
+    client_order_id: Option<String>, // of the command being submitted, journaled with it
+    #[serde(skip)]
+    events: Vec<EventEnvelope>, // drained by whoever drives the engine
+    #[serde(skip)]
+    journal: Option<Journal>,
+    #[serde(skip)]
+    snapshots: Option<SnapshotPolicy>,
+    #[serde(skip)]
+    accounts: Option<Arc<AccountLedger>>, // balances shared with the other markets
+    #[serde(default)]
+    exposure: BTreeMap<u32, f64>, // what the fills held on the ledger when the snapshot was taken
+    #[serde(skip)]
+    audit: Option<Arc<AuditLog>>,
     commision_rate: f64, //eg 0.0223 -> 2.23 percentage
 }
 
 impl MatchingEngine {
-    fn new() -> Self {
+    fn new(market_id: u32) -> Self {
         MatchingEngine {
+            market_id,
+            status: MarketStatus::Open,
             yes_book: OrderBook::new(OptionType::Yes),
             no_book: OrderBook::new(OptionType::No),
+            open_orders: BTreeMap::new(),
+            portfolio: Portfolio::new(),
+            risk_limits: RiskLimits::default(),
+            rate_limiter: None,
+            last_trade_price: BTreeMap::new(),
+            stats: RollingStats::default(),
             next_order_id: 1,
+            next_trade_id: 1,
+            journal_seq: 0,
+            event_seq: 0,
+            book_seq: 0,
+            client_orders: BTreeMap::new(),
+            client_order_id: None,
+            events: Vec::new(),
+            journal: None,
+            snapshots: None,
+            accounts: None,
+            exposure: BTreeMap::new(),
+            audit: None,
             commision_rate: 0.0223, //this would be 2.23 percentage as a platform charge
         }
     }
@@ -107,6 +355,278 @@ impl MatchingEngine {
         id
     }
 
+    fn generate_trade_id(&mut self) -> u64 {
+        let id = self.next_trade_id;
+        self.next_trade_id += 1;
+        id
+    }
+
+    fn attach_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    //from now on orders have to fit the users' balances, orders already resting are held as they
+    //are. a market restored from a snapshot also brings back what its fills hold and, once
+    //resolved, what it paid out
+    fn attach_ledger(&mut self, ledger: Arc<AccountLedger>) {
+        for order in self.open_orders.values() {
+            ledger.adopt_order(self.market_id, order);
+        }
+        ledger.restore_exposure(self.market_id, &self.exposure);
+        if self.status != MarketStatus::Open {
+            ledger.settle_market(self.market_id, self.portfolio.realized_pnl_by_user());
+        }
+        self.accounts = Some(ledger);
+    }
+
+    fn attach_rate_limiter(&mut self, rate_limiter: Arc<RateLimiter>) {
+        self.rate_limiter = Some(rate_limiter);
+    }
+
+    fn attach_audit(&mut self, audit: Arc<AuditLog>) {
+        self.audit = Some(audit);
+    }
+
+    fn emit(&mut self, timestamp: u64, event: EngineEvent) {
+        if !matches!(event, EngineEvent::OrderRejected { .. }) {
+            self.event_seq += 1;
+        }
+        self.events.push(EventEnvelope {
+            market_id: self.market_id,
+            seq: self.event_seq,
+            timestamp,
+            event,
+        });
+    }
+
+    //one level 2 delta per level the command touched, new aggregate quantity included
+    fn publish_book_changes(&mut self, timestamp: u64) {
+        for option in [OptionType::Yes, OptionType::No] {
+            let changes = match option {
+                OptionType::Yes => self.yes_book.take_changes(),
+                OptionType::No => self.no_book.take_changes(),
+            };
+            for (side, price, quantity) in changes {
+                self.book_seq += 1;
+                self.emit(
+                    timestamp,
+                    EngineEvent::BookDelta {
+                        book_seq: self.book_seq,
+                        option,
+                        side,
+                        price: price as f64 / 100.0,
+                        quantity,
+                    },
+                );
+            }
+        }
+    }
+
+    fn drain_events(&mut self) -> Vec<EventEnvelope> {
+        std::mem::take(&mut self.events)
+    }
+
+    //counted from the state the engine was recovered to, not from an empty journal
+    fn enable_snapshots(&mut self, mut policy: SnapshotPolicy) {
+        policy.last_seq = self.journal_seq;
+        self.snapshots = Some(policy);
+    }
+
+    //on demand snapshot of the state after the last applied journal entry
+    fn snapshot(&mut self, dir: &Path) -> io::Result<()> {
+        if let Some(ledger) = &self.accounts {
+            self.exposure = ledger.exposure(self.market_id);
+        }
+        snapshot::write_snapshot(dir, self)?;
+        if let Some(policy) = self.snapshots.as_mut() {
+            policy.last_seq = self.journal_seq;
+        }
+        Ok(())
+    }
+
+    //write ahead, a command only touches the engine once it is on disk
+    fn journal(&mut self, timestamp: u64, command: &Command) -> Result<(), RejectReason> {
+        // the previous command is fully applied at this point, a good moment for a periodic snapshot
+        if let Some(policy) = self.snapshots.as_ref()
+            && policy.is_due(self.journal_seq)
+        {
+            let dir = policy.dir.clone();
+            if let Err(e) = self.snapshot(&dir) {
+                error!("snapshot of market {} failed: {}", self.market_id, e);
+            }
+        }
+
+        if let Some(journal) = self.journal.as_mut() {
+            self.journal_seq = journal
+                .append(timestamp, command, self.client_order_id.as_deref())
+                .map_err(|e| RejectReason::JournalWrite(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    //rebuild the engine from a journal, trades come out exactly as they did the first time.
+    //a ledger is attached before the replay, so it gets back the holds and payouts as well
+    fn replay(
+        market_id: u32,
+        path: &str,
+        ledger: Option<Arc<AccountLedger>>,
+    ) -> io::Result<(Self, Vec<Trade>)> {
+        let mut engine = MatchingEngine::new(market_id);
+        if let Some(ledger) = ledger {
+            engine.attach_ledger(ledger);
+        }
+        let trades = engine.replay_after(path)?;
+        Ok((engine, trades))
+    }
+
+    //start from the latest snapshot and replay only the journal tail written after it
+    fn recover(
+        market_id: u32,
+        snapshot_dir: &Path,
+        path: &str,
+        ledger: Option<Arc<AccountLedger>>,
+    ) -> io::Result<(Self, Vec<Trade>)> {
+        let mut engine = snapshot::load_latest_snapshot(snapshot_dir)?
+            .unwrap_or_else(|| MatchingEngine::new(market_id));
+        if let Some(ledger) = ledger {
+            engine.attach_ledger(ledger);
+        }
+        let trades = engine.replay_after(path)?;
+        Ok((engine, trades))
+    }
+
+    fn replay_after(&mut self, path: &str) -> io::Result<Vec<Trade>> {
+        let entries = match Journal::read_entries(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut trades = Vec::new();
+        for entry in entries {
+            if entry.seq <= self.journal_seq {
+                continue;
+            }
+            let (order, applied) = self.apply_command(&entry.command, entry.timestamp);
+            if let Some(client_order_id) = &entry.client_order_id {
+                let key = client_order_key(entry.command.user_id(), client_order_id);
+                self.client_orders.insert(key, order);
+            }
+            trades.extend(applied);
+            self.journal_seq = entry.seq;
+        }
+        // these events went out before the restart
+        self.events.clear();
+        Ok(trades)
+    }
+
+    //applies an already accepted command, no rate limits, checks or journaling here.
+    //comes back with what submit answered the first time
+    fn apply_command(&mut self, command: &Command, timestamp: u64) -> (Option<Order>, Vec<Trade>) {
+        match command {
+            Command::PlaceOrder {
+                user_id,
+                option,
+                order_type,
+                price,
+                quantity,
+            } => {
+                let order = Order {
+                    id: self.generate_order_id(),
+                    user_id: *user_id,
+                    option: *option,
+                    order_type: order_type.clone(),
+                    price: *price,
+                    quantity: *quantity,
+                    timestamp,
+                };
+                self.emit(
+                    timestamp,
+                    EngineEvent::OrderAccepted {
+                        order: order.clone(),
+                    },
+                );
+                // its balance was checked when it was accepted
+                if let Some(ledger) = &self.accounts {
+                    ledger.adopt_order(self.market_id, &order);
+                }
+                let (order, trades) = self.execute_order(order);
+                (Some(order), trades)
+            }
+            Command::CancelOrder { order_id, .. } => {
+                (self.apply_cancel(*order_id, timestamp), Vec::new())
+            }
+            Command::AmendOrder {
+                order_id,
+                price,
+                quantity,
+                ..
+            } => {
+                if let Some(ledger) = &self.accounts {
+                    ledger.reprice_order(self.market_id, *order_id, *price, *quantity);
+                }
+                self.apply_amend(*order_id, *price, *quantity, timestamp)
+                    .map_or_else(
+                        || (None, Vec::new()),
+                        |(order, trades)| (Some(order), trades),
+                    )
+            }
+            // journals written before resolves were checked can hold a second one
+            Command::Admin(AdminCommand::ResolveMarket { .. })
+                if self.status != MarketStatus::Open =>
+            {
+                (None, Vec::new())
+            }
+            Command::Admin(AdminCommand::ResolveMarket { outcome }) => {
+                self.status = MarketStatus::Resolved(*outcome);
+                self.yes_book.clear();
+                self.no_book.clear();
+                self.publish_book_changes(timestamp);
+                // by id, oldest first
+                for order in std::mem::take(&mut self.open_orders).into_values() {
+                    if let Some(ledger) = &self.accounts {
+                        ledger.close_order(self.market_id, order.id);
+                    }
+                    self.emit(
+                        timestamp,
+                        EngineEvent::OrderCancelled {
+                            order_id: order.id,
+                            user_id: order.user_id,
+                            remaining_quantity: order.quantity,
+                        },
+                    );
+                }
+                self.portfolio.settle(*outcome);
+                if let Some(ledger) = &self.accounts {
+                    ledger.settle_market(self.market_id, self.portfolio.realized_pnl_by_user());
+                }
+                self.emit(timestamp, EngineEvent::Settlement { outcome: *outcome });
+                (None, Vec::new())
+            }
+            Command::Admin(AdminCommand::SetRiskLimits(limits)) => {
+                self.risk_limits = limits.clone();
+                (None, Vec::new())
+            }
+        }
+    }
+
+    fn set_risk_limits(&mut self, limits: RiskLimits) -> Result<(), RejectReason> {
+        let command = Command::Admin(AdminCommand::SetRiskLimits(limits));
+        let timestamp = now_nanos();
+        self.journal(timestamp, &command)?;
+        self.apply_command(&command, timestamp);
+        Ok(())
+    }
+
+    //spend one token of the user's bucket, throttled requests are rejected and never queued
+    fn throttle(&mut self, user_id: u32, kind: RequestKind) -> Result<(), RejectReason> {
+        let Some(rate_limiter) = &self.rate_limiter else {
+            return Ok(());
+        };
+        rate_limiter
+            .check(user_id, kind, Instant::now())
+            .map_err(|retry_after| RejectReason::RateLimited { retry_after })
+    }
+
     //placing new order
     fn place_order(
         &mut self,
@@ -115,12 +635,32 @@ impl MatchingEngine {
         order_type: OrderType,
         price: f64,
         quantity: u32,
-    ) -> (Order, Vec<Trade>) {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let mut order = Order {
+    ) -> Result<(Order, Vec<Trade>), RejectReason> {
+        self.throttle(user_id, RequestKind::NewOrder)?;
+        self.pre_trade_check(user_id, option, &order_type, price, quantity, None)?;
+        let held = ledger::collateral(&order_type, price, quantity);
+        if let Some(ledger) = &self.accounts {
+            ledger.reserve(user_id, held)?;
+        }
+
+        let timestamp = now_nanos();
+        let journaled = self.journal(
+            timestamp,
+            &Command::PlaceOrder {
+                user_id,
+                option,
+                order_type: order_type.clone(),
+                price,
+                quantity,
+            },
+        );
+        if let Err(reason) = journaled {
+            if let Some(ledger) = &self.accounts {
+                ledger.release(user_id, held);
+            }
+            return Err(reason);
+        }
+        let order = Order {
             id: self.generate_order_id(),
             user_id,
             option,
@@ -129,39 +669,390 @@ impl MatchingEngine {
             quantity,
             timestamp,
         };
+        self.emit(
+            timestamp,
+            EngineEvent::OrderAccepted {
+                order: order.clone(),
+            },
+        );
+        if let Some(ledger) = &self.accounts {
+            ledger.open_order(self.market_id, &order);
+        }
+        Ok(self.execute_order(order))
+    }
+
+    //match first and only rest what is left, otherwise a filled order stays in the book
+    fn execute_order(&mut self, mut order: Order) -> (Order, Vec<Trade>) {
+        let mut trades = self.match_order(&mut order);
+        for trade in trades.iter_mut() {
+            trade.id = self.generate_trade_id();
+            trade.timestamp = order.timestamp;
+            trade.market_id = self.market_id;
+            self.stats.record(trade);
+        }
+        self.settle_trades(&order, &trades);
         if order.quantity > 0 {
-            let book = match option {
+            let book = match order.option {
                 OptionType::Yes => &mut self.yes_book,
                 OptionType::No => &mut self.no_book,
             };
+            book.add_order(order.clone());
+            self.open_orders.insert(order.id, order.clone());
+        }
+        self.publish_book_changes(order.timestamp);
+        (order, trades)
+    }
+
+    //rejects the order before it gets an id or touches the books
+    fn pre_trade_check(
+        &self,
+        user_id: u32,
+        option: OptionType,
+        order_type: &OrderType,
+        price: f64,
+        quantity: u32,
+        amending: Option<u64>,
+    ) -> Result<(), RejectReason> {
+        if self.status != MarketStatus::Open {
+            return Err(RejectReason::MarketClosed);
+        }
+        if !(0.5..=9.5).contains(&price) {
+            return Err(RejectReason::PriceOutOfRange(price));
+        }
+        if quantity == 0 {
+            return Err(RejectReason::ZeroQuantity);
+        }
+
+        let ctx = RiskContext {
+            // an amended order replaces itself, it does not add to the open order count
+            open_orders: self
+                .open_orders
+                .values()
+                .filter(|o| o.user_id == user_id && Some(o.id) != amending)
+                .count(),
+            net_position: self.portfolio.net_position(user_id),
+            last_trade_price: self.last_trade_price.get(&option).copied(),
+        };
+        self.risk_limits
+            .check(option, order_type, price, quantity, &ctx)
+    }
+
+    //update positions of both sides and the resting quantity of the maker orders
+    fn settle_trades(&mut self, order: &Order, trades: &[Trade]) {
+        let mut taker_remaining = order.quantity + trades.iter().map(|t| t.quantity).sum::<u32>();
+        for trade in trades {
+            taker_remaining -= trade.quantity;
+            if let Some(ledger) = &self.accounts {
+                ledger.fill(self.market_id, trade);
+            }
+            self.emit(
+                order.timestamp,
+                EngineEvent::Trade {
+                    trade: trade.clone(),
+                },
+            );
+            if trade.match_type == MatchType::Mint {
+                let (yes_user_id, no_user_id) = match trade.option {
+                    OptionType::Yes => (trade.taker_user_id, trade.maker_user_id),
+                    OptionType::No => (trade.maker_user_id, trade.taker_user_id),
+                };
+                self.emit(
+                    order.timestamp,
+                    EngineEvent::Mint {
+                        trade_id: trade.id,
+                        yes_user_id,
+                        no_user_id,
+                        quantity: trade.quantity,
+                    },
+                );
+            }
+            self.emit(
+                order.timestamp,
+                EngineEvent::OrderFilled {
+                    order_id: order.id,
+                    user_id: order.user_id,
+                    price: trade.price,
+                    filled_quantity: trade.quantity,
+                    remaining_quantity: taker_remaining,
+                },
+            );
+
+            self.portfolio.apply_fill(
+                order.user_id,
+                order.option,
+                &order.order_type,
+                trade.price,
+                trade.quantity,
+            );
+
+            let maker_id = if trade.buy_order_id == order.id {
+                trade.sell_order_id
+            } else {
+                trade.buy_order_id
+            };
+            self.last_trade_price.insert(trade.option, trade.price);
+            self.last_trade_price
+                .insert(trade.maker_option, trade.maker_price);
 
-            if price < 0.5 || price > 9.5 {
-                return (
-                    Order {
-                        quantity: 0,
-                        ..order
+            if let Some(maker) = self.open_orders.get_mut(&maker_id) {
+                self.portfolio.apply_fill(
+                    maker.user_id,
+                    maker.option,
+                    &maker.order_type,
+                    trade.maker_price,
+                    trade.quantity,
+                );
+                maker.quantity -= trade.quantity;
+                let (maker_user_id, maker_remaining) = (maker.user_id, maker.quantity);
+                if maker.quantity == 0 {
+                    self.open_orders.remove(&maker_id);
+                }
+                self.emit(
+                    order.timestamp,
+                    EngineEvent::OrderFilled {
+                        order_id: maker_id,
+                        user_id: maker_user_id,
+                        price: trade.maker_price,
+                        filled_quantity: trade.quantity,
+                        remaining_quantity: maker_remaining,
                     },
-                    Vec::new(),
-                ); //reject order
+                );
             }
-            book.add_order(order.clone());
         }
-        let trades = self.match_order(&mut order);
-        (order, trades)
     }
 
-    fn cancel_order(
+    //only the owner may cancel, the order comes back with the quantity that was still open
+    fn cancel_order(&mut self, user_id: u32, order_id: u64) -> Result<Order, RejectReason> {
+        self.throttle(user_id, RequestKind::Cancel)?;
+        let existing = self
+            .open_orders
+            .get(&order_id)
+            .ok_or(RejectReason::UnknownOrder(order_id))?;
+        if existing.user_id != user_id {
+            return Err(RejectReason::NotOrderOwner(order_id));
+        }
+        let command = Command::CancelOrder { user_id, order_id };
+        let timestamp = now_nanos();
+        self.journal(timestamp, &command)?;
+        Ok(self
+            .apply_cancel(order_id, timestamp)
+            .expect("cancelled order is open"))
+    }
+
+    //takes the order off the book it rests in, at the price it rests at
+    fn apply_cancel(&mut self, order_id: u64, timestamp: u64) -> Option<Order> {
+        let order = self.open_orders.remove(&order_id)?;
+        let book = match order.option {
+            OptionType::Yes => &mut self.yes_book,
+            OptionType::No => &mut self.no_book,
+        };
+        book.remove_order(order.order_type.clone(), order.price, order_id);
+        if let Some(ledger) = &self.accounts {
+            ledger.close_order(self.market_id, order.id);
+        }
+        self.emit(
+            timestamp,
+            EngineEvent::OrderCancelled {
+                order_id: order.id,
+                user_id: order.user_id,
+                remaining_quantity: order.quantity,
+            },
+        );
+        self.publish_book_changes(timestamp);
+        Some(order)
+    }
+
+    //change price or quantity of a resting order, only the owner may amend
+    fn amend_order(
         &mut self,
-        option: OptionType,
-        order_type: OrderType,
+        user_id: u32,
+        order_id: u64,
         price: f64,
+        quantity: u32,
+    ) -> Result<(Order, Vec<Trade>), RejectReason> {
+        self.throttle(user_id, RequestKind::NewOrder)?;
+        let existing = self
+            .open_orders
+            .get(&order_id)
+            .ok_or(RejectReason::UnknownOrder(order_id))?;
+        if existing.user_id != user_id {
+            return Err(RejectReason::NotOrderOwner(order_id));
+        }
+        let (option, order_type) = (existing.option, existing.order_type.clone());
+        self.pre_trade_check(
+            user_id,
+            option,
+            &order_type,
+            price,
+            quantity,
+            Some(order_id),
+        )?;
+        let previous_hold = match &self.accounts {
+            Some(ledger) => ledger.amend_order(self.market_id, order_id, price, quantity)?,
+            None => None,
+        };
+
+        let timestamp = now_nanos();
+        let journaled = self.journal(
+            timestamp,
+            &Command::AmendOrder {
+                user_id,
+                order_id,
+                price,
+                quantity,
+            },
+        );
+        if let Err(reason) = journaled {
+            if let (Some(ledger), Some(previous)) = (&self.accounts, previous_hold) {
+                ledger.restore_order(self.market_id, order_id, previous);
+            }
+            return Err(reason);
+        }
+        Ok(self
+            .apply_amend(order_id, price, quantity, timestamp)
+            .expect("amended order is open"))
+    }
+
+    //a pure size reduction keeps queue priority, anything else is re-entered as a fresh order
+    fn apply_amend(
+        &mut self,
         order_id: u64,
-    ) {
-        let book = match option {
+        price: f64,
+        quantity: u32,
+        timestamp: u64,
+    ) -> Option<(Order, Vec<Trade>)> {
+        let existing = self.open_orders.get(&order_id)?.clone();
+        let book = match existing.option {
             OptionType::Yes => &mut self.yes_book,
             OptionType::No => &mut self.no_book,
         };
-        book.remove_order(order_type, price, order_id);
+
+        if price == existing.price && quantity <= existing.quantity {
+            book.reduce_order(&existing.order_type, price, order_id, quantity);
+            let order = self.open_orders.get_mut(&order_id)?;
+            order.quantity = quantity;
+            let order = order.clone();
+            self.emit(
+                timestamp,
+                EngineEvent::OrderAmended {
+                    order: order.clone(),
+                },
+            );
+            self.publish_book_changes(timestamp);
+            return Some((order, Vec::new()));
+        }
+
+        book.remove_order(existing.order_type.clone(), existing.price, order_id);
+        self.open_orders.remove(&order_id);
+        let order = Order {
+            price,
+            quantity,
+            timestamp,
+            ..existing
+        };
+        self.emit(
+            timestamp,
+            EngineEvent::OrderAmended {
+                order: order.clone(),
+            },
+        );
+        Some(self.execute_order(order))
+    }
+
+    //runs any command through the same checks and journaling as the dedicated methods. a
+    //command sent again with the client order id it was accepted with is answered the way it
+    //was the first time, without the trades, and not applied again
+    fn submit(
+        &mut self,
+        command: Command,
+        client_order_id: Option<String>,
+    ) -> Result<(Option<Order>, Vec<Trade>), RejectReason> {
+        let user_id = command.user_id();
+        let audited = self.audit.is_some().then(|| command.clone());
+        let key = client_order_id
+            .as_deref()
+            .map(|id| client_order_key(user_id, id));
+        let result = match key.as_ref().and_then(|key| self.client_orders.get(key)) {
+            Some(order) => Ok((order.clone(), Vec::new())),
+            None => {
+                self.client_order_id = client_order_id;
+                let result = self.dispatch(command);
+                self.client_order_id = None;
+                if let (Ok((order, _)), Some(key)) = (&result, key) {
+                    self.client_orders.insert(key, order.clone());
+                }
+                result
+            }
+        };
+        if let Err(reason) = &result {
+            self.emit(
+                now_nanos(),
+                EngineEvent::OrderRejected {
+                    user_id,
+                    reason: reason.to_string(),
+                },
+            );
+        }
+        if let (Some(audit), Some(command)) = (&self.audit, audited) {
+            let recorded = audit.record(
+                now_nanos(),
+                self.market_id,
+                self.event_seq,
+                &command,
+                &result,
+            );
+            if let Err(e) = recorded {
+                error!(
+                    "could not audit {:?} in market {}: {}",
+                    command, self.market_id, e
+                );
+            }
+        }
+        result
+    }
+
+    fn dispatch(&mut self, command: Command) -> Result<(Option<Order>, Vec<Trade>), RejectReason> {
+        match command {
+            Command::PlaceOrder {
+                user_id,
+                option,
+                order_type,
+                price,
+                quantity,
+            } => self
+                .place_order(user_id, option, order_type, price, quantity)
+                .map(|(order, trades)| (Some(order), trades)),
+            Command::CancelOrder { user_id, order_id } => self
+                .cancel_order(user_id, order_id)
+                .map(|order| (Some(order), Vec::new())),
+            Command::AmendOrder {
+                user_id,
+                order_id,
+                price,
+                quantity,
+            } => self
+                .amend_order(user_id, order_id, price, quantity)
+                .map(|(order, trades)| (Some(order), trades)),
+            Command::Admin(AdminCommand::ResolveMarket { outcome }) => {
+                self.resolve_market(outcome).map(|()| (None, Vec::new()))
+            }
+            Command::Admin(AdminCommand::SetRiskLimits(limits)) => {
+                self.set_risk_limits(limits).map(|()| (None, Vec::new()))
+            }
+        }
+    }
+
+    //settle the market, resting orders are dropped and every position is paid out.
+    //a market settles once, a second resolve is refused whatever its outcome
+    fn resolve_market(&mut self, outcome: OptionType) -> Result<(), RejectReason> {
+        if self.status != MarketStatus::Open {
+            return Err(RejectReason::MarketClosed);
+        }
+        let command = Command::Admin(AdminCommand::ResolveMarket { outcome });
+        let timestamp = now_nanos();
+        self.journal(timestamp, &command)?;
+        self.apply_command(&command, timestamp);
+        Ok(())
     }
 
     fn match_order(&mut self, order: &mut Order) -> Vec<Trade> {
@@ -175,9 +1066,8 @@ impl MatchingEngine {
         };
 
         //step 1: try matching with same option book first
-        println!("matching with same side");
+        trace!("order {} matching in the {:?} book", order.id, order.option);
         remaining_quantity = Self::match_with_book(book, order, remaining_quantity, &mut trades);
-        println!("Cannot able to full fils");
 
         let book_for_counter = if order.option == OptionType::Yes {
             &mut self.no_book
@@ -194,8 +1084,11 @@ impl MatchingEngine {
         //         Self::match_with_book(&mut self.no_book, order, remaining_quantity, &mut trades);
         // }
 
-        println!("mathching with opposite side ");
-        let counter_price = 10.0 - order.price;
+        let counter_price = OrderBook::complement_price(order.price);
+        trace!(
+            "order {} matching {} against the counter book at {:.2}",
+            order.id, remaining_quantity, counter_price
+        );
         remaining_quantity = Self::match_with_counter_book(
             book_for_counter,
             order,
@@ -204,10 +1097,8 @@ impl MatchingEngine {
             &mut trades,
         );
 
-        println!("here end matching with opposite side ");
-
         //matching with opposite side same type then oposite type like YES buy with NO buy , YES sell with NO buy
-        Self::match_with_counter_book_same_type(
+        remaining_quantity = Self::match_with_counter_book_same_type(
             book_for_counter,
             order,
             remaining_quantity,
@@ -240,6 +1131,7 @@ impl MatchingEngine {
         //     order.quantity = remaining_quantity;
         // }
 
+        order.quantity = remaining_quantity;
         trades
     }
 
@@ -257,20 +1149,11 @@ impl MatchingEngine {
                         let ask_price = ask_price_cents as f64 / 100.0;
                         if ask_price <= order.price {
                             if let Some(ask) = asks.pop_front() {
+                                book.changed_asks.insert(ask_price_cents);
                                 let matched_quantity = remaining_quantity.min(ask.quantity);
-                                trades.push(Trade {
-                                    buy_order_id: order.id,
-                                    sell_order_id: ask.id,
-                                    option: order.option,
-                                    price: ask_price,
-                                    quantity: matched_quantity,
-                                });
-
-                                // //updating last matched price
-                                // match order.option {
-                                //     OptionType::Yes =>  // will push price to redis
-                                //     OptionType::No => // will push pricce to redis
-                                //     }
+                                trades.push(Trade::new(order, &ask, matched_quantity));
+
+                                //last matched price is pushed to redis by publisher::MarketPublisher
 
                                 remaining_quantity -= matched_quantity;
                                 if ask.quantity > matched_quantity {
@@ -292,20 +1175,15 @@ impl MatchingEngine {
             }
             OrderType::Sell => {
                 while remaining_quantity > 0 {
-                    if let Some((&bid_price_cents, bids)) = book.bids.iter_mut().rev().next() {
+                    if let Some((&bid_price_cents, bids)) = book.bids.iter_mut().next_back() {
                         let bid_price = bid_price_cents as f64 / 100.0;
                         if bid_price == order.price {
                             // prefer exact match else platform won't able to earn ,
                             // everyone ablt to sell and platform earn minimal so to prevent such and little favour to user also prefer exact match
                             if let Some(bid) = bids.pop_front() {
+                                book.changed_bids.insert(bid_price_cents);
                                 let matched_quantity = remaining_quantity.min(bid.quantity);
-                                trades.push(Trade {
-                                    buy_order_id: bid.id,
-                                    sell_order_id: order.id,
-                                    option: order.option,
-                                    price: bid_price,
-                                    quantity: matched_quantity,
-                                });
+                                trades.push(Trade::new(order, &bid, matched_quantity));
                                 remaining_quantity -= matched_quantity;
                                 if bid.quantity > matched_quantity {
                                     let mut new_bid = bid.clone();
@@ -343,14 +1221,9 @@ impl MatchingEngine {
                         let ask_price = asks_price_cents as f64 / 100.0;
                         if ask_price <= counter_price {
                             if let Some(ask) = asks.pop_front() {
+                                counter_book.changed_asks.insert(asks_price_cents);
                                 let matched_quantity = remaining_quantity.min(ask.quantity);
-                                trades.push(Trade {
-                                    buy_order_id: order.id,
-                                    sell_order_id: ask.id,
-                                    option: order.option,
-                                    price: order.price,
-                                    quantity: matched_quantity,
-                                });
+                                trades.push(Trade::new(order, &ask, matched_quantity));
                                 remaining_quantity -= matched_quantity;
                                 if ask.quantity > matched_quantity {
                                     let mut new_ask = ask.clone();
@@ -372,20 +1245,14 @@ impl MatchingEngine {
             OrderType::Sell => {
                 // counter prcie for  2.7 is 7.3
                 while remaining_quantity > 0 {
-                    if let Some((&bid_price_cents, bids)) =
-                        counter_book.bids.iter_mut().rev().next()
+                    if let Some((&bid_price_cents, bids)) = counter_book.bids.iter_mut().next_back()
                     {
                         let bid_price = bid_price_cents as f64 / 100.0;
                         if bid_price <= counter_price {
                             if let Some(bid) = bids.pop_front() {
+                                counter_book.changed_bids.insert(bid_price_cents);
                                 let matched_quantity = remaining_quantity.min(bid.quantity);
-                                trades.push(Trade {
-                                    buy_order_id: bid.id,
-                                    sell_order_id: order.id,
-                                    option: order.option,
-                                    price: order.price,
-                                    quantity: matched_quantity,
-                                });
+                                trades.push(Trade::new(order, &bid, matched_quantity));
                                 remaining_quantity -= matched_quantity;
                                 if bid.quantity > matched_quantity {
                                     let mut new_bid = bid.clone();
@@ -414,24 +1281,19 @@ impl MatchingEngine {
         mut remaining_quantity: u32,
         counter_price: f64,
         trades: &mut Vec<Trade>,
-    ) {
+    ) -> u32 {
         match order.order_type {
             OrderType::Buy => {
                 while remaining_quantity > 0 {
-                    if let Some((&bid_price_cents, bids)) =
-                        counter_book.bids.iter_mut().rev().next()
+                    if let Some((&bid_price_cents, bids)) = counter_book.bids.iter_mut().next_back()
                     {
                         let bid_price = bid_price_cents as f64 / 100.0;
                         if bid_price >= counter_price {
                             if let Some(bid) = bids.pop_front() {
+                                counter_book.changed_bids.insert(bid_price_cents);
                                 let matched_quantity = remaining_quantity.min(bid.quantity);
-                                trades.push(Trade {
-                                    buy_order_id: order.id,
-                                    sell_order_id: bid.id, //buy-to-buy match
-                                    option: order.option,
-                                    price: order.price,
-                                    quantity: matched_quantity,
-                                });
+                                //buy-to-buy match
+                                trades.push(Trade::new(order, &bid, matched_quantity));
                                 remaining_quantity -= matched_quantity;
                                 if bid.quantity > matched_quantity {
                                     let mut new_bid = bid.clone();
@@ -452,21 +1314,15 @@ impl MatchingEngine {
             }
             OrderType::Sell => {
                 while remaining_quantity > 0 {
-                    if let Some((&bid_price_cents, bids)) =
-                        counter_book.bids.iter_mut().rev().next()
+                    if let Some((&bid_price_cents, bids)) = counter_book.bids.iter_mut().next_back()
                     {
                         let bid_price = bid_price_cents as f64 / 100.0;
                         if bid_price == counter_price {
                             //opposite side buy but want exact match else platform won't able to earn everyone able to sell :)
                             if let Some(bid) = bids.pop_front() {
+                                counter_book.changed_bids.insert(bid_price_cents);
                                 let matched_quantity = remaining_quantity.min(bid.quantity);
-                                trades.push(Trade {
-                                    buy_order_id: bid.id,
-                                    sell_order_id: order.id,
-                                    option: order.option,
-                                    price: order.price,
-                                    quantity: matched_quantity,
-                                });
+                                trades.push(Trade::new(order, &bid, matched_quantity));
                                 remaining_quantity -= matched_quantity;
                                 if bid.quantity > matched_quantity {
                                     let mut new_bid = bid.clone();
@@ -486,6 +1342,7 @@ impl MatchingEngine {
                 }
             }
         }
+        remaining_quantity
     }
 
     fn get_market_price(&self, option: OptionType) -> (Option<f64>, Option<f64>) {
@@ -493,16 +1350,31 @@ impl MatchingEngine {
             OptionType::Yes => &self.yes_book,
             OptionType::No => &self.no_book,
         };
-        let bid_price = book
-            .bids
-            .iter()
-            .rev()
-            .next()
-            .map(|(&p, _)| p as f64 / 100.0);
+        let bid_price = book.bids.iter().next_back().map(|(&p, _)| p as f64 / 100.0);
         let ask_price = book.asks.iter().next().map(|(&p, _)| p as f64 / 100.0);
         (bid_price, ask_price)
     }
 
+    //24h ticker of both outcomes with the best prices as get_market_price has them
+    fn get_ticker(&self, now: u64) -> MarketTicker {
+        let outcomes = [OptionType::Yes, OptionType::No]
+            .into_iter()
+            .map(|option| {
+                let (best_bid, best_ask) = self.get_market_price(option);
+                stats::OutcomeTicker {
+                    open_interest: self.portfolio.open_interest(option),
+                    best_bid,
+                    best_ask,
+                    ..self.stats.ticker(option, now)
+                }
+            })
+            .collect();
+        MarketTicker {
+            market_id: self.market_id,
+            outcomes,
+        }
+    }
+
     fn get_order_book(&self, option: OptionType) -> (BTreeMap<u64, u32>, BTreeMap<u64, u32>) {
         let book = match option {
             OptionType::Yes => &self.yes_book,
@@ -521,34 +1393,672 @@ impl MatchingEngine {
             .collect();
         (bids, asks)
     }
+
+    //one outcome with the other one seen through the complement: buying no at 2.7 is
+    //selling yes at 7.3, so a no bid is a yes ask at 7.3 and a no ask at 2.7 a yes bid
+    fn get_consolidated_book(&self, option: OptionType) -> BookDepth {
+        let (mut bids, mut asks) = self.get_order_book(option);
+        let (counter_bids, counter_asks) = self.get_order_book(match option {
+            OptionType::Yes => OptionType::No,
+            OptionType::No => OptionType::Yes,
+        });
+        for (price, quantity) in counter_bids {
+            *asks.entry(1000 - price).or_default() += quantity;
+        }
+        for (price, quantity) in counter_asks {
+            *bids.entry(1000 - price).or_default() += quantity;
+        }
+        (bids, asks)
+    }
+
+    //both books as of the last level 2 delta, deltas with a higher book_seq apply on top
+    fn get_book_snapshot(&self) -> BookSnapshot {
+        BookSnapshot {
+            book_seq: self.book_seq,
+            yes: self.get_order_book(OptionType::Yes),
+            no: self.get_order_book(OptionType::No),
+        }
+    }
+
+    //level 3 view, every resting order of a book in the order it would be filled
+    fn get_order_queue(&self, option: OptionType) -> BookQueue {
+        let book = match option {
+            OptionType::Yes => &self.yes_book,
+            OptionType::No => &self.no_book,
+        };
+        let queue = |side: &BTreeMap<u64, VecDeque<Order>>| {
+            side.iter()
+                .map(|(&price, orders)| (price, orders.iter().cloned().collect()))
+                .collect()
+        };
+        (queue(&book.bids), queue(&book.asks))
+    }
+
+    //depth of both books and how far the journal is ahead of the last snapshot
+    fn gauges(&self) -> MarketGauges {
+        let snapshot_seq = self.snapshots.as_ref().map_or(0, |policy| policy.last_seq);
+        MarketGauges {
+            books: [
+                (OptionType::Yes, self.get_order_book(OptionType::Yes)),
+                (OptionType::No, self.get_order_book(OptionType::No)),
+            ],
+            journal_seq: self.journal_seq,
+            journal_lag: self.journal_seq.saturating_sub(snapshot_seq),
+        }
+    }
+
+    //a resting order, only its owner gets to see it
+    fn get_order(&mut self, user_id: u32, order_id: u64) -> Result<Order, RejectReason> {
+        self.throttle(user_id, RequestKind::Query)?;
+        let order = self
+            .open_orders
+            .get(&order_id)
+            .ok_or(RejectReason::UnknownOrder(order_id))?;
+        if order.user_id != user_id {
+            return Err(RejectReason::NotOrderOwner(order_id));
+        }
+        Ok(order.clone())
+    }
+
+    fn get_open_orders(&mut self, user_id: u32) -> Result<Vec<Order>, RejectReason> {
+        self.throttle(user_id, RequestKind::Query)?;
+        Ok(self
+            .open_orders
+            .values()
+            .filter(|o| o.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    //average cost, realized and unrealized pnl of every position the user holds in this market
+    fn get_portfolio(&self, user_id: u32) -> Vec<PositionPnl> {
+        self.portfolio
+            .positions(user_id)
+            .map(|(&option, position)| {
+                let mark_price = match self.get_market_price(option) {
+                    (Some(bid), Some(ask)) if self.status == MarketStatus::Open => {
+                        Some((bid + ask) / 2.0)
+                    }
+                    _ => None,
+                };
+                PositionPnl {
+                    market_id: self.market_id,
+                    option,
+                    quantity: position.quantity,
+                    avg_cost: position.avg_cost,
+                    realized_pnl: position.realized_pnl,
+                    mark_price,
+                    unrealized_pnl: mark_price
+                        .map(|mark| (mark - position.avg_cost) * position.quantity as f64),
+                }
+            })
+            .collect()
+    }
+}
+
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+// --journal <path>: recover from and keep appending to the journal
+// --snapshots <dir>: start from the latest snapshot, snapshot every 100 journaled commands
+// --snapshot-every <n>: snapshot every n journaled commands instead
+//three markets on their own tasks sharing one account ledger
+async fn run_shards() -> Result<(), ServiceError> {
+    let ledger = Arc::new(AccountLedger::new());
+    ledger.deposit(1, 1000.0);
+    ledger.deposit(2, 1000.0);
+    let router = EngineRouter::new(Some(ledger.clone()), Arc::new(RateLimiter::new()), None);
+    for market_id in 1..=3 {
+        router.add_market(MatchingEngine::new(market_id)).await?;
+    }
+    let place = |user_id, option, order_type, price, quantity| Command::PlaceOrder {
+        user_id,
+        option,
+        order_type,
+        price,
+        quantity,
+    };
+
+    router
+        .submit(1, place(1, OptionType::Yes, OrderType::Buy, 6.0, 150))
+        .await?;
+    // the 900 held in market 1 can not back an order in market 2 as well
+    match router
+        .submit(2, place(1, OptionType::No, OrderType::Buy, 4.0, 50))
+        .await
+    {
+        Ok(_) => println!("Market 2 order accepted"),
+        Err(e) => println!("Market 2 order rejected: {}", e),
+    }
+    let (_, trades) = router
+        .submit(1, place(2, OptionType::Yes, OrderType::Sell, 6.0, 100))
+        .await?;
+    println!("Market 1 trades: {:?}", trades);
+
+    // market 3 is quiet, let it share the task of market 1
+    router.move_market(3, 0).await?;
+    router
+        .submit(3, place(2, OptionType::No, OrderType::Buy, 3.5, 20))
+        .await?;
+    println!("Markets and shards: {:?}", router.markets().await);
+
+    router
+        .submit(
+            1,
+            Command::Admin(AdminCommand::ResolveMarket {
+                outcome: OptionType::Yes,
+            }),
+        )
+        .await?;
+    for user_id in [1, 2] {
+        let (balance, available) = ledger.balance(user_id);
+        println!(
+            "User {} balance {:.2}, available {:.2}",
+            user_id, balance, available
+        );
+    }
+    Ok(())
+}
+
+// --balances <file>: json map of user id -> deposit, orders then have to fit the balance
+fn open_ledger(args: &[String]) -> Option<Arc<AccountLedger>> {
+    flag(args, "--balances").map(|path| {
+        let raw = std::fs::read_to_string(path).expect("could not read --balances");
+        let balances: HashMap<u32, f64> = serde_json::from_str(&raw).expect("invalid --balances");
+        let ledger = AccountLedger::new();
+        for (user_id, amount) in balances {
+            ledger.deposit(user_id, amount);
+        }
+        Arc::new(ledger)
+    })
+}
+
+// --tokens <file>: json map of api token -> user id
+fn open_tokens(args: &[String]) -> AuthTokens {
+    match flag(args, "--tokens") {
+        Some(path) => {
+            let raw = std::fs::read_to_string(path).expect("could not read --tokens");
+            serde_json::from_str(&raw).expect("invalid --tokens")
+        }
+        None => HashMap::new(),
+    }
+}
+
+//candles and trade tape, backfilled with every trade in the journal. without a snapshot those
+//are the trades the engine replayed to. a snapshot only carries the engine state, so after
+//recovering from one the whole journal is read again for the trades before it
+fn open_history(args: &[String], replayed: &[Trade]) -> Arc<TradeHistory> {
+    let intervals = match flag(args, "--candle-intervals") {
+        Some(list) => list
+            .split(',')
+            .map(|name| Interval::parse(name.trim()).expect("invalid --candle-intervals"))
+            .collect(),
+        None => Interval::defaults(),
+    };
+    let history = TradeHistory::new(intervals);
+    let journaled;
+    let trades = match (flag(args, "--snapshots"), flag(args, "--journal")) {
+        (Some(_), Some(path)) => {
+            (_, journaled) =
+                MatchingEngine::replay(1, path, None).expect("could not backfill history");
+            &journaled
+        }
+        _ => replayed,
+    };
+    for trade in trades {
+        history.record(trade);
+    }
+    Arc::new(history)
+}
+
+//round trips of quote, requote and pull over the binary protocol, then a self cross for the fills
+fn run_wire_bench(addr: &str, token: &str, rounds: usize) -> io::Result<()> {
+    let (mut client, user_id) = wire::Client::connect(addr, token)?;
+    println!("logged in as user {}", user_id);
+    let mut latencies = Vec::new();
+    let mut timed = |client: &mut wire::Client, client_id: u64| {
+        let start = Instant::now();
+        let response = client.wait(client_id);
+        latencies.push(start.elapsed());
+        response
+    };
+    for _ in 0..rounds {
+        let client_id = client.new_order(1, wire::Outcome::Yes, wire::Side::Buy, 100, 10)?;
+        let order_id = match timed(&mut client, client_id)? {
+            wire::Response::Ack { order_id, .. } => order_id,
+            other => return Err(io::Error::other(format!("quote rejected: {:?}", other))),
+        };
+        let client_id = client.amend(1, order_id, 101, 10)?;
+        timed(&mut client, client_id)?;
+        let client_id = client.cancel(1, order_id)?;
+        timed(&mut client, client_id)?;
+    }
+    latencies.sort();
+    if let (Some(median), Some(worst)) = (latencies.get(latencies.len() / 2), latencies.last()) {
+        println!(
+            "{} round trips, median {:?}, worst {:?}",
+            latencies.len(),
+            median,
+            worst
+        );
+    }
+
+    client.new_order(1, wire::Outcome::Yes, wire::Side::Buy, 500, 1)?;
+    client.new_order(1, wire::Outcome::Yes, wire::Side::Sell, 500, 1)?;
+    let mut fills = 0;
+    while fills < 2 {
+        if let wire::Response::Fill {
+            order_id, price, ..
+        } = client.recv()?
+        {
+            println!("order {} filled at {}", order_id, price);
+            fills += 1;
+        }
+    }
+    Ok(())
+}
+
+//the engine plus the trades replayed from its journal, for whatever needs backfilling. after
+//recovering from a snapshot those are only the trades of the journal tail. the ledger gets
+//back what the journaled orders, fills and settlements of the market did to it
+fn open_engine(
+    args: &[String],
+    ledger: Option<&Arc<AccountLedger>>,
+) -> (MatchingEngine, Vec<Trade>) {
+    let snapshot_dir = flag(args, "--snapshots");
+    let (mut engine, trades) = match flag(args, "--journal") {
+        Some(path) => {
+            let (mut engine, trades) = match snapshot_dir {
+                Some(dir) => MatchingEngine::recover(1, Path::new(dir), path, ledger.cloned()),
+                None => MatchingEngine::replay(1, path, ledger.cloned()),
+            }
+            .expect("could not recover engine");
+            engine.attach_journal(Journal::open(path).expect("could not open journal"));
+            (engine, trades)
+        }
+        None => {
+            let mut engine = MatchingEngine::new(1);
+            if let Some(ledger) = ledger {
+                engine.attach_ledger(ledger.clone());
+            }
+            (engine, Vec::new())
+        }
+    };
+    if let Some(dir) = snapshot_dir {
+        let interval = flag(args, "--snapshot-every")
+            .map_or(100, |n| n.parse().expect("invalid --snapshot-every"));
+        engine.enable_snapshots(SnapshotPolicy::new(dir, interval));
+    }
+    (engine, trades)
+}
+
+// --audit <path>: json lines record of every command of every market, its outcome and trades
+fn open_audit(args: &[String]) -> Option<Arc<AuditLog>> {
+    flag(args, "--audit")
+        .map(|path| Arc::new(AuditLog::open(path).expect("could not open audit log")))
+}
+
+// --rate-limits <file>: json with per class limits and the class of accounts, the defaults
+// stand for whatever it leaves out
+// --market-makers <ids>: comma separated users on the market maker rate limits
+fn open_rate_limiter(args: &[String]) -> Arc<RateLimiter> {
+    let config = match flag(args, "--rate-limits") {
+        Some(path) => {
+            let raw = std::fs::read_to_string(path).expect("could not read --rate-limits");
+            serde_json::from_str(&raw).expect("invalid --rate-limits")
+        }
+        None => RateLimitConfig::default(),
+    };
+    let limiter = RateLimiter::from_config(config).expect("invalid --rate-limits");
+    if let Some(users) = flag(args, "--market-makers") {
+        for user_id in users.split(',') {
+            let user_id = user_id.trim().parse().expect("invalid --market-makers");
+            limiter.set_account_class(user_id, AccountClass::MarketMaker);
+        }
+    }
+    Arc::new(limiter)
 }
 
 fn main() {
-    let mut engine = MatchingEngine::new();
+    // RUST_LOG overrides, e.g. RUST_LOG=trace for the matching steps
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // probo-engine replay <journal>: rebuild the engine and print the trades it produced
+    // --write-snapshot <dir>: also snapshot the rebuilt state, byte for byte what the live
+    // engine wrote at the same journal entry when it is given the same --balances
+    // probo-engine recover <journal> <snapshot dir>: same, starting from the latest snapshot
+    let recovered = match args.as_slice() {
+        [mode, path, ..] if mode == "replay" => {
+            Some(MatchingEngine::replay(1, path, open_ledger(&args)))
+        }
+        [mode, path, dir] if mode == "recover" => {
+            Some(MatchingEngine::recover(1, Path::new(dir), path, None))
+        }
+        _ => None,
+    };
+    if let Some(recovered) = recovered {
+        let (mut engine, trades) = recovered.expect("could not replay journal");
+        if let Some(dir) = flag(&args, "--write-snapshot") {
+            engine
+                .snapshot(Path::new(dir))
+                .expect("could not write snapshot");
+        }
+        for trade in &trades {
+            println!("{}", serde_json::to_string(trade).unwrap());
+        }
+        for option in [OptionType::Yes, OptionType::No] {
+            let (bids, asks) = engine.get_order_book(option);
+            println!("{:?} bids: {:?}, asks: {:?}", option, bids, asks);
+        }
+        return;
+    }
+
+    // probo-engine worker <redis url>: apply commands pushed to redis by the api servers
+    if let [mode, redis_url, ..] = args.as_slice()
+        && mode == "worker"
+    {
+        let ledger = open_ledger(&args);
+        let (engine, _) = open_engine(&args, ledger.as_ref());
+        // --stream-maxlen <n> or --stream-max-age-secs <n>: retention of the event streams
+        let retention = match (
+            flag(&args, "--stream-maxlen"),
+            flag(&args, "--stream-max-age-secs"),
+        ) {
+            (Some(len), _) => {
+                RetentionPolicy::MaxLen(len.parse().expect("invalid --stream-maxlen"))
+            }
+            (None, Some(secs)) => RetentionPolicy::MaxAge(Duration::from_secs(
+                secs.parse().expect("invalid --stream-max-age-secs"),
+            )),
+            (None, None) => RetentionPolicy::default(),
+        };
+        let rate_limiter = open_rate_limiter(&args);
+        let audit = open_audit(&args);
+        let runtime = tokio::runtime::Runtime::new().expect("could not start tokio runtime");
+        let result = runtime.block_on(async {
+            let router = EngineRouter::new(ledger, rate_limiter, audit);
+            router.add_market(engine).await?;
+            RedisWorker::new(redis_url, Arc::new(router), retention)?
+                .run()
+                .await
+        });
+        if let Err(e) = result {
+            error!("worker stopped: {}", e);
+        }
+        return;
+    }
+
+    // probo-engine http <addr>: serve the rest api and websocket feeds
+    // --tokens <file>: json map of api token -> user id, for order entry and the private feeds
+    // --admins <ids>: comma separated users who see the owners of orders in the level 3 book
+    // --candle-intervals <list>: comma separated candle widths, 1m,5m,1h,1d by default
+    if let [mode, addr, ..] = args.as_slice()
+        && mode == "http"
+    {
+        let ledger = open_ledger(&args);
+        let (engine, replayed) = open_engine(&args, ledger.as_ref());
+        let rate_limiter = open_rate_limiter(&args);
+        let audit = open_audit(&args);
+        let tokens = open_tokens(&args);
+        let admins = flag(&args, "--admins")
+            .map(|users| {
+                users
+                    .split(',')
+                    .map(|user_id| user_id.trim().parse().expect("invalid --admins"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let history = open_history(&args, &replayed);
+        let runtime = tokio::runtime::Runtime::new().expect("could not start tokio runtime");
+        let result = runtime.block_on(async {
+            let router = EngineRouter::new(ledger, rate_limiter, audit);
+            router.add_market(engine).await?;
+            http::serve(addr, Arc::new(router), tokens, admins, history).await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+        if let Err(e) = result {
+            error!("http api stopped: {}", e);
+        }
+        return;
+    }
+
+    // probo-engine fix <addr> --fix-sessions <file>: fix 4.4 order entry
+    // --fix-sessions <file>: json map of counterparty comp id -> user id
+    // --fix-store <dir>: sequence numbers and sent messages per session, fix-store by default
+    // --fix-comp-id <id>: our comp id, PROBO by default
+    if let [mode, addr, ..] = args.as_slice()
+        && mode == "fix"
+    {
+        let ledger = open_ledger(&args);
+        let (engine, _) = open_engine(&args, ledger.as_ref());
+        let rate_limiter = open_rate_limiter(&args);
+        let audit = open_audit(&args);
+        let raw = std::fs::read_to_string(
+            flag(&args, "--fix-sessions").expect("--fix-sessions is required"),
+        )
+        .expect("could not read --fix-sessions");
+        let config = fix::FixConfig {
+            comp_id: flag(&args, "--fix-comp-id").unwrap_or("PROBO").to_string(),
+            sessions: serde_json::from_str(&raw).expect("invalid --fix-sessions"),
+            store_dir: flag(&args, "--fix-store").unwrap_or("fix-store").into(),
+        };
+        let runtime = tokio::runtime::Runtime::new().expect("could not start tokio runtime");
+        let result = runtime.block_on(async {
+            let router = EngineRouter::new(ledger, rate_limiter, audit);
+            router.add_market(engine).await?;
+            fix::serve(addr, Arc::new(router), config).await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+        if let Err(e) = result {
+            error!("fix acceptor stopped: {}", e);
+        }
+        return;
+    }
+
+    // probo-engine binary <addr>: length-prefixed binary order entry, logins from --tokens
+    if let [mode, addr, ..] = args.as_slice()
+        && mode == "binary"
+    {
+        let ledger = open_ledger(&args);
+        let (engine, _) = open_engine(&args, ledger.as_ref());
+        let rate_limiter = open_rate_limiter(&args);
+        let audit = open_audit(&args);
+        let tokens = open_tokens(&args);
+        let runtime = tokio::runtime::Runtime::new().expect("could not start tokio runtime");
+        let result = runtime.block_on(async {
+            let router = EngineRouter::new(ledger, rate_limiter, audit);
+            router.add_market(engine).await?;
+            wire_server::serve(addr, Arc::new(router), tokens).await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+        if let Err(e) = result {
+            error!("binary order entry stopped: {}", e);
+        }
+        return;
+    }
+
+    // probo-engine wire-bench <addr> <token> [--rounds <n>]: latency of the binary protocol
+    if let [mode, addr, token, ..] = args.as_slice()
+        && mode == "wire-bench"
+    {
+        let rounds =
+            flag(&args, "--rounds").map_or(1_000, |n| n.parse().expect("invalid --rounds"));
+        if let Err(e) = run_wire_bench(addr, token, rounds) {
+            error!("wire bench failed: {}", e);
+        }
+        return;
+    }
+
+    // probo-engine repl [--markets <n>]: operator console, market 1 from --journal and
+    // markets 2 to n empty
+    if let Some(mode) = args.first()
+        && mode == "repl"
+    {
+        let ledger = open_ledger(&args);
+        let (engine, _) = open_engine(&args, ledger.as_ref());
+        let rate_limiter = open_rate_limiter(&args);
+        let audit = open_audit(&args);
+        let markets: u32 =
+            flag(&args, "--markets").map_or(1, |n| n.parse().expect("invalid --markets"));
+        let runtime = tokio::runtime::Runtime::new().expect("could not start tokio runtime");
+        let router = runtime.block_on(async {
+            let router = EngineRouter::new(ledger, rate_limiter, audit);
+            router.add_market(engine).await?;
+            for market_id in 2..=markets {
+                router.add_market(MatchingEngine::new(market_id)).await?;
+            }
+            Ok::<_, ServiceError>(router)
+        });
+        let router = router.expect("could not start markets");
+        let mut repl = repl::Repl::new(runtime, router, 1);
+        if let Err(e) = repl.run(io::stdin().lock(), &mut io::stdout()) {
+            error!("console stopped: {}", e);
+        }
+        return;
+    }
+
+    // probo-engine scenario <file>... [--update]: check golden scenarios, --update rewrites
+    // the expected transcripts instead
+    if let Some(mode) = args.first()
+        && mode == "scenario"
+    {
+        let update = args.iter().any(|arg| arg == "--update");
+        let mut failed = false;
+        for path in args[1..].iter().filter(|arg| !arg.starts_with("--")) {
+            match scenario::check(Path::new(path), update) {
+                Ok(None) => println!("ok {}", path),
+                Ok(Some(diff)) => {
+                    failed = true;
+                    println!("FAILED {}, - expected + actual:\n{}", path, diff);
+                }
+                Err(e) => {
+                    failed = true;
+                    println!("FAILED {}: {}", path, e);
+                }
+            }
+        }
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // probo-engine shards: several markets on their own tasks, sharing account balances
+    if let Some(mode) = args.first()
+        && mode == "shards"
+    {
+        let runtime = tokio::runtime::Runtime::new().expect("could not start tokio runtime");
+        if let Err(e) = runtime.block_on(run_shards()) {
+            error!("shards scenario failed: {}", e);
+        }
+        return;
+    }
+
+    let (mut engine, _) = open_engine(&args, None);
+    if let Some(audit) = open_audit(&args) {
+        engine.attach_audit(audit);
+    }
+    let snapshot_dir = flag(&args, "--snapshots");
+
+    engine
+        .set_risk_limits(RiskLimits {
+            max_order_quantity: 1_000,
+            ..RiskLimits::default()
+        })
+        .expect("could not set risk limits");
+    let rate_limiter = Arc::new(RateLimiter::new());
+    rate_limiter
+        .set_class_limits(
+            AccountClass::Retail,
+            ClassLimits {
+                new_orders: BucketLimit {
+                    burst: 5,
+                    per_second: 1.0,
+                },
+                cancels: BucketLimit {
+                    burst: 5,
+                    per_second: 1.0,
+                },
+                queries: BucketLimit {
+                    burst: 10,
+                    per_second: 5.0,
+                },
+            },
+        )
+        .expect("invalid rate limits");
+    engine.attach_rate_limiter(rate_limiter.clone());
 
     //scenario: buy Yes at 7.3, Buy No at 2.7
     println!("placing Buy yes at 7.3 (100 shares)");
 
-    let (order11, trades11) = engine.place_order(11, OptionType::No, OrderType::Buy, 2.7, 150); // 2.7 or less trade happen with counter
+    let (order11, trades11) = engine
+        .place_order(11, OptionType::No, OrderType::Buy, 2.7, 150)
+        .expect("order rejected"); // 2.7 or less trade happen with counter
     println!("Order11: {:?}", order11);
     println!("Trades11: {:?},", trades11);
 
-    let (order1, trades1) = engine.place_order(1, OptionType::Yes, OrderType::Buy, 7.4, 150); //placed order
+    let (order1, trades1) = engine
+        .place_order(1, OptionType::Yes, OrderType::Buy, 7.4, 150)
+        .expect("order rejected"); //placed order
     println!("Order: {:?}", order1);
     println!("Trades: {:?},", trades1);
-    // let (order11, trades11) = engine.place_order(11, OptionType::Yes, OrderType::Sell, 7.2, 50);
-    // println!("Order11: {:?}", order11);
-    // println!("Trades11: {:?},", trades11);
+
+    let (order2, trades2) = engine
+        .place_order(2, OptionType::No, OrderType::Sell, 2.9, 50)
+        .expect("order rejected"); //resting no ask
+    println!("Order2: {:?}", order2);
+    println!("Trades2: {:?},", trades2);
+
+    //size reduction keeps the order's place in the queue
+    let (order2, _) = engine
+        .amend_order(2, order2.id, 2.9, 40)
+        .expect("amend rejected");
+    println!("Order2 amended: {:?}", order2);
+    if let Err(reason) = engine.amend_order(11, order2.id, 3.0, 40) {
+        println!("Order2 amend by user 11 rejected: {}", reason);
+    }
+
+    // far away from the last no trade at 2.7, fat finger check rejects it
+    if let Err(reason) = engine.place_order(3, OptionType::No, OrderType::Buy, 9.0, 10) {
+        println!("Order3 rejected: {}", reason);
+    }
 
     // let (order111, trades111) = engine.place_order(111, OptionType::Yes, OrderType::Sell, 7.3, 150);
     // println!("Order111: {:?}", order111);
     // println!("Trades111: {:?},", trades111);
 
-    // let (bid_price, ask_price) = engine.get_market_price(OptionType::Yes);
-    // println!("bid: {:?}, ask: {:?}", bid_price, ask_price);
+    let (bid_price, ask_price) = engine.get_market_price(OptionType::No);
+    println!("bid: {:?}, ask: {:?}", bid_price, ask_price);
 
-    // let (bids, asks) = engine.get_order_book(OptionType::Yes);
-    // println!("bids: {:?}, asks: {:?}", bids, asks);
+    let (bids, asks) = engine.get_order_book(OptionType::No);
+    println!("bids: {:?}, asks: {:?}", bids, asks);
+
+    rate_limiter.set_account_class(1, AccountClass::MarketMaker);
+    if engine.throttle(1, RequestKind::Query).is_ok() {
+        println!("Portfolio1: {:?}", engine.get_portfolio(1));
+    }
+    println!("Portfolio11: {:?}", engine.get_portfolio(11));
+
+    engine.cancel_order(2, order2.id).expect("cancel rejected");
+    engine
+        .resolve_market(OptionType::Yes)
+        .expect("resolve rejected");
+    println!("Portfolio1 after resolve: {:?}", engine.get_portfolio(1));
+
+    for event in engine.drain_events() {
+        println!("Event: {}", serde_json::to_string(&event).unwrap());
+    }
+
+    if let Some(dir) = snapshot_dir {
+        engine
+            .snapshot(Path::new(dir))
+            .expect("could not write snapshot");
+    }
 
     // let (order2, trades2) = engine.place_order(2, OptionType::No, OrderType::Sell, 2.9, 500); //placed sell order
     // println!("Order2: {:?}", order2);
@@ -0,0 +1,350 @@
+use std::{collections::BTreeMap, fmt::Write as _, sync::Mutex, time::Duration};
+
+use crate::{
+    OptionType, Order, Trade, risk::RejectReason, router::EngineRouter, service::BookDepth,
+};
+
+// upper bounds of the place order latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.000_01, 0.000_025, 0.000_05, 0.000_1, 0.000_25, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025,
+    0.1,
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()], // observations per bucket, made cumulative when rendered
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    accepted: BTreeMap<(u32, &'static str), u64>, // market, command
+    rejected: BTreeMap<(u32, &'static str, &'static str), u64>, // market, command, reason
+    trades: BTreeMap<(u32, &'static str), (u64, u64)>, // market, taker outcome -> trades, shares
+    place_order: BTreeMap<u32, Histogram>,
+}
+
+//what the engine tasks have done since the process started, gauges are read from the
+//markets when scraped
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+//book gauges and journal position of one market at the time of the scrape
+pub struct MarketGauges {
+    pub books: [(OptionType, BookDepth); 2],
+    pub journal_seq: u64,
+    pub journal_lag: u64, // journaled commands a restart would have to replay
+}
+
+impl Metrics {
+    //one command run by the engine of a market and how long it took, kind as in Command::kind
+    pub fn record(
+        &self,
+        market_id: u32,
+        kind: &'static str,
+        elapsed: Duration,
+        result: &Result<(Option<Order>, Vec<Trade>), RejectReason>,
+    ) {
+        let mut counters = self.counters.lock().unwrap();
+        match result {
+            Ok((_, trades)) => {
+                *counters.accepted.entry((market_id, kind)).or_default() += 1;
+                for trade in trades {
+                    let (count, shares) = counters
+                        .trades
+                        .entry((market_id, outcome_label(trade.option)))
+                        .or_default();
+                    *count += 1;
+                    *shares += trade.quantity as u64;
+                }
+            }
+            Err(reason) => {
+                *counters
+                    .rejected
+                    .entry((market_id, kind, reason_label(reason)))
+                    .or_default() += 1;
+            }
+        }
+        if kind == "place_order" {
+            counters
+                .place_order
+                .entry(market_id)
+                .or_default()
+                .observe(elapsed.as_secs_f64());
+        }
+    }
+}
+
+fn reason_label(reason: &RejectReason) -> &'static str {
+    match reason {
+        RejectReason::MarketClosed => "market_closed",
+        RejectReason::PriceOutOfRange(_) => "price_out_of_range",
+        RejectReason::ZeroQuantity => "zero_quantity",
+        RejectReason::MaxOrderQuantity { .. } => "max_order_quantity",
+        RejectReason::MaxOrderNotional { .. } => "max_order_notional",
+        RejectReason::MaxOpenOrders { .. } => "max_open_orders",
+        RejectReason::MaxNetPosition { .. } => "max_net_position",
+        RejectReason::PriceDeviation { .. } => "price_deviation",
+        RejectReason::RateLimited { .. } => "rate_limited",
+        RejectReason::UnknownOrder(_) => "unknown_order",
+        RejectReason::NotOrderOwner(_) => "not_order_owner",
+        RejectReason::JournalWrite(_) => "journal_write",
+        RejectReason::InsufficientBalance { .. } => "insufficient_balance",
+    }
+}
+
+fn outcome_label(option: OptionType) -> &'static str {
+    match option {
+        OptionType::Yes => "yes",
+        OptionType::No => "no",
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+//everything in the prometheus text format, markets that went away while scraping are left out
+pub async fn render(engine: &EngineRouter) -> String {
+    let mut out = String::new();
+    {
+        let counters = engine.metrics().counters.lock().unwrap();
+
+        header(
+            &mut out,
+            "probo_commands_accepted_total",
+            "counter",
+            "Commands the engine accepted.",
+        );
+        for ((market, command), count) in &counters.accepted {
+            writeln!(
+                out,
+                "probo_commands_accepted_total{{market=\"{}\",command=\"{}\"}} {}",
+                market, command, count
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "probo_commands_rejected_total",
+            "counter",
+            "Commands the engine rejected, by reason.",
+        );
+        for ((market, command, reason), count) in &counters.rejected {
+            writeln!(
+                out,
+                "probo_commands_rejected_total{{market=\"{}\",command=\"{}\",reason=\"{}\"}} {}",
+                market, command, reason, count
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "probo_trades_total",
+            "counter",
+            "Trades by the outcome of the taker leg.",
+        );
+        for ((market, outcome), (count, _)) in &counters.trades {
+            writeln!(
+                out,
+                "probo_trades_total{{market=\"{}\",outcome=\"{}\"}} {}",
+                market, outcome, count
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "probo_traded_shares_total",
+            "counter",
+            "Shares traded by the outcome of the taker leg.",
+        );
+        for ((market, outcome), (_, shares)) in &counters.trades {
+            writeln!(
+                out,
+                "probo_traded_shares_total{{market=\"{}\",outcome=\"{}\"}} {}",
+                market, outcome, shares
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "probo_place_order_seconds",
+            "histogram",
+            "Time the engine spent on a place order, checks, journal and matching.",
+        );
+        for (market, histogram) in &counters.place_order {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "probo_place_order_seconds_bucket{{market=\"{}\",le=\"{}\"}} {}",
+                    market, bound, cumulative
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "probo_place_order_seconds_bucket{{market=\"{}\",le=\"+Inf\"}} {}",
+                market, histogram.count
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "probo_place_order_seconds_sum{{market=\"{}\"}} {}",
+                market, histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "probo_place_order_seconds_count{{market=\"{}\"}} {}",
+                market, histogram.count
+            )
+            .unwrap();
+        }
+    }
+
+    let mut markets = Vec::new();
+    for (market_id, _) in engine.markets().await {
+        if let Ok(gauges) = engine.gauges(market_id).await {
+            markets.push((market_id, gauges));
+        }
+    }
+
+    header(
+        &mut out,
+        "probo_book_levels",
+        "gauge",
+        "Price levels resting in a book.",
+    );
+    for (market, gauges) in &markets {
+        for (option, (bids, asks)) in &gauges.books {
+            for (side, levels) in [("bid", bids), ("ask", asks)] {
+                writeln!(
+                    out,
+                    "probo_book_levels{{market=\"{}\",outcome=\"{}\",side=\"{}\"}} {}",
+                    market,
+                    outcome_label(*option),
+                    side,
+                    levels.len()
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    header(
+        &mut out,
+        "probo_book_quantity",
+        "gauge",
+        "Shares resting in a book.",
+    );
+    for (market, gauges) in &markets {
+        for (option, (bids, asks)) in &gauges.books {
+            for (side, levels) in [("bid", bids), ("ask", asks)] {
+                writeln!(
+                    out,
+                    "probo_book_quantity{{market=\"{}\",outcome=\"{}\",side=\"{}\"}} {}",
+                    market,
+                    outcome_label(*option),
+                    side,
+                    levels.values().map(|q| *q as u64).sum::<u64>()
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    header(
+        &mut out,
+        "probo_book_spread",
+        "gauge",
+        "Best ask minus best bid of a book, left out while a side is empty.",
+    );
+    for (market, gauges) in &markets {
+        for (option, (bids, asks)) in &gauges.books {
+            if let (Some(bid), Some(ask)) = (bids.keys().next_back(), asks.keys().next()) {
+                writeln!(
+                    out,
+                    "probo_book_spread{{market=\"{}\",outcome=\"{}\"}} {}",
+                    market,
+                    outcome_label(*option),
+                    (*ask as f64 - *bid as f64) / 100.0
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    header(
+        &mut out,
+        "probo_journal_seq",
+        "gauge",
+        "Last journal entry applied to a market.",
+    );
+    for (market, gauges) in &markets {
+        writeln!(
+            out,
+            "probo_journal_seq{{market=\"{}\"}} {}",
+            market, gauges.journal_seq
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "probo_journal_lag",
+        "gauge",
+        "Journal entries since the last snapshot, what a restart would replay.",
+    );
+    for (market, gauges) in &markets {
+        writeln!(
+            out,
+            "probo_journal_lag{{market=\"{}\"}} {}",
+            market, gauges.journal_lag
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "probo_shard_queue_length",
+        "gauge",
+        "Requests waiting for an engine task.",
+    );
+    for (shard, queued) in engine.queue_lengths().await.iter().enumerate() {
+        writeln!(
+            out,
+            "probo_shard_queue_length{{shard=\"{}\"}} {}",
+            shard, queued
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "probo_event_queue_length",
+        "gauge",
+        "Engine events not yet taken by the slowest subscriber.",
+    );
+    writeln!(out, "probo_event_queue_length {}", engine.queued_events()).unwrap();
+    out
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{OptionType, OrderType};
+
+// a winning share pays out the full 10.0, a losing one pays nothing
+pub const SETTLEMENT_PAYOUT: f64 = 10.0;
+
+//position of one user in one outcome of a market
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Position {
+    pub quantity: i64, // negative when the user sold more than they hold
+    pub avg_cost: f64,
+    pub realized_pnl: f64,
+}
+
+impl Position {
+    fn apply_fill(&mut self, side: &OrderType, price: f64, quantity: u32) {
+        let delta = match side {
+            OrderType::Buy => quantity as i64,
+            OrderType::Sell => -(quantity as i64),
+        };
+
+        if self.quantity == 0 || self.quantity.signum() == delta.signum() {
+            //opening or adding to the position, blend the cost basis
+            let held = self.quantity.abs() as f64;
+            let added = delta.abs() as f64;
+            self.avg_cost = (self.avg_cost * held + price * added) / (held + added);
+            self.quantity += delta;
+            return;
+        }
+
+        //reducing the position, realize pnl on the closed part
+        let closed = delta.abs().min(self.quantity.abs());
+        self.realized_pnl +=
+            (price - self.avg_cost) * closed as f64 * self.quantity.signum() as f64;
+        self.quantity += delta;
+        if self.quantity == 0 {
+            self.avg_cost = 0.0;
+        } else if self.quantity.signum() == delta.signum() {
+            // flipped sides, the leftover is opened at the fill price
+            self.avg_cost = price;
+        }
+    }
+
+    fn settle(&mut self, payout: f64) {
+        self.realized_pnl += (payout - self.avg_cost) * self.quantity as f64;
+        self.quantity = 0;
+        self.avg_cost = 0.0;
+    }
+}
+
+//pnl view of a position returned by the portfolio query
+#[derive(Clone, Debug, Serialize)]
+pub struct PositionPnl {
+    pub market_id: u32,
+    pub option: OptionType,
+    pub quantity: i64,
+    pub avg_cost: f64,
+    pub realized_pnl: f64,
+    pub mark_price: Option<f64>, // mid of the book, None if one side is empty
+    pub unrealized_pnl: Option<f64>, // None when there is no mark price
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Portfolio {
+    positions: BTreeMap<u32, BTreeMap<OptionType, Position>>,
+}
+
+impl Portfolio {
+    pub fn new() -> Self {
+        Portfolio::default()
+    }
+
+    pub fn apply_fill(
+        &mut self,
+        user_id: u32,
+        option: OptionType,
+        side: &OrderType,
+        price: f64,
+        quantity: u32,
+    ) {
+        self.positions
+            .entry(user_id)
+            .or_default()
+            .entry(option)
+            .or_default()
+            .apply_fill(side, price, quantity);
+    }
+
+    //close every position at the settlement payout once the market resolves
+    pub fn settle(&mut self, outcome: OptionType) {
+        for positions in self.positions.values_mut() {
+            for (option, position) in positions.iter_mut() {
+                let payout = if *option == outcome {
+                    SETTLEMENT_PAYOUT
+                } else {
+                    0.0
+                };
+                position.settle(payout);
+            }
+        }
+    }
+
+    //yes shares minus no shares, the direction the user is exposed to in this market
+    pub fn net_position(&self, user_id: u32) -> i64 {
+        self.positions(user_id)
+            .map(|(option, position)| match option {
+                OptionType::Yes => position.quantity,
+                OptionType::No => -position.quantity,
+            })
+            .sum()
+    }
+
+    //everything each user made or lost in this market so far, complete once it is settled
+    pub fn realized_pnl_by_user(&self) -> Vec<(u32, f64)> {
+        let mut pnl: Vec<(u32, f64)> = self
+            .positions
+            .iter()
+            .map(|(user_id, positions)| {
+                let realized = positions.values().map(|p| p.realized_pnl).sum();
+                (*user_id, realized)
+            })
+            .collect();
+        pnl.sort_by_key(|(user_id, _)| *user_id);
+        pnl
+    }
+
+    //shares of the outcome held long across all users
+    pub fn open_interest(&self, option: OptionType) -> u64 {
+        self.positions
+            .values()
+            .filter_map(|positions| positions.get(&option))
+            .map(|position| position.quantity.max(0) as u64)
+            .sum()
+    }
+
+    pub fn positions(&self, user_id: u32) -> impl Iterator<Item = (&OptionType, &Position)> {
+        self.positions.get(&user_id).into_iter().flatten()
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use deadpool_redis::{Pool, redis::AsyncCommands};
+use log::warn;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    OptionType, OrderType, Trade,
+    events::{EngineEvent, EventEnvelope},
+    redis_worker::{RETRY_DELAY, WorkerError},
+    router::EngineRouter,
+    service::{BookSnapshot, EngineApi, ServiceError},
+};
+
+pub fn trades_channel(market_id: u32) -> String {
+    format!("probo:market:{}:trades", market_id)
+}
+
+pub fn ticker_channel(market_id: u32) -> String {
+    format!("probo:market:{}:ticker", market_id)
+}
+
+pub fn depth_channel(market_id: u32) -> String {
+    format!("probo:market:{}:depth", market_id)
+}
+
+// hash holding the latest ticker of every outcome, for clients that connect late
+pub fn ticker_key(market_id: u32) -> String {
+    format!("probo:ticker:{}", market_id)
+}
+
+//aggregated quantity at one price level after a command, 0 means the level is gone
+#[derive(Clone, Debug, Serialize)]
+pub struct DepthChange {
+    pub option: OptionType,
+    pub side: OrderType,
+    pub price: f64,
+    pub quantity: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Ticker {
+    pub market_id: u32,
+    pub option: OptionType,
+    pub last_price: Option<f64>,
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+}
+
+// price in cents -> aggregated quantity, as returned by get_order_book
+pub type Levels = BTreeMap<u64, u32>;
+
+//aggregated books of both outcomes
+pub struct DepthSnapshot(Vec<(OptionType, Levels, Levels)>);
+
+impl DepthSnapshot {
+    //option, bids and asks of each outcome
+    pub fn books(&self) -> impl Iterator<Item = &(OptionType, Levels, Levels)> {
+        self.0.iter()
+    }
+
+    pub fn changes_since(&self, before: &DepthSnapshot) -> Vec<DepthChange> {
+        let mut changes = Vec::new();
+        for ((option, bids, asks), (_, old_bids, old_asks)) in self.0.iter().zip(&before.0) {
+            for (side, new, old) in [
+                (OrderType::Buy, bids, old_bids),
+                (OrderType::Sell, asks, old_asks),
+            ] {
+                let prices = new.keys().chain(old.keys()).collect::<BTreeSet<_>>();
+                for price in prices {
+                    let quantity = new.get(price).copied().unwrap_or(0);
+                    if old.get(price).copied().unwrap_or(0) != quantity {
+                        changes.push(DepthChange {
+                            option: *option,
+                            side: side.clone(),
+                            price: *price as f64 / 100.0,
+                            quantity,
+                        });
+                    }
+                }
+            }
+        }
+        changes
+    }
+
+    //best bid and ask of an outcome
+    pub fn best(&self, option: OptionType) -> (Option<f64>, Option<f64>) {
+        let (_, bids, asks) = self.book(option);
+        (
+            bids.keys().next_back().map(|p| *p as f64 / 100.0),
+            asks.keys().next().map(|p| *p as f64 / 100.0),
+        )
+    }
+
+    fn book(&self, option: OptionType) -> &(OptionType, Levels, Levels) {
+        self.0
+            .iter()
+            .find(|(o, _, _)| *o == option)
+            .expect("both outcomes are in the snapshot")
+    }
+
+    //sets a level to its new aggregate, the change unless the level already was at it
+    fn apply(
+        &mut self,
+        option: OptionType,
+        side: &OrderType,
+        price: f64,
+        quantity: u32,
+    ) -> Option<DepthChange> {
+        let (_, bids, asks) = self
+            .0
+            .iter_mut()
+            .find(|(o, _, _)| *o == option)
+            .expect("both outcomes are in the snapshot");
+        let levels = match side {
+            OrderType::Buy => bids,
+            OrderType::Sell => asks,
+        };
+        let cents = (price * 100.0).round() as u64;
+        let before = match quantity {
+            0 => levels.remove(&cents),
+            _ => levels.insert(cents, quantity),
+        };
+        (before.unwrap_or(0) != quantity).then(|| DepthChange {
+            option,
+            side: side.clone(),
+            price,
+            quantity,
+        })
+    }
+}
+
+impl From<BookSnapshot> for DepthSnapshot {
+    fn from(snapshot: BookSnapshot) -> Self {
+        let (yes_bids, yes_asks) = snapshot.yes;
+        let (no_bids, no_asks) = snapshot.no;
+        DepthSnapshot(vec![
+            (OptionType::Yes, yes_bids, yes_asks),
+            (OptionType::No, no_bids, no_asks),
+        ])
+    }
+}
+
+//one market as its subscribers have been told about it, kept current from the level 2 deltas
+//of the engine. a delta at or below book_seq is already in the depth, one further ahead than
+//the next means some went missing and the depth has to come from a new snapshot
+pub struct MarketState {
+    depth: DepthSnapshot,
+    book_seq: u64,
+    last_price: HashMap<OptionType, f64>,
+    changes: Vec<DepthChange>, // applied since they were last taken
+    traded: bool,              // since the last ticker went out
+    stale: bool,
+}
+
+impl MarketState {
+    pub async fn fetch(engine: &impl EngineApi, market_id: u32) -> Result<Self, ServiceError> {
+        let snapshot = engine.book_snapshot(market_id).await?;
+        Ok(MarketState {
+            book_seq: snapshot.book_seq,
+            depth: snapshot.into(),
+            last_price: HashMap::new(),
+            changes: Vec::new(),
+            traded: false,
+            stale: false,
+        })
+    }
+
+    pub fn apply(&mut self, event: &EngineEvent) {
+        match event {
+            EngineEvent::Trade { trade } => {
+                self.last_price.extend(trade.legs());
+                self.traded = true;
+            }
+            EngineEvent::BookDelta {
+                book_seq,
+                option,
+                side,
+                price,
+                quantity,
+            } => {
+                if self.stale || *book_seq <= self.book_seq {
+                    return;
+                }
+                if *book_seq != self.book_seq + 1 {
+                    self.stale = true;
+                    return;
+                }
+                self.book_seq = *book_seq;
+                if let Some(change) = self.depth.apply(*option, side, *price, *quantity) {
+                    self.changes.push(change);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    //deltas were lost on the way here
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    //catches up from a new snapshot, whatever differs from the old depth counts as changed
+    pub fn resync(&mut self, snapshot: BookSnapshot) {
+        self.book_seq = snapshot.book_seq;
+        let depth = DepthSnapshot::from(snapshot);
+        self.changes.extend(depth.changes_since(&self.depth));
+        self.depth = depth;
+        self.stale = false;
+    }
+
+    pub fn depth(&self) -> &DepthSnapshot {
+        &self.depth
+    }
+
+    pub fn book_seq(&self) -> u64 {
+        self.book_seq
+    }
+
+    //level changes since the last call and whether the tickers need to go out again
+    pub fn take_update(&mut self) -> (Vec<DepthChange>, bool) {
+        let changes = std::mem::take(&mut self.changes);
+        let tickers = !changes.is_empty() || self.traded;
+        self.traded = false;
+        (changes, tickers)
+    }
+
+    //an update that could not be sent goes out with the next one, tickers included
+    pub fn put_back(&mut self, mut changes: Vec<DepthChange>) {
+        changes.append(&mut self.changes);
+        self.changes = changes;
+        self.traded = true;
+    }
+
+    pub fn tickers(&self, market_id: u32) -> Vec<Ticker> {
+        [OptionType::Yes, OptionType::No]
+            .into_iter()
+            .map(|option| {
+                let (best_bid, best_ask) = self.depth.best(option);
+                Ticker {
+                    market_id,
+                    option,
+                    last_price: self.last_price.get(&option).copied(),
+                    best_bid,
+                    best_ask,
+                }
+            })
+            .collect()
+    }
+}
+
+//follows the engine events and pushes market data to per market redis channels for the websocket gateway
+pub struct MarketPublisher {
+    pool: Pool,
+    engine: Arc<EngineRouter>,
+    markets: HashMap<u32, MarketState>,
+}
+
+impl MarketPublisher {
+    pub fn new(pool: Pool, engine: Arc<EngineRouter>) -> Self {
+        MarketPublisher {
+            pool,
+            engine,
+            markets: HashMap::new(),
+        }
+    }
+
+    //a redis error never stops the publisher, the market it hit is resynced and published again
+    //once redis is back, while the worker carries on matching
+    pub async fn run(mut self, mut events: broadcast::Receiver<EventEnvelope>) {
+        let mut dirty = BTreeSet::new();
+        loop {
+            // markets left dirty by a failed publish are retried without waiting for an event
+            if dirty.is_empty() || !events.is_empty() {
+                match events.recv().await {
+                    Ok(envelope) => self.apply(&envelope, &mut dirty).await,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("market publisher missed {} events, resyncing", missed);
+                        for (market_id, market) in self.markets.iter_mut() {
+                            market.mark_stale();
+                            dirty.insert(*market_id);
+                        }
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+            // book and ticker only once the burst of events of a command is through
+            if events.is_empty() {
+                for market_id in std::mem::take(&mut dirty) {
+                    if let Err(e) = self.publish_book(market_id).await {
+                        warn!(
+                            "could not publish market {}, retrying in {:?}: {}",
+                            market_id, RETRY_DELAY, e
+                        );
+                        if let Some(market) = self.markets.get_mut(&market_id) {
+                            market.mark_stale();
+                        }
+                        dirty.insert(market_id);
+                    }
+                }
+                if !dirty.is_empty() {
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+
+    async fn apply(&mut self, envelope: &EventEnvelope, dirty: &mut BTreeSet<u32>) {
+        let market_id = envelope.market_id;
+        dirty.insert(market_id);
+        if let EngineEvent::Trade { trade } = &envelope.event {
+            // trades are not in any snapshot, one that is not published now never is
+            while let Err(e) = self.publish_trade(market_id, trade).await {
+                warn!(
+                    "could not publish a trade of market {}, retrying in {:?}: {}",
+                    market_id, RETRY_DELAY, e
+                );
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+        // a market seen for the first time starts from a snapshot that already
+        // has this event in it
+        if !self.markets.contains_key(&market_id) {
+            match MarketState::fetch(&*self.engine, market_id).await {
+                Ok(market) => {
+                    self.markets.insert(market_id, market);
+                }
+                // fetched again with its next event
+                Err(e) => warn!("could not snapshot market {}: {}", market_id, e),
+            }
+        }
+        if let Some(market) = self.markets.get_mut(&market_id) {
+            market.apply(&envelope.event);
+        }
+    }
+
+    async fn publish_trade(&self, market_id: u32, trade: &Trade) -> Result<(), WorkerError> {
+        let mut conn = self.pool.get().await?;
+        let _: i64 = conn
+            .publish(trades_channel(market_id), to_json(trade))
+            .await?;
+        Ok(())
+    }
+
+    async fn publish_book(&mut self, market_id: u32) -> Result<(), WorkerError> {
+        let Some(market) = self.markets.get_mut(&market_id) else {
+            return Ok(());
+        };
+        if market.is_stale() {
+            market.resync(self.engine.book_snapshot(market_id).await?);
+        }
+        let (depth_changes, tickers) = market.take_update();
+        if !tickers {
+            return Ok(());
+        }
+        let tickers = market.tickers(market_id);
+        let sent = send_book(&self.pool, market_id, &depth_changes, &tickers).await;
+        if sent.is_err() {
+            market.put_back(depth_changes);
+        }
+        sent
+    }
+}
+
+async fn send_book(
+    pool: &Pool,
+    market_id: u32,
+    depth_changes: &[DepthChange],
+    tickers: &[Ticker],
+) -> Result<(), WorkerError> {
+    let mut conn = pool.get().await?;
+    if !depth_changes.is_empty() {
+        let _: i64 = conn
+            .publish(depth_channel(market_id), to_json(&depth_changes))
+            .await?;
+    }
+    for ticker in tickers {
+        let body = to_json(ticker);
+        let _: () = conn
+            .hset(ticker_key(market_id), format!("{:?}", ticker.option), &body)
+            .await?;
+        let _: i64 = conn.publish(ticker_channel(market_id), &body).await?;
+    }
+    Ok(())
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("market data serializes")
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+// full buckets are dropped this often, a full bucket is no different from a missing one
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum RequestKind {
+    NewOrder,
+    Cancel,
+    Query,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Deserialize)]
+pub enum AccountClass {
+    #[default]
+    Retail,
+    MarketMaker,
+}
+
+#[derive(Debug, Error)]
+pub enum RateLimitError {
+    #[error("{class:?} {kind:?} limit needs a burst of at least 1 and a positive refill rate")]
+    InvalidLimit {
+        class: AccountClass,
+        kind: RequestKind,
+    },
+}
+
+//bucket size and how fast it fills back up
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct BucketLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl BucketLimit {
+    fn is_valid(&self) -> bool {
+        self.burst >= 1 && self.per_second.is_finite() && self.per_second > 0.0
+    }
+}
+
+//limits of one account class, separate bucket for every request kind
+#[derive(Clone, Debug, Deserialize)]
+pub struct ClassLimits {
+    pub new_orders: BucketLimit,
+    pub cancels: BucketLimit,
+    pub queries: BucketLimit,
+}
+
+impl ClassLimits {
+    fn for_kind(&self, kind: RequestKind) -> BucketLimit {
+        match kind {
+            RequestKind::NewOrder => self.new_orders,
+            RequestKind::Cancel => self.cancels,
+            RequestKind::Query => self.queries,
+        }
+    }
+
+    fn validate(&self, class: AccountClass) -> Result<(), RateLimitError> {
+        for kind in [
+            RequestKind::NewOrder,
+            RequestKind::Cancel,
+            RequestKind::Query,
+        ] {
+            if !self.for_kind(kind).is_valid() {
+                return Err(RateLimitError::InvalidLimit { class, kind });
+            }
+        }
+        Ok(())
+    }
+}
+
+//what --rate-limits reads, classes left out keep their defaults
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub classes: HashMap<AccountClass, ClassLimits>,
+    pub accounts: HashMap<u32, AccountClass>, // users not listed here are retail
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, limit: BucketLimit, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    fn take(&mut self, limit: BucketLimit, now: Instant) -> Result<(), Duration> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        //throttled, tell the client when the next token is available
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / limit.per_second,
+        ))
+    }
+}
+
+struct Limits {
+    classes: HashMap<AccountClass, ClassLimits>,
+    accounts: HashMap<u32, AccountClass>,
+    buckets: HashMap<(u32, RequestKind), TokenBucket>,
+    last_sweep: Instant,
+}
+
+impl Limits {
+    fn limit(&self, user_id: u32, kind: RequestKind) -> BucketLimit {
+        let class = self.accounts.get(&user_id).copied().unwrap_or_default();
+        self.classes[&class].for_kind(kind)
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        let mut buckets = std::mem::take(&mut self.buckets);
+        buckets.retain(|(user_id, kind), bucket| {
+            let limit = self.limit(*user_id, *kind);
+            bucket.refill(limit, now);
+            bucket.tokens < limit.burst as f64
+        });
+        self.buckets = buckets;
+        self.last_sweep = now;
+    }
+}
+
+//token buckets per user and request kind, one limiter is shared by every market of the
+//process so a user's requests count the same wherever they go
+pub struct RateLimiter {
+    limits: Mutex<Limits>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new()
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        let mut classes = HashMap::new();
+        classes.insert(
+            AccountClass::Retail,
+            ClassLimits {
+                new_orders: BucketLimit {
+                    burst: 20,
+                    per_second: 10.0,
+                },
+                cancels: BucketLimit {
+                    burst: 40,
+                    per_second: 20.0,
+                },
+                queries: BucketLimit {
+                    burst: 100,
+                    per_second: 50.0,
+                },
+            },
+        );
+        classes.insert(
+            AccountClass::MarketMaker,
+            ClassLimits {
+                new_orders: BucketLimit {
+                    burst: 500,
+                    per_second: 250.0,
+                },
+                cancels: BucketLimit {
+                    burst: 1_000,
+                    per_second: 500.0,
+                },
+                queries: BucketLimit {
+                    burst: 500,
+                    per_second: 250.0,
+                },
+            },
+        );
+        RateLimiter {
+            limits: Mutex::new(Limits {
+                classes,
+                accounts: HashMap::new(),
+                buckets: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    //the defaults with the configured classes and accounts on top
+    pub fn from_config(config: RateLimitConfig) -> Result<Self, RateLimitError> {
+        let limiter = RateLimiter::new();
+        for (class, limits) in config.classes {
+            limiter.set_class_limits(class, limits)?;
+        }
+        for (user_id, class) in config.accounts {
+            limiter.set_account_class(user_id, class);
+        }
+        Ok(limiter)
+    }
+
+    pub fn set_class_limits(
+        &self,
+        class: AccountClass,
+        limits: ClassLimits,
+    ) -> Result<(), RateLimitError> {
+        limits.validate(class)?;
+        self.limits.lock().unwrap().classes.insert(class, limits);
+        Ok(())
+    }
+
+    pub fn set_account_class(&self, user_id: u32, class: AccountClass) {
+        self.limits.lock().unwrap().accounts.insert(user_id, class);
+    }
+
+    //take one token for the request, Err carries the retry-after hint
+    pub fn check(&self, user_id: u32, kind: RequestKind, now: Instant) -> Result<(), Duration> {
+        let mut limits = self.limits.lock().unwrap();
+        if now.saturating_duration_since(limits.last_sweep) >= SWEEP_INTERVAL {
+            limits.sweep(now);
+        }
+        let limit = limits.limit(user_id, kind);
+        limits
+            .buckets
+            .entry((user_id, kind))
+            .or_insert(TokenBucket {
+                tokens: limit.burst as f64,
+                last_refill: now,
+            })
+            .take(limit, now)
+    }
+}
@@ -0,0 +1,190 @@
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use deadpool_redis::{
+    Config, Pool, PoolError, Runtime,
+    redis::{AsyncCommands, Direction, RedisError},
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    Order, Trade,
+    event_stream::{EventStream, RetentionPolicy},
+    journal::Command,
+    publisher::MarketPublisher,
+    router::EngineRouter,
+    service::{EngineApi, ServiceError},
+};
+
+pub const ORDER_QUEUE: &str = "probo:orders";
+pub const PROCESSING_QUEUE: &str = "probo:orders:processing";
+pub const RESPONSE_TTL_SECS: u64 = 3600;
+// wait before reconnecting after redis went away
+pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+pub fn response_key(request_id: &str) -> String {
+    format!("probo:response:{}", request_id)
+}
+
+#[derive(Debug, Error)]
+pub enum WorkerError {
+    #[error("redis error: {0}")]
+    Redis(#[from] RedisError),
+    #[error("redis pool error: {0}")]
+    Pool(#[from] PoolError),
+    #[error("could not create redis pool: {0}")]
+    CreatePool(#[from] deadpool_redis::CreatePoolError),
+    #[error(transparent)]
+    Service(#[from] ServiceError),
+    #[error("event stream fell behind and lost {0} events")]
+    Lagged(u64),
+}
+
+//what the api servers push onto the order queue
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommandRequest {
+    pub request_id: String,
+    pub market_id: u32,
+    pub client_order_id: Option<String>, // retries with the same id are applied only once
+    pub command: Command,
+}
+
+//written back under the request id
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum CommandResponse {
+    Accepted {
+        request_id: String,
+        order: Option<Order>,
+        trades: Vec<Trade>,
+    },
+    Rejected {
+        request_id: String,
+        reason: String,
+    },
+}
+
+//consumes commands from redis and hands them to the engine of their market one at a time
+pub struct RedisWorker {
+    pool: Pool,
+    engine: Arc<EngineRouter>,
+    retention: RetentionPolicy,
+}
+
+impl RedisWorker {
+    pub fn new(
+        redis_url: &str,
+        engine: Arc<EngineRouter>,
+        retention: RetentionPolicy,
+    ) -> Result<Self, WorkerError> {
+        let pool = Config::from_url(redis_url).create_pool(Some(Runtime::Tokio1))?;
+        Ok(RedisWorker {
+            pool,
+            engine,
+            retention,
+        })
+    }
+
+    //at-least-once: a request stays in the processing list until its response is written,
+    //whatever is left there after a crash is pushed back. the engine journals the client order
+    //id with the command, so a request that was applied before the crash is not applied again.
+    //losing redis only pauses the worker, it carries on once redis is back. the worker stops
+    //with the event stream, a stream with a gap in it is worse than no worker
+    pub async fn run(self) -> Result<(), WorkerError> {
+        let publisher = MarketPublisher::new(self.pool.clone(), self.engine.clone());
+        let events = self.engine.subscribe();
+        tokio::spawn(publisher.run(events));
+        let stream = EventStream::new(self.pool.clone(), self.retention);
+        let events = self.engine.subscribe();
+        let mut stream = tokio::spawn(stream.run(events));
+
+        loop {
+            let e = tokio::select! {
+                stopped = &mut stream => return stopped.expect("event stream panicked"),
+                result = self.consume() => {
+                    let Err(e) = result;
+                    e
+                }
+            };
+            match e {
+                WorkerError::Redis(_) | WorkerError::Pool(_) => {
+                    warn!("lost redis, retrying in {:?}: {}", RETRY_DELAY, e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+                e => return Err(e),
+            }
+        }
+    }
+
+    //requeues what an earlier run left unanswered, then takes requests until an error
+    async fn consume(&self) -> Result<Infallible, WorkerError> {
+        let mut conn = self.pool.get().await?;
+        // back onto the end requests are taken from, so they still go before anything newer.
+        // processing holds the newest first, moving that one first leaves the oldest last
+        loop {
+            let moved: Option<String> = conn
+                .lmove(
+                    PROCESSING_QUEUE,
+                    ORDER_QUEUE,
+                    Direction::Left,
+                    Direction::Right,
+                )
+                .await?;
+            if moved.is_none() {
+                break;
+            }
+        }
+
+        loop {
+            let raw: Option<String> = conn.brpoplpush(ORDER_QUEUE, PROCESSING_QUEUE, 5.0).await?;
+            let Some(raw) = raw else {
+                continue;
+            };
+            self.handle(&raw).await?;
+            let _: i64 = conn.lrem(PROCESSING_QUEUE, 1, &raw).await?;
+        }
+    }
+
+    async fn handle(&self, raw: &str) -> Result<(), WorkerError> {
+        let mut conn = self.pool.get().await?;
+        let request: CommandRequest = match serde_json::from_str(raw) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("dropping malformed request {}: {}", raw, e);
+                return Ok(());
+            }
+        };
+
+        let result = match request.client_order_id {
+            Some(client_order_id) => {
+                self.engine
+                    .submit_once(request.market_id, client_order_id, request.command)
+                    .await
+            }
+            None => self.engine.submit(request.market_id, request.command).await,
+        };
+        let response = match result {
+            Ok((order, trades)) => CommandResponse::Accepted {
+                request_id: request.request_id.clone(),
+                order,
+                trades,
+            },
+            Err(ServiceError::Rejected(reason)) => CommandResponse::Rejected {
+                request_id: request.request_id.clone(),
+                reason: reason.to_string(),
+            },
+            Err(e @ ServiceError::UnknownMarket(_)) => CommandResponse::Rejected {
+                request_id: request.request_id.clone(),
+                reason: e.to_string(),
+            },
+            // engine is gone, leave the request in the processing list for the next worker
+            Err(e) => return Err(e.into()),
+        };
+        let body = serde_json::to_string(&response).expect("response serializes");
+        let _: () = conn
+            .set_ex(response_key(&request.request_id), &body, RESPONSE_TTL_SECS)
+            .await?;
+        Ok(())
+    }
+}
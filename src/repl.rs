@@ -0,0 +1,423 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, BufRead, IsTerminal, Write},
+};
+
+use thiserror::Error;
+use tokio::runtime::Runtime;
+
+use crate::{
+    MatchingEngine, OptionType, Order, OrderType, Trade,
+    journal::{AdminCommand, Command},
+    portfolio::PositionPnl,
+    risk::RiskLimits,
+    router::EngineRouter,
+    service::{BookDepth, CommandResult, EngineApi, ServiceError},
+};
+
+const HELP: &str = "\
+buy|sell yes|no <price> <qty> [user=<id>]   place a limit order
+amend <order id> <price> <qty> [user=<id>]  change price and open quantity
+cancel <order id> [user=<id>]               cancel an order
+orders [user=<id>]                          open orders of a user
+portfolio [user=<id>]                       positions of a user with their pnl
+book yes|no                                 depth of a book
+price yes|no                                best bid and ask
+resolve yes|no                              settle the market
+limits [quantity= notional= open_orders= net_position= deviation=]
+                                            set risk limits, the rest at their defaults
+markets                                     markets and the shard they run on
+move <market id> <shard>                    move a market onto another shard
+market <id>                                 switch market, add it if it is new
+user <id>                                   who orders are for when user= is left out
+help, quit
+every command takes market=<id> to act on another market than the current one";
+
+#[derive(Debug, Error)]
+pub enum ReplError {
+    #[error("{0}, try help")]
+    Usage(String),
+    #[error(transparent)]
+    Engine(#[from] ServiceError),
+}
+
+fn usage(message: impl Into<String>) -> ReplError {
+    ReplError::Usage(message.into())
+}
+
+//words of a command line, key=value options taken out
+struct Line<'a> {
+    words: Vec<&'a str>,
+    options: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Line<'a> {
+    fn parse(line: &'a str) -> Self {
+        let (options, words): (Vec<_>, Vec<_>) =
+            line.split_whitespace().partition(|w| w.contains('='));
+        Line {
+            words,
+            options: options.iter().filter_map(|o| o.split_once('=')).collect(),
+        }
+    }
+
+    fn word<T: std::str::FromStr>(&self, i: usize, what: &str) -> Result<T, ReplError> {
+        let word = self
+            .words
+            .get(i)
+            .ok_or_else(|| usage(format!("missing {}", what)))?;
+        word.parse()
+            .map_err(|_| usage(format!("{} is not a valid {}", word, what)))
+    }
+
+    fn option<T: std::str::FromStr>(&self, key: &str) -> Result<Option<T>, ReplError> {
+        self.options
+            .get(key)
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| usage(format!("invalid {}={}", key, v)))
+            })
+            .transpose()
+    }
+
+    fn outcome(&self, i: usize) -> Result<OptionType, ReplError> {
+        match self.words.get(i).map(|w| w.to_ascii_lowercase()).as_deref() {
+            Some("yes") => Ok(OptionType::Yes),
+            Some("no") => Ok(OptionType::No),
+            _ => Err(usage("expected yes or no")),
+        }
+    }
+}
+
+//operator console over a router running in this process, one command per line
+pub struct Repl {
+    runtime: Runtime,
+    engine: EngineRouter,
+    market_id: u32,
+    user_id: Option<u32>,
+    owners: HashMap<(u32, u64), u32>, // orders placed from here -> user
+}
+
+impl Repl {
+    pub fn new(runtime: Runtime, engine: EngineRouter, market_id: u32) -> Self {
+        Repl {
+            runtime,
+            engine,
+            market_id,
+            user_id: None,
+            owners: HashMap::new(),
+        }
+    }
+
+    pub fn run(&mut self, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+        // no prompt when the commands come from a pipe
+        let interactive = io::stdin().is_terminal();
+        if interactive {
+            writeln!(out, "probo engine console, help lists the commands")?;
+        }
+        let mut lines = input.lines();
+        loop {
+            if interactive {
+                write!(out, "market {}> ", self.market_id)?;
+                out.flush()?;
+            }
+            let Some(line) = lines.next() else {
+                return Ok(());
+            };
+            let line = line?;
+            match self.execute(&line) {
+                Ok(Some(reply)) => write!(out, "{}", reply)?,
+                Ok(None) => return Ok(()),
+                Err(e) => writeln!(out, "error: {}", e)?,
+            }
+        }
+    }
+
+    //what to print, None to quit
+    pub fn execute(&mut self, line: &str) -> Result<Option<String>, ReplError> {
+        let line = Line::parse(line);
+        let Some(command) = line.words.first() else {
+            return Ok(Some(String::new()));
+        };
+        let market_id = line.option("market")?.unwrap_or(self.market_id);
+        let reply = match command.to_ascii_lowercase().as_str() {
+            "buy" | "sell" => {
+                let order_type = if command.eq_ignore_ascii_case("buy") {
+                    OrderType::Buy
+                } else {
+                    OrderType::Sell
+                };
+                let user_id = self.user(&line)?;
+                let command = Command::PlaceOrder {
+                    user_id,
+                    option: line.outcome(1)?,
+                    order_type,
+                    price: line.word(2, "price")?,
+                    quantity: line.word(3, "quantity")?,
+                };
+                let result = self.submit(market_id, command);
+                if let Ok((Some(order), _)) = &result {
+                    self.owners.insert((market_id, order.id), user_id);
+                }
+                result_text(result?)
+            }
+            "amend" => {
+                let order_id = line.word(1, "order id")?;
+                let command = Command::AmendOrder {
+                    user_id: self.owner(&line, market_id, order_id)?,
+                    order_id,
+                    price: line.word(2, "price")?,
+                    quantity: line.word(3, "quantity")?,
+                };
+                result_text(self.submit(market_id, command)?)
+            }
+            "cancel" => {
+                let order_id = line.word(1, "order id")?;
+                let user_id = self.owner(&line, market_id, order_id)?;
+                let command = Command::CancelOrder { user_id, order_id };
+                let (order, _) = self.submit(market_id, command)?;
+                let order = order.expect("a cancel hands back the cancelled order");
+                self.owners.remove(&(market_id, order_id));
+                format!("cancelled {}\n", order_text(&order))
+            }
+            "orders" => {
+                let user_id = self.user(&line)?;
+                let orders = self
+                    .runtime
+                    .block_on(self.engine.open_orders(market_id, user_id))?;
+                if orders.is_empty() {
+                    format!("user {} has no open orders\n", user_id)
+                } else {
+                    orders.iter().map(|o| order_text(o) + "\n").collect()
+                }
+            }
+            "portfolio" => {
+                let user_id = self.user(&line)?;
+                let positions = self
+                    .runtime
+                    .block_on(self.engine.portfolio(market_id, user_id))?;
+                if positions.is_empty() {
+                    format!("user {} has no positions\n", user_id)
+                } else {
+                    positions
+                        .iter()
+                        .map(|p| format!("user {} {}\n", user_id, position_text(p)))
+                        .collect()
+                }
+            }
+            "book" => {
+                let option = line.outcome(1)?;
+                let depth = self
+                    .runtime
+                    .block_on(self.engine.order_book(market_id, option))?;
+                book_text(market_id, option, &depth)
+            }
+            "price" => {
+                let option = line.outcome(1)?;
+                let (bid, ask) = self
+                    .runtime
+                    .block_on(self.engine.market_price(market_id, option))?;
+                format!(
+                    "{:?} bid {} ask {}\n",
+                    option,
+                    price_text(bid),
+                    price_text(ask)
+                )
+            }
+            "resolve" => {
+                let outcome = line.outcome(1)?;
+                self.submit(
+                    market_id,
+                    Command::Admin(AdminCommand::ResolveMarket { outcome }),
+                )?;
+                format!("market {} resolved {:?}\n", market_id, outcome)
+            }
+            "limits" => {
+                let mut limits = RiskLimits::default();
+                if let Some(quantity) = line.option("quantity")? {
+                    limits.max_order_quantity = quantity;
+                }
+                if let Some(notional) = line.option("notional")? {
+                    limits.max_order_notional = notional;
+                }
+                if let Some(open_orders) = line.option("open_orders")? {
+                    limits.max_open_orders_per_user = open_orders;
+                }
+                if let Some(net_position) = line.option("net_position")? {
+                    limits.max_net_position = net_position;
+                }
+                if let Some(deviation) = line.option("deviation")? {
+                    limits.max_price_deviation = deviation;
+                }
+                let text = format!(
+                    "market {} limits: quantity {}, notional {:.2}, open orders {}, net position {}, deviation {:.2}\n",
+                    market_id,
+                    limits.max_order_quantity,
+                    limits.max_order_notional,
+                    limits.max_open_orders_per_user,
+                    limits.max_net_position,
+                    limits.max_price_deviation
+                );
+                self.submit(
+                    market_id,
+                    Command::Admin(AdminCommand::SetRiskLimits(limits)),
+                )?;
+                text
+            }
+            "markets" => {
+                let markets = self.runtime.block_on(self.engine.markets());
+                markets
+                    .iter()
+                    .map(|(id, shard)| {
+                        let current = if *id == self.market_id { " *" } else { "" };
+                        format!("market {} on shard {}{}\n", id, shard, current)
+                    })
+                    .collect()
+            }
+            "move" => {
+                let id: u32 = line.word(1, "market id")?;
+                let shard: usize = line.word(2, "shard")?;
+                self.runtime.block_on(self.engine.move_market(id, shard))?;
+                format!("moved market {} to shard {}\n", id, shard)
+            }
+            "market" => {
+                let id: u32 = line.word(1, "market id")?;
+                let markets = self.runtime.block_on(self.engine.markets());
+                let reply = if markets.iter().any(|(market, _)| *market == id) {
+                    String::new()
+                } else {
+                    let shard = self
+                        .runtime
+                        .block_on(self.engine.add_market(MatchingEngine::new(id)))?;
+                    format!("added market {} on shard {}\n", id, shard)
+                };
+                self.market_id = id;
+                reply
+            }
+            "user" => {
+                self.user_id = Some(line.word(1, "user id")?);
+                String::new()
+            }
+            "help" => format!("{}\n", HELP),
+            "quit" | "exit" => return Ok(None),
+            other => return Err(usage(format!("unknown command {}", other))),
+        };
+        Ok(Some(reply))
+    }
+
+    pub fn market_ids(&self) -> Vec<u32> {
+        let markets = self.runtime.block_on(self.engine.markets());
+        markets
+            .into_iter()
+            .map(|(market_id, _)| market_id)
+            .collect()
+    }
+
+    fn submit(&self, market_id: u32, command: Command) -> CommandResult {
+        self.runtime
+            .block_on(self.engine.submit(market_id, command))
+    }
+
+    fn user(&self, line: &Line) -> Result<u32, ReplError> {
+        line.option("user")?
+            .or(self.user_id)
+            .ok_or_else(|| usage("say who it is for with user=<id>"))
+    }
+
+    //orders placed from this console are known, anyone else's need user=
+    fn owner(&self, line: &Line, market_id: u32, order_id: u64) -> Result<u32, ReplError> {
+        match line.option("user")? {
+            Some(user_id) => Ok(user_id),
+            None => self
+                .owners
+                .get(&(market_id, order_id))
+                .copied()
+                .ok_or_else(|| {
+                    usage(format!(
+                        "order {} was not placed here, say whose it is with user=<id>",
+                        order_id
+                    ))
+                }),
+        }
+    }
+}
+
+fn price_text(price: Option<f64>) -> String {
+    price.map_or("-".to_string(), |p| format!("{:.2}", p))
+}
+
+fn order_text(order: &Order) -> String {
+    format!(
+        "order {}: user {} {:?} {} {:?} at {:.2}",
+        order.id, order.user_id, order.order_type, order.quantity, order.option, order.price
+    )
+}
+
+fn position_text(position: &PositionPnl) -> String {
+    format!(
+        "{:?} {} at avg {:.2}, realized {:.2}, unrealized {} (mark {})",
+        position.option,
+        position.quantity,
+        position.avg_cost,
+        position.realized_pnl,
+        price_text(position.unrealized_pnl),
+        price_text(position.mark_price)
+    )
+}
+
+fn trade_text(trade: &Trade) -> String {
+    format!(
+        "trade {} ({:?}): user {} {:?} {} {:?} at {:.2} against user {} ({:?} at {:.2})",
+        trade.id,
+        trade.match_type,
+        trade.taker_user_id,
+        trade.aggressor_side,
+        trade.quantity,
+        trade.option,
+        trade.price,
+        trade.maker_user_id,
+        trade.maker_option,
+        trade.maker_price
+    )
+}
+
+fn result_text((order, trades): (Option<Order>, Vec<Trade>)) -> String {
+    let mut text = String::new();
+    for trade in &trades {
+        writeln!(text, "{}", trade_text(trade)).unwrap();
+    }
+    match order {
+        Some(order) if order.quantity > 0 => writeln!(text, "{} open", order_text(&order)),
+        Some(order) => writeln!(text, "order {} filled", order.id),
+        None => Ok(()),
+    }
+    .unwrap();
+    text
+}
+
+//bids and asks side by side, best prices on the first row
+fn book_text(market_id: u32, option: OptionType, (bids, asks): &BookDepth) -> String {
+    let mut text = format!(
+        "market {} {:?}\n{:>8} {:>8} | {:<8} qty\n",
+        market_id, option, "qty", "bid", "ask"
+    );
+    let mut bids = bids.iter().rev();
+    let mut asks = asks.iter();
+    loop {
+        let level = |level: Option<(&u64, &u32)>| {
+            level.map(|(price, qty)| (format!("{:.2}", *price as f64 / 100.0), qty.to_string()))
+        };
+        let (bid, ask) = (level(bids.next()), level(asks.next()));
+        if bid.is_none() && ask.is_none() {
+            return text;
+        }
+        let (bid_price, bid_qty) = bid.unwrap_or_default();
+        let (ask_price, ask_qty) = ask.unwrap_or_default();
+        let row = format!(
+            "{:>8} {:>8} | {:<8} {:<8}",
+            bid_qty, bid_price, ask_price, ask_qty
+        );
+        writeln!(text, "{}", row.trim_end()).unwrap();
+    }
+}
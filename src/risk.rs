@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{OptionType, OrderType};
+
+//why place_order refused an order, every pre-trade check has its own reason
+#[derive(Clone, Debug, PartialEq, Error, Serialize)]
+pub enum RejectReason {
+    #[error("market is not open for trading")]
+    MarketClosed,
+    #[error("price {0} is outside the 0.5 - 9.5 range")]
+    PriceOutOfRange(f64),
+    #[error("quantity must be greater than zero")]
+    ZeroQuantity,
+    #[error("quantity {quantity} exceeds the max order quantity {limit}")]
+    MaxOrderQuantity { quantity: u32, limit: u32 },
+    #[error("notional {notional} exceeds the max order notional {limit}")]
+    MaxOrderNotional { notional: f64, limit: f64 },
+    #[error("user already has {limit} open orders")]
+    MaxOpenOrders { limit: usize },
+    #[error("net position would be {projected}, limit is {limit}")]
+    MaxNetPosition { projected: i64, limit: i64 },
+    #[error("price {price} is more than {max_deviation} away from last trade {last_trade}")]
+    PriceDeviation {
+        price: f64,
+        last_trade: f64,
+        max_deviation: f64,
+    },
+    #[error("rate limited, retry after {retry_after:?}")]
+    RateLimited { retry_after: Duration },
+    #[error("order {0} is not open")]
+    UnknownOrder(u64),
+    #[error("order {0} belongs to another user")]
+    NotOrderOwner(u64),
+    #[error("could not write the command journal: {0}")]
+    JournalWrite(String),
+    #[error("order needs {required} of balance, only {available} is available")]
+    InsufficientBalance { required: f64, available: f64 },
+}
+
+//configurable limits checked in place_order before matching
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RiskLimits {
+    pub max_order_quantity: u32,
+    pub max_order_notional: f64, // price * quantity
+    pub max_open_orders_per_user: usize,
+    pub max_net_position: i64, // yes shares minus no shares held in one market
+    pub max_price_deviation: f64, // fat finger band around the last trade
+}
+
+impl Default for RiskLimits {
+    fn default() -> Self {
+        RiskLimits {
+            max_order_quantity: 10_000,
+            max_order_notional: 50_000.0,
+            max_open_orders_per_user: 100,
+            max_net_position: 100_000,
+            max_price_deviation: 3.0,
+        }
+    }
+}
+
+//state of the user and market the checks run against
+pub struct RiskContext {
+    pub open_orders: usize,
+    pub net_position: i64,
+    pub last_trade_price: Option<f64>,
+}
+
+impl RiskLimits {
+    pub fn check(
+        &self,
+        option: OptionType,
+        order_type: &OrderType,
+        price: f64,
+        quantity: u32,
+        ctx: &RiskContext,
+    ) -> Result<(), RejectReason> {
+        if quantity > self.max_order_quantity {
+            return Err(RejectReason::MaxOrderQuantity {
+                quantity,
+                limit: self.max_order_quantity,
+            });
+        }
+
+        let notional = price * quantity as f64;
+        if notional > self.max_order_notional {
+            return Err(RejectReason::MaxOrderNotional {
+                notional,
+                limit: self.max_order_notional,
+            });
+        }
+
+        if ctx.open_orders >= self.max_open_orders_per_user {
+            return Err(RejectReason::MaxOpenOrders {
+                limit: self.max_open_orders_per_user,
+            });
+        }
+
+        // buying yes or selling no both move the net position towards yes
+        let delta = match (option, order_type) {
+            (OptionType::Yes, OrderType::Buy) | (OptionType::No, OrderType::Sell) => {
+                quantity as i64
+            }
+            (OptionType::Yes, OrderType::Sell) | (OptionType::No, OrderType::Buy) => {
+                -(quantity as i64)
+            }
+        };
+        let projected = ctx.net_position + delta;
+        if projected.abs() > self.max_net_position {
+            return Err(RejectReason::MaxNetPosition {
+                projected,
+                limit: self.max_net_position,
+            });
+        }
+
+        if let Some(last_trade) = ctx.last_trade_price
+            && (price - last_trade).abs() > self.max_price_deviation
+        {
+            return Err(RejectReason::PriceDeviation {
+                price,
+                last_trade,
+                max_deviation: self.max_price_deviation,
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use log::error;
+use tokio::sync::{RwLock, broadcast, oneshot};
+
+use crate::{
+    MatchingEngine, OptionType, Order,
+    audit::AuditLog,
+    events::EventEnvelope,
+    journal::Command,
+    ledger::AccountLedger,
+    metrics::{MarketGauges, Metrics},
+    portfolio::PositionPnl,
+    rate_limit::RateLimiter,
+    service::{
+        self, BestPrices, BookDepth, BookQueue, BookSnapshot, CommandResult, EngineApi,
+        EngineHandle, EngineRequest, ServiceError,
+    },
+    stats::MarketTicker,
+};
+
+const EVENT_BUFFER: usize = 100_000;
+
+struct Routing {
+    shards: Vec<EngineHandle>,
+    markets: HashMap<u32, usize>, // market id -> shard
+}
+
+//owns the engine tasks of a process, every market gets a task of its own when it is added and
+//can later be moved onto another one. commands are routed by market id, the account ledger and
+//the rate limiter are shared by all markets so balance checks and limits hold across them, and
+//every market added writes to the same audit log
+pub struct EngineRouter {
+    routing: RwLock<Routing>,
+    events: broadcast::Sender<EventEnvelope>,
+    ledger: Option<Arc<AccountLedger>>,
+    rate_limiter: Arc<RateLimiter>,
+    audit: Option<Arc<AuditLog>>,
+    metrics: Arc<Metrics>,
+}
+
+impl EngineRouter {
+    pub fn new(
+        ledger: Option<Arc<AccountLedger>>,
+        rate_limiter: Arc<RateLimiter>,
+        audit: Option<Arc<AuditLog>>,
+    ) -> Self {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        EngineRouter {
+            routing: RwLock::new(Routing {
+                shards: Vec::new(),
+                markets: HashMap::new(),
+            }),
+            events,
+            ledger,
+            rate_limiter,
+            audit,
+            metrics: Arc::new(Metrics::default()),
+        }
+    }
+
+    //events of every market from now on, in order within a market
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.events.subscribe()
+    }
+
+    //starts a task for the market and returns its shard number
+    pub async fn add_market(&self, mut engine: MatchingEngine) -> Result<usize, ServiceError> {
+        let mut routing = self.routing.write().await;
+        if routing.markets.contains_key(&engine.market_id) {
+            return Err(ServiceError::MarketExists(engine.market_id));
+        }
+        // one recovered from a journal got it before the replay
+        if let Some(ledger) = &self.ledger
+            && engine.accounts.is_none()
+        {
+            engine.attach_ledger(ledger.clone());
+        }
+        engine.attach_rate_limiter(self.rate_limiter.clone());
+        if let Some(audit) = &self.audit {
+            engine.attach_audit(audit.clone());
+        }
+        let shard = service::spawn(self.events.clone(), self.metrics.clone());
+        let market_id = engine.market_id;
+        shard
+            .attach(engine)
+            .await
+            .map_err(|_| ServiceError::Stopped)?;
+        routing.shards.push(shard);
+        let shard_id = routing.shards.len() - 1;
+        routing.markets.insert(market_id, shard_id);
+        Ok(shard_id)
+    }
+
+    //the routing table stays locked for the move, commands sent to the market meanwhile wait
+    //and go to the new shard. the old one applies what it already had queued before letting go.
+    //if the new shard is gone the market goes back to the old one
+    pub async fn move_market(&self, market_id: u32, shard_id: usize) -> Result<(), ServiceError> {
+        let mut routing = self.routing.write().await;
+        let from = *routing
+            .markets
+            .get(&market_id)
+            .ok_or(ServiceError::UnknownMarket(market_id))?;
+        if shard_id >= routing.shards.len() {
+            return Err(ServiceError::UnknownShard(shard_id));
+        }
+        if from == shard_id {
+            return Ok(());
+        }
+        let engine = routing.shards[from].detach(market_id).await?;
+        if let Err(engine) = routing.shards[shard_id].attach(engine).await {
+            if routing.shards[from].attach(*engine).await.is_err() {
+                error!("market {} lost, both shards of the move stopped", market_id);
+            }
+            return Err(ServiceError::Stopped);
+        }
+        routing.markets.insert(market_id, shard_id);
+        Ok(())
+    }
+
+    //market id and shard of every market
+    pub async fn markets(&self) -> Vec<(u32, usize)> {
+        let routing = self.routing.read().await;
+        let mut markets: Vec<(u32, usize)> =
+            routing.markets.iter().map(|(m, s)| (*m, *s)).collect();
+        markets.sort();
+        markets
+    }
+
+    //the market list, markets that go away meanwhile are left out
+    pub async fn tickers(&self) -> Vec<MarketTicker> {
+        let mut tickers = Vec::new();
+        for (market_id, _) in self.markets().await {
+            if let Ok(ticker) = self.ticker(market_id).await {
+                tickers.push(ticker);
+            }
+        }
+        tickers
+    }
+
+    //counted by every shard of this router
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub async fn gauges(&self, market_id: u32) -> Result<MarketGauges, ServiceError> {
+        self.call(market_id, |reply| EngineRequest::Gauges {
+            market_id,
+            reply,
+        })
+        .await?
+    }
+
+    //requests waiting on each shard, by shard number
+    pub async fn queue_lengths(&self) -> Vec<usize> {
+        let routing = self.routing.read().await;
+        routing.shards.iter().map(EngineHandle::queued).collect()
+    }
+
+    //events the slowest subscriber has yet to take
+    pub fn queued_events(&self) -> usize {
+        self.events.len()
+    }
+
+    async fn call<T>(
+        &self,
+        market_id: u32,
+        make: impl FnOnce(oneshot::Sender<T>) -> EngineRequest,
+    ) -> Result<T, ServiceError> {
+        // only queueing happens under the lock, a move can not slip in between lookup and send
+        let reply = {
+            let routing = self.routing.read().await;
+            let shard = routing
+                .markets
+                .get(&market_id)
+                .ok_or(ServiceError::UnknownMarket(market_id))?;
+            routing.shards[*shard].send(make).await?
+        };
+        reply.await.map_err(|_| ServiceError::Stopped)
+    }
+}
+
+#[async_trait]
+impl EngineApi for EngineRouter {
+    async fn submit(&self, market_id: u32, command: Command) -> CommandResult {
+        self.call(market_id, |reply| EngineRequest::Submit {
+            market_id,
+            command,
+            client_order_id: None,
+            reply,
+        })
+        .await?
+    }
+
+    async fn submit_once(
+        &self,
+        market_id: u32,
+        client_order_id: String,
+        command: Command,
+    ) -> CommandResult {
+        self.call(market_id, |reply| EngineRequest::Submit {
+            market_id,
+            command,
+            client_order_id: Some(client_order_id),
+            reply,
+        })
+        .await?
+    }
+
+    async fn market_price(
+        &self,
+        market_id: u32,
+        option: OptionType,
+    ) -> Result<BestPrices, ServiceError> {
+        self.call(market_id, |reply| EngineRequest::MarketPrice {
+            market_id,
+            option,
+            reply,
+        })
+        .await?
+    }
+
+    async fn order_book(
+        &self,
+        market_id: u32,
+        option: OptionType,
+    ) -> Result<BookDepth, ServiceError> {
+        self.call(market_id, |reply| EngineRequest::OrderBook {
+            market_id,
+            option,
+            reply,
+        })
+        .await?
+    }
+
+    async fn ticker(&self, market_id: u32) -> Result<MarketTicker, ServiceError> {
+        self.call(market_id, |reply| EngineRequest::Ticker {
+            market_id,
+            reply,
+        })
+        .await?
+    }
+
+    async fn consolidated_book(
+        &self,
+        market_id: u32,
+        option: OptionType,
+    ) -> Result<BookDepth, ServiceError> {
+        self.call(market_id, |reply| EngineRequest::ConsolidatedBook {
+            market_id,
+            option,
+            reply,
+        })
+        .await?
+    }
+
+    async fn book_snapshot(&self, market_id: u32) -> Result<BookSnapshot, ServiceError> {
+        self.call(market_id, |reply| EngineRequest::BookSnapshot {
+            market_id,
+            reply,
+        })
+        .await?
+    }
+
+    async fn order_queue(
+        &self,
+        market_id: u32,
+        option: OptionType,
+    ) -> Result<BookQueue, ServiceError> {
+        self.call(market_id, |reply| EngineRequest::OrderQueue {
+            market_id,
+            option,
+            reply,
+        })
+        .await?
+    }
+
+    async fn order(
+        &self,
+        market_id: u32,
+        user_id: u32,
+        order_id: u64,
+    ) -> Result<Order, ServiceError> {
+        self.call(market_id, |reply| EngineRequest::Order {
+            market_id,
+            user_id,
+            order_id,
+            reply,
+        })
+        .await?
+    }
+
+    async fn open_orders(&self, market_id: u32, user_id: u32) -> Result<Vec<Order>, ServiceError> {
+        self.call(market_id, |reply| EngineRequest::OpenOrders {
+            market_id,
+            user_id,
+            reply,
+        })
+        .await?
+    }
+
+    async fn portfolio(
+        &self,
+        market_id: u32,
+        user_id: u32,
+    ) -> Result<Vec<PositionPnl>, ServiceError> {
+        self.call(market_id, |reply| EngineRequest::Portfolio {
+            market_id,
+            user_id,
+            reply,
+        })
+        .await?
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    MatchingEngine, rate_limit::RateLimiter, repl::Repl, router::EngineRouter,
+    service::ServiceError,
+};
+
+const CONTEXT: usize = 2;
+
+//golden scenarios: a script of console commands in <name>.scenario, and next to it in
+//<name>.expected the transcript it has to produce, closing with the books of every market.
+//blank lines and # comments are copied to the transcript as they are
+pub fn transcript(script: &str) -> Result<String, ServiceError> {
+    let runtime = tokio::runtime::Runtime::new().map_err(|_| ServiceError::Stopped)?;
+    let router = EngineRouter::new(None, Arc::new(RateLimiter::new()), None);
+    runtime.block_on(router.add_market(MatchingEngine::new(1)))?;
+    let mut repl = Repl::new(runtime, router, 1);
+
+    let mut out = String::new();
+    for line in script.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+        out.push_str(&format!("> {}\n", line));
+        match repl.execute(line) {
+            Ok(Some(reply)) => out.push_str(&reply),
+            Ok(None) => break,
+            Err(e) => out.push_str(&format!("error: {}\n", e)),
+        }
+    }
+    out.push_str("\n# final books\n");
+    for market_id in repl.market_ids() {
+        for option in ["yes", "no"] {
+            let book = format!("book {} market={}", option, market_id);
+            match repl.execute(&book) {
+                Ok(Some(reply)) => out.push_str(&reply),
+                Ok(None) => {}
+                Err(e) => out.push_str(&format!("error: {}\n", e)),
+            }
+        }
+    }
+    Ok(out)
+}
+
+//changed lines with a little context, - expected and + actual
+pub fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // longest common subsequence from the back, then walk it from the front
+    let (n, m) = (expected.len(), actual.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            lines.push((' ', expected[i]));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            lines.push(('+', actual[j]));
+            j += 1;
+        } else {
+            lines.push(('-', expected[i]));
+            i += 1;
+        }
+    }
+
+    let changed: Vec<usize> = (0..lines.len()).filter(|k| lines[*k].0 != ' ').collect();
+    let mut out = String::new();
+    let mut shown_until = 0;
+    for (k, (mark, line)) in lines.iter().enumerate() {
+        let near = changed
+            .iter()
+            .any(|c| k + CONTEXT >= *c && k <= c + CONTEXT);
+        if !near {
+            continue;
+        }
+        if k > shown_until && !out.is_empty() {
+            out.push_str("  ...\n");
+        }
+        out.push_str(&format!("{} {}\n", mark, line));
+        shown_until = k + 1;
+    }
+    out
+}
+
+fn expected_path(path: &Path) -> PathBuf {
+    path.with_extension("expected")
+}
+
+//None when the transcript matches, else the diff. update rewrites the expected file instead
+pub fn check(path: &Path, update: bool) -> io::Result<Option<String>> {
+    let script = fs::read_to_string(path)?;
+    let actual = transcript(&script).map_err(io::Error::other)?;
+    let expected_path = expected_path(path);
+    if update {
+        fs::write(expected_path, actual)?;
+        return Ok(None);
+    }
+    let expected = match fs::read_to_string(&expected_path) {
+        Ok(expected) => expected,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    Ok((expected != actual).then(|| diff(&expected, &actual)))
+}
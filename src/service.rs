@@ -0,0 +1,373 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::{
+    MatchingEngine, OptionType, Order, Trade,
+    events::EventEnvelope,
+    journal::Command,
+    metrics::{MarketGauges, Metrics},
+    now_nanos,
+    portfolio::PositionPnl,
+    risk::RejectReason,
+    stats::MarketTicker,
+};
+
+const REQUEST_QUEUE: usize = 10_000;
+
+pub type CommandResult = Result<(Option<Order>, Vec<Trade>), ServiceError>;
+// best bid and best ask
+pub type BestPrices = (Option<f64>, Option<f64>);
+// price in cents -> aggregated quantity, bids then asks
+pub type BookDepth = (BTreeMap<u64, u32>, BTreeMap<u64, u32>);
+// price in cents -> resting orders in priority order, bids then asks
+pub type BookQueue = (BTreeMap<u64, Vec<Order>>, BTreeMap<u64, Vec<Order>>);
+
+//depth of both books and the book_seq of the last level 2 delta they include
+pub struct BookSnapshot {
+    pub book_seq: u64,
+    pub yes: BookDepth,
+    pub no: BookDepth,
+}
+
+#[derive(Debug, Error)]
+pub enum ServiceError {
+    #[error(transparent)]
+    Rejected(#[from] RejectReason),
+    #[error("market {0} does not exist")]
+    UnknownMarket(u32),
+    #[error("market {0} already exists")]
+    MarketExists(u32),
+    #[error("shard {0} does not exist")]
+    UnknownShard(usize),
+    #[error("matching engine stopped")]
+    Stopped,
+}
+
+//what front-ends can ask of the engine, commands of a market are applied strictly one after the other
+#[async_trait]
+pub trait EngineApi: Send + Sync {
+    async fn submit(&self, market_id: u32, command: Command) -> CommandResult;
+
+    //applied at most once per user and client order id, a retry gets the first answer back
+    async fn submit_once(
+        &self,
+        market_id: u32,
+        client_order_id: String,
+        command: Command,
+    ) -> CommandResult;
+
+    async fn market_price(
+        &self,
+        market_id: u32,
+        option: OptionType,
+    ) -> Result<BestPrices, ServiceError>;
+
+    async fn order_book(
+        &self,
+        market_id: u32,
+        option: OptionType,
+    ) -> Result<BookDepth, ServiceError>;
+
+    //both books merged into one outcome, the other one at complement prices
+    async fn consolidated_book(
+        &self,
+        market_id: u32,
+        option: OptionType,
+    ) -> Result<BookDepth, ServiceError>;
+
+    //rolling 24h statistics and best prices of both outcomes
+    async fn ticker(&self, market_id: u32) -> Result<MarketTicker, ServiceError>;
+
+    //for syncing with the level 2 deltas of the market
+    async fn book_snapshot(&self, market_id: u32) -> Result<BookSnapshot, ServiceError>;
+
+    //every resting order of the book, it is up to the caller to hide whose they are
+    async fn order_queue(
+        &self,
+        market_id: u32,
+        option: OptionType,
+    ) -> Result<BookQueue, ServiceError>;
+
+    async fn order(
+        &self,
+        market_id: u32,
+        user_id: u32,
+        order_id: u64,
+    ) -> Result<Order, ServiceError>;
+
+    async fn open_orders(&self, market_id: u32, user_id: u32) -> Result<Vec<Order>, ServiceError>;
+
+    //positions of the user with their pnl, marked to the mid of the book
+    async fn portfolio(
+        &self,
+        market_id: u32,
+        user_id: u32,
+    ) -> Result<Vec<PositionPnl>, ServiceError>;
+}
+
+pub enum EngineRequest {
+    Submit {
+        market_id: u32,
+        command: Command,
+        client_order_id: Option<String>,
+        reply: oneshot::Sender<CommandResult>,
+    },
+    MarketPrice {
+        market_id: u32,
+        option: OptionType,
+        reply: oneshot::Sender<Result<BestPrices, ServiceError>>,
+    },
+    OrderBook {
+        market_id: u32,
+        option: OptionType,
+        reply: oneshot::Sender<Result<BookDepth, ServiceError>>,
+    },
+    Ticker {
+        market_id: u32,
+        reply: oneshot::Sender<Result<MarketTicker, ServiceError>>,
+    },
+    ConsolidatedBook {
+        market_id: u32,
+        option: OptionType,
+        reply: oneshot::Sender<Result<BookDepth, ServiceError>>,
+    },
+    BookSnapshot {
+        market_id: u32,
+        reply: oneshot::Sender<Result<BookSnapshot, ServiceError>>,
+    },
+    OrderQueue {
+        market_id: u32,
+        option: OptionType,
+        reply: oneshot::Sender<Result<BookQueue, ServiceError>>,
+    },
+    Order {
+        market_id: u32,
+        user_id: u32,
+        order_id: u64,
+        reply: oneshot::Sender<Result<Order, ServiceError>>,
+    },
+    OpenOrders {
+        market_id: u32,
+        user_id: u32,
+        reply: oneshot::Sender<Result<Vec<Order>, ServiceError>>,
+    },
+    Portfolio {
+        market_id: u32,
+        user_id: u32,
+        reply: oneshot::Sender<Result<Vec<PositionPnl>, ServiceError>>,
+    },
+    Gauges {
+        market_id: u32,
+        reply: oneshot::Sender<Result<MarketGauges, ServiceError>>,
+    },
+    Attach {
+        engine: Box<MatchingEngine>,
+    },
+    Detach {
+        market_id: u32,
+        reply: oneshot::Sender<Option<Box<MatchingEngine>>>,
+    },
+}
+
+//cheap to clone, every clone talks to the same engine task
+#[derive(Clone)]
+pub struct EngineHandle {
+    requests: mpsc::Sender<EngineRequest>,
+}
+
+//starts an engine task, the only place the markets attached to it are ever touched from.
+//their events go out on the given channel, what the commands did is counted in metrics
+pub fn spawn(events: broadcast::Sender<EventEnvelope>, metrics: Arc<Metrics>) -> EngineHandle {
+    let (requests, rx) = mpsc::channel(REQUEST_QUEUE);
+    tokio::spawn(run(rx, events, metrics));
+    EngineHandle { requests }
+}
+
+async fn run(
+    mut rx: mpsc::Receiver<EngineRequest>,
+    events: broadcast::Sender<EventEnvelope>,
+    metrics: Arc<Metrics>,
+) {
+    let mut markets: HashMap<u32, Box<MatchingEngine>> = HashMap::new();
+    while let Some(request) = rx.recv().await {
+        // a dropped reply only means the caller went away, the command still counts
+        match request {
+            EngineRequest::Submit {
+                market_id,
+                command,
+                client_order_id,
+                reply,
+            } => {
+                let Some(engine) = markets.get_mut(&market_id) else {
+                    let _ = reply.send(Err(ServiceError::UnknownMarket(market_id)));
+                    continue;
+                };
+                let started = Instant::now();
+                let kind = command.kind();
+                let result = engine.submit(command, client_order_id);
+                metrics.record(market_id, kind, started.elapsed(), &result);
+                let _ = reply.send(result.map_err(ServiceError::from));
+                for event in engine.drain_events() {
+                    // no subscribers is fine
+                    let _ = events.send(event);
+                }
+            }
+            EngineRequest::MarketPrice {
+                market_id,
+                option,
+                reply,
+            } => {
+                let result = markets
+                    .get(&market_id)
+                    .map(|engine| engine.get_market_price(option))
+                    .ok_or(ServiceError::UnknownMarket(market_id));
+                let _ = reply.send(result);
+            }
+            EngineRequest::OrderBook {
+                market_id,
+                option,
+                reply,
+            } => {
+                let result = markets
+                    .get(&market_id)
+                    .map(|engine| engine.get_order_book(option))
+                    .ok_or(ServiceError::UnknownMarket(market_id));
+                let _ = reply.send(result);
+            }
+            EngineRequest::Ticker { market_id, reply } => {
+                let result = markets
+                    .get(&market_id)
+                    .map(|engine| engine.get_ticker(now_nanos()))
+                    .ok_or(ServiceError::UnknownMarket(market_id));
+                let _ = reply.send(result);
+            }
+            EngineRequest::ConsolidatedBook {
+                market_id,
+                option,
+                reply,
+            } => {
+                let result = markets
+                    .get(&market_id)
+                    .map(|engine| engine.get_consolidated_book(option))
+                    .ok_or(ServiceError::UnknownMarket(market_id));
+                let _ = reply.send(result);
+            }
+            EngineRequest::BookSnapshot { market_id, reply } => {
+                let result = markets
+                    .get(&market_id)
+                    .map(|engine| engine.get_book_snapshot())
+                    .ok_or(ServiceError::UnknownMarket(market_id));
+                let _ = reply.send(result);
+            }
+            EngineRequest::OrderQueue {
+                market_id,
+                option,
+                reply,
+            } => {
+                let result = markets
+                    .get(&market_id)
+                    .map(|engine| engine.get_order_queue(option))
+                    .ok_or(ServiceError::UnknownMarket(market_id));
+                let _ = reply.send(result);
+            }
+            EngineRequest::Order {
+                market_id,
+                user_id,
+                order_id,
+                reply,
+            } => {
+                let result = match markets.get_mut(&market_id) {
+                    Some(engine) => engine.get_order(user_id, order_id).map_err(Into::into),
+                    None => Err(ServiceError::UnknownMarket(market_id)),
+                };
+                let _ = reply.send(result);
+            }
+            EngineRequest::OpenOrders {
+                market_id,
+                user_id,
+                reply,
+            } => {
+                let result = match markets.get_mut(&market_id) {
+                    Some(engine) => engine.get_open_orders(user_id).map_err(Into::into),
+                    None => Err(ServiceError::UnknownMarket(market_id)),
+                };
+                let _ = reply.send(result);
+            }
+            EngineRequest::Portfolio {
+                market_id,
+                user_id,
+                reply,
+            } => {
+                let result = markets
+                    .get(&market_id)
+                    .map(|engine| engine.get_portfolio(user_id))
+                    .ok_or(ServiceError::UnknownMarket(market_id));
+                let _ = reply.send(result);
+            }
+            EngineRequest::Gauges { market_id, reply } => {
+                let result = markets
+                    .get(&market_id)
+                    .map(|engine| engine.gauges())
+                    .ok_or(ServiceError::UnknownMarket(market_id));
+                let _ = reply.send(result);
+            }
+            EngineRequest::Attach { engine } => {
+                markets.insert(engine.market_id, engine);
+            }
+            EngineRequest::Detach { market_id, reply } => {
+                let _ = reply.send(markets.remove(&market_id));
+            }
+        }
+    }
+}
+
+impl EngineHandle {
+    //queues the request and hands back where the reply will arrive,
+    //requests are handled in the order they were queued
+    pub async fn send<T>(
+        &self,
+        make: impl FnOnce(oneshot::Sender<T>) -> EngineRequest,
+    ) -> Result<oneshot::Receiver<T>, ServiceError> {
+        let (reply, rx) = oneshot::channel();
+        self.requests
+            .send(make(reply))
+            .await
+            .map_err(|_| ServiceError::Stopped)?;
+        Ok(rx)
+    }
+
+    //requests queued and not yet picked up by the task
+    pub fn queued(&self) -> usize {
+        self.requests.max_capacity() - self.requests.capacity()
+    }
+
+    //a task that stopped hands the engine back, so the market is not lost with it
+    pub async fn attach(&self, engine: MatchingEngine) -> Result<(), Box<MatchingEngine>> {
+        let request = EngineRequest::Attach {
+            engine: Box::new(engine),
+        };
+        match self.requests.send(request).await {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::SendError(EngineRequest::Attach { engine })) => Err(engine),
+            Err(_) => unreachable!("only an attach was sent"),
+        }
+    }
+
+    //takes the market off the task, whatever was queued for it before is applied first
+    pub async fn detach(&self, market_id: u32) -> Result<MatchingEngine, ServiceError> {
+        let reply = self
+            .send(|reply| EngineRequest::Detach { market_id, reply })
+            .await?;
+        let engine = reply.await.map_err(|_| ServiceError::Stopped)?;
+        engine
+            .map(|engine| *engine)
+            .ok_or(ServiceError::UnknownMarket(market_id))
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::MatchingEngine;
+
+//where snapshots go and how many journaled commands may pass between two of them
+pub struct SnapshotPolicy {
+    pub dir: PathBuf,
+    pub interval: u64,
+    pub last_seq: u64,
+}
+
+impl SnapshotPolicy {
+    pub fn new(dir: impl Into<PathBuf>, interval: u64) -> Self {
+        SnapshotPolicy {
+            dir: dir.into(),
+            interval,
+            last_seq: 0,
+        }
+    }
+
+    pub fn is_due(&self, journal_seq: u64) -> bool {
+        journal_seq >= self.last_seq + self.interval
+    }
+}
+
+// zero padded so the latest snapshot is also the last file name
+fn snapshot_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("snapshot-{:020}.json", seq))
+}
+
+//serialize the whole engine, named after the last journal entry it contains
+pub fn write_snapshot(dir: &Path, engine: &MatchingEngine) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = snapshot_path(dir, engine.journal_seq);
+    let tmp = path.with_extension("tmp");
+
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer(&mut writer, engine)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    // rename only once it is fully on disk so a crash never leaves half a snapshot behind
+    fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
+pub fn load_latest_snapshot(dir: &Path) -> io::Result<Option<MatchingEngine>> {
+    let latest = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|ext| ext == "json")
+                    && path
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with("snapshot-"))
+            })
+            .max(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    match latest {
+        Some(path) => {
+            let engine = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            Ok(Some(engine))
+        }
+        None => Ok(None),
+    }
+}
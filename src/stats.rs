@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{OptionType, Trade};
+
+pub const WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+//trades of one outcome over the last 24h and the last price from before that
+#[derive(Default, Serialize, Deserialize)]
+struct OutcomeWindow {
+    trades: VecDeque<(u64, f64, u32)>, // timestamp, price, quantity, oldest first
+    before_window: Option<f64>,
+}
+
+impl OutcomeWindow {
+    fn prune(&mut self, now: u64) {
+        let start = now.saturating_sub(WINDOW_NANOS);
+        while let Some((timestamp, price, _)) = self.trades.front() {
+            if *timestamp >= start {
+                break;
+            }
+            self.before_window = Some(*price);
+            self.trades.pop_front();
+        }
+    }
+}
+
+//rolling 24h statistics of a market, every trade goes in as it happens
+#[derive(Default, Serialize, Deserialize)]
+pub struct RollingStats {
+    outcomes: BTreeMap<OptionType, OutcomeWindow>,
+}
+
+//one outcome of a market on the market list
+#[derive(Clone, Debug, Serialize)]
+pub struct OutcomeTicker {
+    pub option: OptionType,
+    pub last_price: Option<f64>,
+    pub change_24h: Option<f64>, // against the last price 24h ago, or the first one since
+    pub high_24h: Option<f64>,
+    pub low_24h: Option<f64>,
+    pub volume_24h: u64,    // shares
+    pub open_interest: u64, // shares held long
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MarketTicker {
+    pub market_id: u32,
+    pub outcomes: Vec<OutcomeTicker>,
+}
+
+impl RollingStats {
+    //both legs count, each in its own outcome and price
+    pub fn record(&mut self, trade: &Trade) {
+        for (option, price) in trade.legs() {
+            let window = self.outcomes.entry(option).or_default();
+            window
+                .trades
+                .push_back((trade.timestamp, price, trade.quantity));
+            window.prune(trade.timestamp);
+        }
+    }
+
+    //last, change, high, low and volume of the window ending now. the book and position
+    //fields are left for the engine to fill in. a query leaves the window as it is, only
+    //trades prune it
+    pub fn ticker(&self, option: OptionType, now: u64) -> OutcomeTicker {
+        let start = now.saturating_sub(WINDOW_NANOS);
+        let (trades, before_window) = match self.outcomes.get(&option) {
+            Some(window) => {
+                let split = window.trades.partition_point(|(ts, _, _)| *ts < start);
+                let before = window.trades.range(..split).next_back();
+                (
+                    window.trades.range(split..),
+                    before.map(|(_, price, _)| *price).or(window.before_window),
+                )
+            }
+            None => (Default::default(), None),
+        };
+        let prices = || trades.clone().map(|(_, price, _)| *price);
+        let last_price = prices().next_back().or(before_window);
+        let reference = before_window.or(prices().next());
+        OutcomeTicker {
+            option,
+            last_price,
+            change_24h: last_price.zip(reference).map(|(last, open)| last - open),
+            high_24h: prices().reduce(f64::max),
+            low_24h: prices().reduce(f64::min),
+            volume_24h: trades.clone().map(|(_, _, qty)| *qty as u64).sum(),
+            open_interest: 0,
+            best_bid: None,
+            best_ask: None,
+        }
+    }
+}
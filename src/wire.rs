@@ -0,0 +1,524 @@
+//compact binary order entry. every frame is a big-endian u16 length of what follows, a u8
+//message type and a fixed layout body, integers big-endian and prices in cents.
+//only std is used here so quoting bots can take this file as their client as is
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+pub const MAX_FRAME: usize = 64;
+pub const TOKEN_LEN: usize = 32;
+
+const LOGIN: u8 = 0x01;
+const NEW_ORDER: u8 = 0x10;
+const CANCEL: u8 = 0x11;
+const AMEND: u8 = 0x12;
+const LOGGED_IN: u8 = 0x81;
+const ACK: u8 = 0x90;
+const REJECT: u8 = 0x91;
+const FILL: u8 = 0x92;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Yes,
+    No,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectCode {
+    Malformed = 1,
+    NotLoggedIn = 2,
+    BadToken = 3,
+    UnknownMarket = 4,
+    UnknownOrder = 5,
+    NotOrderOwner = 6,
+    PriceOutOfRange = 7,
+    ZeroQuantity = 8,
+    MarketClosed = 9,
+    RateLimited = 10,
+    RiskLimit = 11,
+    InsufficientBalance = 12,
+    Unavailable = 13, // journal or engine down, worth retrying later
+}
+
+impl RejectCode {
+    fn from_u8(code: u8) -> io::Result<Self> {
+        use RejectCode::*;
+        let codes = [
+            Malformed,
+            NotLoggedIn,
+            BadToken,
+            UnknownMarket,
+            UnknownOrder,
+            NotOrderOwner,
+            PriceOutOfRange,
+            ZeroQuantity,
+            MarketClosed,
+            RateLimited,
+            RiskLimit,
+            InsufficientBalance,
+            Unavailable,
+        ];
+        codes
+            .into_iter()
+            .find(|c| *c as u8 == code)
+            .ok_or_else(|| invalid(format!("unknown reject code {}", code)))
+    }
+}
+
+// client_id is picked by the client and echoed in the ack or reject of the request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    // token of up to TOKEN_LEN bytes, zero padded
+    Login {
+        token: String,
+    },
+    NewOrder {
+        client_id: u64,
+        market_id: u32,
+        outcome: Outcome,
+        side: Side,
+        price: u32,
+        quantity: u32,
+    },
+    Cancel {
+        client_id: u64,
+        market_id: u32,
+        order_id: u64,
+    },
+    Amend {
+        client_id: u64,
+        market_id: u32,
+        order_id: u64,
+        price: u32,
+        quantity: u32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    LoggedIn {
+        user_id: u32,
+    },
+    // quantity is what is left open once the request went through, fills follow separately
+    Ack {
+        client_id: u64,
+        market_id: u32,
+        order_id: u64,
+        price: u32,
+        quantity: u32,
+    },
+    Reject {
+        client_id: u64,
+        code: RejectCode,
+    },
+    Fill {
+        market_id: u32,
+        order_id: u64,
+        price: u32,
+        quantity: u32,
+        remaining: u32,
+    },
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn new(msg_type: u8) -> Self {
+        Encoder(vec![0, 0, msg_type])
+    }
+
+    fn u8(mut self, v: u8) -> Self {
+        self.0.push(v);
+        self
+    }
+
+    fn u32(mut self, v: u32) -> Self {
+        self.0.extend(v.to_be_bytes());
+        self
+    }
+
+    fn u64(mut self, v: u64) -> Self {
+        self.0.extend(v.to_be_bytes());
+        self
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let len = (self.0.len() - 2) as u16;
+        self.0[..2].copy_from_slice(&len.to_be_bytes());
+        self.0
+    }
+}
+
+//reads a body of exactly the expected size, the message type already taken off
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn new(body: &'a [u8], expected: usize, msg_type: u8) -> io::Result<Self> {
+        if body.len() != expected {
+            return Err(invalid(format!(
+                "message {:#04x} needs {} bytes, got {}",
+                msg_type,
+                expected,
+                body.len()
+            )));
+        }
+        Ok(Decoder(body))
+    }
+
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        head.try_into().unwrap()
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_be_bytes(self.take())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_be_bytes(self.take())
+    }
+
+    fn outcome(&mut self) -> io::Result<Outcome> {
+        match self.u8() {
+            0 => Ok(Outcome::Yes),
+            1 => Ok(Outcome::No),
+            other => Err(invalid(format!("unknown outcome {}", other))),
+        }
+    }
+
+    fn side(&mut self) -> io::Result<Side> {
+        match self.u8() {
+            0 => Ok(Side::Buy),
+            1 => Ok(Side::Sell),
+            other => Err(invalid(format!("unknown side {}", other))),
+        }
+    }
+}
+
+fn split(frame: &[u8]) -> io::Result<(u8, &[u8])> {
+    frame
+        .split_first()
+        .map(|(msg_type, body)| (*msg_type, body))
+        .ok_or_else(|| invalid("empty frame".to_string()))
+}
+
+impl Request {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Request::Login { token } => {
+                let mut padded = [0u8; TOKEN_LEN];
+                let len = token.len().min(TOKEN_LEN);
+                padded[..len].copy_from_slice(&token.as_bytes()[..len]);
+                let mut encoder = Encoder::new(LOGIN);
+                encoder.0.extend(padded);
+                encoder.finish()
+            }
+            Request::NewOrder {
+                client_id,
+                market_id,
+                outcome,
+                side,
+                price,
+                quantity,
+            } => Encoder::new(NEW_ORDER)
+                .u64(*client_id)
+                .u32(*market_id)
+                .u8(*outcome as u8)
+                .u8(*side as u8)
+                .u32(*price)
+                .u32(*quantity)
+                .finish(),
+            Request::Cancel {
+                client_id,
+                market_id,
+                order_id,
+            } => Encoder::new(CANCEL)
+                .u64(*client_id)
+                .u32(*market_id)
+                .u64(*order_id)
+                .finish(),
+            Request::Amend {
+                client_id,
+                market_id,
+                order_id,
+                price,
+                quantity,
+            } => Encoder::new(AMEND)
+                .u64(*client_id)
+                .u32(*market_id)
+                .u64(*order_id)
+                .u32(*price)
+                .u32(*quantity)
+                .finish(),
+        }
+    }
+
+    //a frame without its length prefix
+    pub fn decode(frame: &[u8]) -> io::Result<Self> {
+        let (msg_type, body) = split(frame)?;
+        match msg_type {
+            LOGIN => {
+                let token = Decoder::new(body, TOKEN_LEN, msg_type)?.0;
+                let end = token.iter().position(|b| *b == 0).unwrap_or(TOKEN_LEN);
+                let token = std::str::from_utf8(&token[..end])
+                    .map_err(|e| invalid(e.to_string()))?
+                    .to_string();
+                Ok(Request::Login { token })
+            }
+            NEW_ORDER => {
+                let mut d = Decoder::new(body, 22, msg_type)?;
+                Ok(Request::NewOrder {
+                    client_id: d.u64(),
+                    market_id: d.u32(),
+                    outcome: d.outcome()?,
+                    side: d.side()?,
+                    price: d.u32(),
+                    quantity: d.u32(),
+                })
+            }
+            CANCEL => {
+                let mut d = Decoder::new(body, 20, msg_type)?;
+                Ok(Request::Cancel {
+                    client_id: d.u64(),
+                    market_id: d.u32(),
+                    order_id: d.u64(),
+                })
+            }
+            AMEND => {
+                let mut d = Decoder::new(body, 28, msg_type)?;
+                Ok(Request::Amend {
+                    client_id: d.u64(),
+                    market_id: d.u32(),
+                    order_id: d.u64(),
+                    price: d.u32(),
+                    quantity: d.u32(),
+                })
+            }
+            other => Err(invalid(format!("unknown request type {:#04x}", other))),
+        }
+    }
+}
+
+impl Response {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Response::LoggedIn { user_id } => Encoder::new(LOGGED_IN).u32(*user_id).finish(),
+            Response::Ack {
+                client_id,
+                market_id,
+                order_id,
+                price,
+                quantity,
+            } => Encoder::new(ACK)
+                .u64(*client_id)
+                .u32(*market_id)
+                .u64(*order_id)
+                .u32(*price)
+                .u32(*quantity)
+                .finish(),
+            Response::Reject { client_id, code } => Encoder::new(REJECT)
+                .u64(*client_id)
+                .u8(*code as u8)
+                .finish(),
+            Response::Fill {
+                market_id,
+                order_id,
+                price,
+                quantity,
+                remaining,
+            } => Encoder::new(FILL)
+                .u32(*market_id)
+                .u64(*order_id)
+                .u32(*price)
+                .u32(*quantity)
+                .u32(*remaining)
+                .finish(),
+        }
+    }
+
+    pub fn decode(frame: &[u8]) -> io::Result<Self> {
+        let (msg_type, body) = split(frame)?;
+        match msg_type {
+            LOGGED_IN => {
+                let mut d = Decoder::new(body, 4, msg_type)?;
+                Ok(Response::LoggedIn { user_id: d.u32() })
+            }
+            ACK => {
+                let mut d = Decoder::new(body, 28, msg_type)?;
+                Ok(Response::Ack {
+                    client_id: d.u64(),
+                    market_id: d.u32(),
+                    order_id: d.u64(),
+                    price: d.u32(),
+                    quantity: d.u32(),
+                })
+            }
+            REJECT => {
+                let mut d = Decoder::new(body, 9, msg_type)?;
+                Ok(Response::Reject {
+                    client_id: d.u64(),
+                    code: RejectCode::from_u8(d.u8())?,
+                })
+            }
+            FILL => {
+                let mut d = Decoder::new(body, 24, msg_type)?;
+                Ok(Response::Fill {
+                    market_id: d.u32(),
+                    order_id: d.u64(),
+                    price: d.u32(),
+                    quantity: d.u32(),
+                    remaining: d.u32(),
+                })
+            }
+            other => Err(invalid(format!("unknown response type {:#04x}", other))),
+        }
+    }
+}
+
+//the next frame without its length prefix
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    reader.read_exact(&mut len)?;
+    let len = u16::from_be_bytes(len) as usize;
+    if len == 0 || len > MAX_FRAME {
+        return Err(invalid(format!("frame length {}", len)));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+//blocking client. requests are pipelined, wait picks out the answer to one of them and
+//keeps fills and other answers that arrive first for recv
+pub struct Client {
+    stream: TcpStream,
+    next_id: u64,
+    pending: VecDeque<Response>,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs, token: &str) -> io::Result<(Self, u32)> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut client = Client {
+            stream,
+            next_id: 1,
+            pending: VecDeque::new(),
+        };
+        client.send(&Request::Login {
+            token: token.to_string(),
+        })?;
+        match client.read()? {
+            Response::LoggedIn { user_id } => Ok((client, user_id)),
+            other => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("login failed: {:?}", other),
+            )),
+        }
+    }
+
+    pub fn new_order(
+        &mut self,
+        market_id: u32,
+        outcome: Outcome,
+        side: Side,
+        price: u32,
+        quantity: u32,
+    ) -> io::Result<u64> {
+        let client_id = self.next_id();
+        self.send(&Request::NewOrder {
+            client_id,
+            market_id,
+            outcome,
+            side,
+            price,
+            quantity,
+        })?;
+        Ok(client_id)
+    }
+
+    pub fn cancel(&mut self, market_id: u32, order_id: u64) -> io::Result<u64> {
+        let client_id = self.next_id();
+        self.send(&Request::Cancel {
+            client_id,
+            market_id,
+            order_id,
+        })?;
+        Ok(client_id)
+    }
+
+    pub fn amend(
+        &mut self,
+        market_id: u32,
+        order_id: u64,
+        price: u32,
+        quantity: u32,
+    ) -> io::Result<u64> {
+        let client_id = self.next_id();
+        self.send(&Request::Amend {
+            client_id,
+            market_id,
+            order_id,
+            price,
+            quantity,
+        })?;
+        Ok(client_id)
+    }
+
+    //the ack or reject of a request
+    pub fn wait(&mut self, client_id: u64) -> io::Result<Response> {
+        let answered = |response: &Response| match response {
+            Response::Ack { client_id: id, .. } | Response::Reject { client_id: id, .. } => {
+                *id == client_id
+            }
+            _ => false,
+        };
+        if let Some(i) = self.pending.iter().position(answered) {
+            return Ok(self.pending.remove(i).unwrap());
+        }
+        loop {
+            let response = self.read()?;
+            if answered(&response) {
+                return Ok(response);
+            }
+            self.pending.push_back(response);
+        }
+    }
+
+    pub fn recv(&mut self) -> io::Result<Response> {
+        match self.pending.pop_front() {
+            Some(response) => Ok(response),
+            None => self.read(),
+        }
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    fn send(&mut self, request: &Request) -> io::Result<()> {
+        self.stream.write_all(&request.encode())
+    }
+
+    fn read(&mut self) -> io::Result<Response> {
+        Response::decode(&read_frame(&mut self.stream)?)
+    }
+}
@@ -0,0 +1,258 @@
+use std::{io, sync::Arc};
+
+use log::info;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, tcp::OwnedReadHalf},
+    sync::{broadcast::error::RecvError, mpsc},
+};
+
+use crate::{
+    OptionType, Order, OrderType,
+    events::{EngineEvent, EventEnvelope},
+    journal::Command,
+    risk::RejectReason,
+    router::EngineRouter,
+    service::{EngineApi, ServiceError},
+    wire::{MAX_FRAME, Outcome, RejectCode, Request, Response, Side},
+    ws::AuthTokens,
+};
+
+pub async fn serve(addr: &str, engine: Arc<EngineRouter>, tokens: AuthTokens) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("binary order entry listening on {}", listener.local_addr()?);
+    let tokens = Arc::new(tokens);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let (engine, tokens) = (engine.clone(), tokens.clone());
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, engine, tokens).await {
+                info!("binary connection {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+fn code(e: &ServiceError) -> RejectCode {
+    match e {
+        ServiceError::Rejected(reason) => match reason {
+            RejectReason::PriceOutOfRange(_) => RejectCode::PriceOutOfRange,
+            RejectReason::ZeroQuantity => RejectCode::ZeroQuantity,
+            RejectReason::UnknownOrder(_) => RejectCode::UnknownOrder,
+            RejectReason::NotOrderOwner(_) => RejectCode::NotOrderOwner,
+            RejectReason::MarketClosed => RejectCode::MarketClosed,
+            RejectReason::RateLimited { .. } => RejectCode::RateLimited,
+            RejectReason::JournalWrite(_) => RejectCode::Unavailable,
+            RejectReason::InsufficientBalance { .. } => RejectCode::InsufficientBalance,
+            RejectReason::MaxOrderQuantity { .. }
+            | RejectReason::MaxOrderNotional { .. }
+            | RejectReason::MaxOpenOrders { .. }
+            | RejectReason::MaxNetPosition { .. }
+            | RejectReason::PriceDeviation { .. } => RejectCode::RiskLimit,
+        },
+        ServiceError::UnknownMarket(_) => RejectCode::UnknownMarket,
+        ServiceError::Stopped | ServiceError::MarketExists(_) | ServiceError::UnknownShard(_) => {
+            RejectCode::Unavailable
+        }
+    }
+}
+
+fn cents(price: f64) -> u32 {
+    (price * 100.0).round() as u32
+}
+
+fn ack(client_id: u64, market_id: u32, order: &Order) -> Response {
+    Response::Ack {
+        client_id,
+        market_id,
+        order_id: order.id,
+        price: cents(order.price),
+        quantity: order.quantity,
+    }
+}
+
+//frames are read on their own task, reading them inside a select would not be cancel safe
+async fn read_requests(
+    mut reader: OwnedReadHalf,
+    requests: mpsc::Sender<io::Result<Request>>,
+) -> io::Result<()> {
+    loop {
+        let mut len = [0u8; 2];
+        reader.read_exact(&mut len).await?;
+        let len = u16::from_be_bytes(len) as usize;
+        let request = if len == 0 || len > MAX_FRAME {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame length {}", len),
+            ))
+        } else {
+            let mut frame = vec![0u8; len];
+            reader.read_exact(&mut frame).await?;
+            Request::decode(&frame)
+        };
+        let malformed = request.is_err();
+        if requests.send(request).await.is_err() || malformed {
+            return Ok(());
+        }
+    }
+}
+
+async fn handle(
+    stream: TcpStream,
+    engine: Arc<EngineRouter>,
+    tokens: Arc<AuthTokens>,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let (reader, mut writer) = stream.into_split();
+    let (tx, mut requests) = mpsc::channel(64);
+    tokio::spawn(read_requests(reader, tx));
+    let mut events = engine.subscribe();
+    let mut user_id = None;
+    loop {
+        let response = tokio::select! {
+            request = requests.recv() => match request {
+                None => return Ok(()),
+                Some(Err(e)) => {
+                    // framing is lost after a bad frame, say so and hang up
+                    let reject = Response::Reject { client_id: 0, code: RejectCode::Malformed };
+                    writer.write_all(&reject.encode()).await?;
+                    return Err(e);
+                }
+                Some(Ok(Request::Login { token })) => match tokens.get(&token) {
+                    Some(id) => {
+                        user_id = Some(*id);
+                        Response::LoggedIn { user_id: *id }
+                    }
+                    None => Response::Reject { client_id: 0, code: RejectCode::BadToken },
+                },
+                Some(Ok(request)) => match user_id {
+                    Some(user_id) => execute(&engine, user_id, request).await,
+                    None => Response::Reject {
+                        client_id: client_id(&request),
+                        code: RejectCode::NotLoggedIn,
+                    },
+                },
+            },
+            event = events.recv() => match event {
+                Ok(envelope) => match fill(user_id, envelope) {
+                    Some(fill) => fill,
+                    None => continue,
+                },
+                Err(RecvError::Lagged(missed)) => {
+                    // fills can not be made up for, the client has to resync from the rest api
+                    return Err(io::Error::other(format!("missed {} engine events", missed)));
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        };
+        writer.write_all(&response.encode()).await?;
+    }
+}
+
+fn client_id(request: &Request) -> u64 {
+    match request {
+        Request::Login { .. } => 0,
+        Request::NewOrder { client_id, .. }
+        | Request::Cancel { client_id, .. }
+        | Request::Amend { client_id, .. } => *client_id,
+    }
+}
+
+fn fill(user_id: Option<u32>, envelope: EventEnvelope) -> Option<Response> {
+    match envelope.event {
+        EngineEvent::OrderFilled {
+            order_id,
+            user_id: owner,
+            price,
+            filled_quantity,
+            remaining_quantity,
+        } if Some(owner) == user_id => Some(Response::Fill {
+            market_id: envelope.market_id,
+            order_id,
+            price: cents(price),
+            quantity: filled_quantity,
+            remaining: remaining_quantity,
+        }),
+        _ => None,
+    }
+}
+
+//the ack comes back before the fills of the request, those are read off the events afterwards
+async fn execute(engine: &EngineRouter, user_id: u32, request: Request) -> Response {
+    let client_id = client_id(&request);
+    let result = match request {
+        Request::Login { .. } => unreachable!("handled by the connection"),
+        Request::NewOrder {
+            market_id,
+            outcome,
+            side,
+            price,
+            quantity,
+            ..
+        } => {
+            let command = Command::PlaceOrder {
+                user_id,
+                option: match outcome {
+                    Outcome::Yes => OptionType::Yes,
+                    Outcome::No => OptionType::No,
+                },
+                order_type: match side {
+                    Side::Buy => OrderType::Buy,
+                    Side::Sell => OrderType::Sell,
+                },
+                price: price as f64 / 100.0,
+                quantity,
+            };
+            engine
+                .submit(market_id, command)
+                .await
+                .map(|(order, _)| (market_id, order))
+        }
+        Request::Cancel {
+            market_id,
+            order_id,
+            ..
+        } => {
+            let command = Command::CancelOrder { user_id, order_id };
+            engine.submit(market_id, command).await.map(|(order, _)| {
+                let cancelled = order.map(|order| Order {
+                    quantity: 0,
+                    ..order
+                });
+                (market_id, cancelled)
+            })
+        }
+        Request::Amend {
+            market_id,
+            order_id,
+            price,
+            quantity,
+            ..
+        } => {
+            let command = Command::AmendOrder {
+                user_id,
+                order_id,
+                price: price as f64 / 100.0,
+                quantity,
+            };
+            engine
+                .submit(market_id, command)
+                .await
+                .map(|(order, _)| (market_id, order))
+        }
+    };
+    match result {
+        Ok((market_id, Some(order))) => ack(client_id, market_id, &order),
+        Ok((market_id, None)) => Response::Ack {
+            client_id,
+            market_id,
+            order_id: 0,
+            price: 0,
+            quantity: 0,
+        },
+        Err(e) => Response::Reject {
+            client_id,
+            code: code(&e),
+        },
+    }
+}
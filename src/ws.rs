@@ -0,0 +1,430 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Router,
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+    routing::get,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    OptionType, OrderType, Trade,
+    events::{EngineEvent, EventEnvelope},
+    http::BookResponse,
+    publisher::{DepthChange, MarketState, Ticker},
+    router::EngineRouter,
+    service::{EngineApi, ServiceError},
+};
+
+const FEED_BUFFER: usize = 10_000;
+
+// api token -> user id
+pub type AuthTokens = HashMap<String, u32>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Depth,
+    Trades,
+    Ticker,
+    Orders, // private, order status changes and fills of the authenticated user
+    // level 2 deltas straight from the engine, apply those newer than GET /markets/{id}/book
+    BookDeltas,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Auth { token: String },
+    Subscribe { channel: Channel, market_id: u32 },
+    Unsubscribe { channel: Channel, market_id: u32 },
+}
+
+//everything the server sends. depth updates carry absolute level quantities and an id per
+//market, a client applies the ones newer than its snapshot
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    DepthSnapshot {
+        market_id: u32,
+        update_id: u64,
+        books: Vec<BookResponse>,
+    },
+    DepthUpdate {
+        market_id: u32,
+        update_id: u64,
+        changes: Vec<DepthChange>,
+    },
+    Trade {
+        trade: Trade,
+    },
+    Ticker {
+        ticker: Ticker,
+    },
+    Order {
+        event: EventEnvelope,
+    },
+    BookDelta {
+        market_id: u32,
+        book_seq: u64,
+        option: OptionType,
+        side: OrderType,
+        price: f64,
+        quantity: u32,
+    },
+    Subscribed {
+        channel: Channel,
+        market_id: u32,
+    },
+    Unsubscribed {
+        channel: Channel,
+        market_id: u32,
+    },
+    Authenticated {
+        user_id: u32,
+    },
+    Error {
+        message: String,
+    },
+}
+
+//turns engine events into public market data once for all connections, a market is picked up
+//the first time anyone asks for it or it has something going on
+pub struct MarketDataHub {
+    engine: Arc<EngineRouter>,
+    markets: Mutex<HashMap<u32, MarketState>>,
+    feed: broadcast::Sender<ServerMessage>,
+}
+
+impl MarketDataHub {
+    pub fn spawn(engine: Arc<EngineRouter>) -> Arc<Self> {
+        let events = engine.subscribe();
+        let (feed, _) = broadcast::channel(FEED_BUFFER);
+        let hub = Arc::new(MarketDataHub {
+            engine,
+            markets: Mutex::new(HashMap::new()),
+            feed,
+        });
+        tokio::spawn(hub.clone().run(events));
+        hub
+    }
+
+    async fn run(self: Arc<Self>, mut events: broadcast::Receiver<EventEnvelope>) {
+        let mut dirty = BTreeSet::new();
+        loop {
+            match events.recv().await {
+                Ok(envelope) => {
+                    let market_id = envelope.market_id;
+                    dirty.insert(market_id);
+                    if let EngineEvent::Trade { trade } = &envelope.event {
+                        let _ = self.feed.send(ServerMessage::Trade {
+                            trade: trade.clone(),
+                        });
+                    }
+                    if let Err(e) = self.open_market(market_id).await {
+                        warn!("could not follow market {}: {}", market_id, e);
+                        continue;
+                    }
+                    let mut markets = self.markets.lock().unwrap();
+                    if let Some(market) = markets.get_mut(&market_id) {
+                        market.apply(&envelope.event);
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("market data hub missed {} events, resyncing", missed);
+                    let mut markets = self.markets.lock().unwrap();
+                    for (market_id, market) in markets.iter_mut() {
+                        market.mark_stale();
+                        dirty.insert(*market_id);
+                    }
+                }
+                Err(RecvError::Closed) => return,
+            }
+            // book and ticker only once the burst of events of a command is through
+            if events.is_empty() {
+                for market_id in std::mem::take(&mut dirty) {
+                    if let Err(e) = self.flush(market_id).await {
+                        warn!("could not update market {}: {}", market_id, e);
+                    }
+                }
+            }
+        }
+    }
+
+    //starts following a market from a snapshot, deltas already in it are skipped later on
+    async fn open_market(&self, market_id: u32) -> Result<(), ServiceError> {
+        if self.markets.lock().unwrap().contains_key(&market_id) {
+            return Ok(());
+        }
+        let market = MarketState::fetch(&*self.engine, market_id).await?;
+        self.markets
+            .lock()
+            .unwrap()
+            .entry(market_id)
+            .or_insert(market);
+        Ok(())
+    }
+
+    async fn flush(&self, market_id: u32) -> Result<(), ServiceError> {
+        let stale = self
+            .markets
+            .lock()
+            .unwrap()
+            .get(&market_id)
+            .is_some_and(MarketState::is_stale);
+        let snapshot = match stale {
+            true => Some(self.engine.book_snapshot(market_id).await?),
+            false => None,
+        };
+
+        // the lock covers state and send, a new subscriber sees either both or neither
+        let mut markets = self.markets.lock().unwrap();
+        let Some(market) = markets.get_mut(&market_id) else {
+            return Ok(());
+        };
+        if let Some(snapshot) = snapshot {
+            market.resync(snapshot);
+        }
+        let (changes, tickers) = market.take_update();
+        if !changes.is_empty() {
+            let _ = self.feed.send(ServerMessage::DepthUpdate {
+                market_id,
+                update_id: market.book_seq(),
+                changes,
+            });
+        }
+        if tickers {
+            for ticker in market.tickers(market_id) {
+                let _ = self.feed.send(ServerMessage::Ticker { ticker });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct WsState {
+    hub: Arc<MarketDataHub>,
+    tokens: Arc<AuthTokens>,
+}
+
+pub fn routes(hub: Arc<MarketDataHub>, tokens: AuthTokens) -> Router {
+    Router::new()
+        .route("/ws", get(upgrade))
+        .with_state(WsState {
+            hub,
+            tokens: Arc::new(tokens),
+        })
+}
+
+async fn upgrade(State(state): State<WsState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| serve_socket(socket, state))
+}
+
+//subscriptions of one connection
+#[derive(Default)]
+struct Session {
+    user_id: Option<u32>,
+    depth: HashMap<u32, u64>, // market -> update id the client is at
+    trades: HashSet<u32>,
+    ticker: HashSet<u32>,
+    orders: HashSet<u32>,
+    book_deltas: HashSet<u32>,
+}
+
+impl Session {
+    async fn handle(&mut self, state: &WsState, text: &str) -> Vec<ServerMessage> {
+        let request: ClientMessage = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => return vec![error(format!("invalid message: {}", e))],
+        };
+        match request {
+            ClientMessage::Auth { token } => match state.tokens.get(&token) {
+                Some(user_id) => {
+                    self.user_id = Some(*user_id);
+                    vec![ServerMessage::Authenticated { user_id: *user_id }]
+                }
+                None => vec![error("invalid token".to_string())],
+            },
+            ClientMessage::Subscribe { channel, market_id } => {
+                if let Err(e) = state.hub.open_market(market_id).await {
+                    return vec![error(e.to_string())];
+                }
+                self.subscribe(state, channel, market_id)
+            }
+            ClientMessage::Unsubscribe { channel, market_id } => {
+                match channel {
+                    Channel::Depth => self.depth.remove(&market_id).is_some(),
+                    Channel::Trades => self.trades.remove(&market_id),
+                    Channel::Ticker => self.ticker.remove(&market_id),
+                    Channel::Orders => self.orders.remove(&market_id),
+                    Channel::BookDeltas => self.book_deltas.remove(&market_id),
+                };
+                vec![ServerMessage::Unsubscribed { channel, market_id }]
+            }
+        }
+    }
+
+    fn subscribe(
+        &mut self,
+        state: &WsState,
+        channel: Channel,
+        market_id: u32,
+    ) -> Vec<ServerMessage> {
+        let markets = state.hub.markets.lock().unwrap();
+        let Some(market) = markets.get(&market_id) else {
+            return vec![error(ServiceError::UnknownMarket(market_id).to_string())];
+        };
+        let mut messages = vec![ServerMessage::Subscribed { channel, market_id }];
+        match channel {
+            Channel::Depth => {
+                self.depth.insert(market_id, market.book_seq());
+                messages.push(snapshot(market_id, market));
+            }
+            Channel::Trades => {
+                self.trades.insert(market_id);
+            }
+            Channel::Ticker => {
+                self.ticker.insert(market_id);
+                messages.extend(
+                    market
+                        .tickers(market_id)
+                        .into_iter()
+                        .map(|ticker| ServerMessage::Ticker { ticker }),
+                );
+            }
+            Channel::Orders => {
+                if self.user_id.is_none() {
+                    return vec![error(
+                        "authenticate before subscribing to orders".to_string(),
+                    )];
+                }
+                self.orders.insert(market_id);
+            }
+            Channel::BookDeltas => {
+                self.book_deltas.insert(market_id);
+            }
+        }
+        messages
+    }
+
+    //public data the connection asked for, depth updates it already has are dropped
+    fn filter_feed(&mut self, message: ServerMessage) -> Option<ServerMessage> {
+        let wanted = match &message {
+            ServerMessage::DepthUpdate {
+                market_id,
+                update_id,
+                ..
+            } => match self.depth.get_mut(market_id) {
+                Some(at) if update_id > at => {
+                    *at = *update_id;
+                    true
+                }
+                _ => false,
+            },
+            ServerMessage::Trade { trade } => self.trades.contains(&trade.market_id),
+            ServerMessage::Ticker { ticker } => self.ticker.contains(&ticker.market_id),
+            _ => false,
+        };
+        wanted.then_some(message)
+    }
+
+    //engine events the connection asked for, its own orders and level 2 deltas
+    fn filter_events(&self, envelope: EventEnvelope) -> Option<ServerMessage> {
+        if let EngineEvent::BookDelta {
+            book_seq,
+            option,
+            side,
+            price,
+            quantity,
+        } = envelope.event
+        {
+            let wanted = self.book_deltas.contains(&envelope.market_id);
+            return wanted.then_some(ServerMessage::BookDelta {
+                market_id: envelope.market_id,
+                book_seq,
+                option,
+                side,
+                price,
+                quantity,
+            });
+        }
+        let wanted = self.user_id.is_some()
+            && envelope.event.user_id() == self.user_id
+            && self.orders.contains(&envelope.market_id);
+        wanted.then_some(ServerMessage::Order { event: envelope })
+    }
+
+    //after missing part of the feed every depth subscription starts over from a snapshot
+    fn resync(&mut self, state: &WsState) -> Vec<ServerMessage> {
+        let markets = state.hub.markets.lock().unwrap();
+        let mut messages = Vec::new();
+        for (market_id, at) in self.depth.iter_mut() {
+            if let Some(market) = markets.get(market_id) {
+                *at = market.book_seq();
+                messages.push(snapshot(*market_id, market));
+            }
+        }
+        messages
+    }
+}
+
+fn snapshot(market_id: u32, market: &MarketState) -> ServerMessage {
+    ServerMessage::DepthSnapshot {
+        market_id,
+        update_id: market.book_seq(),
+        books: market
+            .depth()
+            .books()
+            .map(|(option, bids, asks)| BookResponse::new(*option, bids, asks))
+            .collect(),
+    }
+}
+
+fn error(message: String) -> ServerMessage {
+    ServerMessage::Error { message }
+}
+
+async fn serve_socket(mut socket: WebSocket, state: WsState) {
+    // both receivers exist before any subscription, so nothing after a snapshot can be missed
+    let mut feed = state.hub.feed.subscribe();
+    let mut events = state.hub.engine.subscribe();
+    let mut session = Session::default();
+    loop {
+        let outgoing = tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => session.handle(&state, text.as_str()).await,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => Vec::new(),
+            },
+            message = feed.recv() => match message {
+                Ok(message) => session.filter_feed(message).into_iter().collect(),
+                Err(RecvError::Lagged(_)) => session.resync(&state),
+                Err(RecvError::Closed) => return,
+            },
+            event = events.recv() => match event {
+                Ok(envelope) => session.filter_events(envelope).into_iter().collect(),
+                // book delta subscribers see the gap in book_seq and fetch a new snapshot
+                Err(RecvError::Lagged(missed)) => {
+                    vec![error(format!("missed {} engine events", missed))]
+                }
+                Err(RecvError::Closed) => return,
+            },
+        };
+        for message in outgoing {
+            let text = serde_json::to_string(&message).expect("messages serialize");
+            if socket.send(Message::Text(text.into())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
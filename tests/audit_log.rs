@@ -0,0 +1,93 @@
+mod common;
+
+use std::{env, fs};
+
+use serde_json::{Value, json};
+
+use common::{request_as, start_server};
+
+fn records(path: &str) -> Vec<Value> {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn every_command_is_audited_with_its_outcome_and_trades() {
+    let path = env::temp_dir().join(format!("probo-audit-{}.jsonl", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let path = path.to_str().unwrap().to_string();
+
+    {
+        let server = start_server(&["--audit", &path]);
+        for (user_id, side, price) in [(1, "Sell", 6.0), (2, "Buy", 6.0), (3, "Buy", 12.0)] {
+            request_as(
+                &server,
+                user_id,
+                "POST",
+                "/markets/1/orders",
+                Some(json!({
+                    "option": "Yes", "order_type": side, "price": price, "quantity": 10
+                })),
+            );
+        }
+    }
+
+    let audit = records(&path);
+    assert_eq!(audit.len(), 3);
+    let seqs: Vec<u64> = audit.iter().map(|r| r["seq"].as_u64().unwrap()).collect();
+    assert_eq!(seqs, [1, 2, 3]);
+
+    assert_eq!(audit[0]["outcome"], "accepted");
+    assert_eq!(audit[0]["market_id"], 1);
+    assert_eq!(audit[0]["command"]["PlaceOrder"]["user_id"], 1);
+    assert!(audit[0]["trades"].as_array().unwrap().is_empty());
+
+    let trades = audit[1]["trades"].as_array().unwrap();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0]["quantity"], 10);
+    assert_eq!(audit[1]["order"]["quantity"], 0);
+    assert!(audit[1]["event_seq"].as_u64() > audit[0]["event_seq"].as_u64());
+
+    assert_eq!(audit[2]["outcome"], "rejected");
+    assert!(audit[2]["reason"].as_str().unwrap().contains("outside"));
+    assert!(audit[2].get("order").is_none());
+
+    // a restarted process carries on with the numbering
+    {
+        let server = start_server(&["--audit", &path]);
+        let order = json!({"option": "No", "order_type": "Buy", "price": 3.0, "quantity": 5});
+        request_as(&server, 4, "POST", "/markets/1/orders", Some(order));
+    }
+    let audit = records(&path);
+    assert_eq!(audit.len(), 4);
+    assert_eq!(audit[3]["seq"], 4);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn a_torn_last_record_is_dropped_on_open() {
+    let path = env::temp_dir().join(format!("probo-audit-torn-{}.jsonl", std::process::id()));
+    fs::write(
+        &path,
+        "{\"seq\":1,\"outcome\":\"accepted\"}\n{\"seq\":2,\"outc",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap().to_string();
+
+    {
+        let server = start_server(&["--audit", &path]);
+        let order = json!({"option": "Yes", "order_type": "Buy", "price": 5.0, "quantity": 5});
+        request_as(&server, 1, "POST", "/markets/1/orders", Some(order));
+    }
+    let audit = records(&path);
+    assert_eq!(audit.len(), 2);
+    assert_eq!(audit[0]["seq"], 1);
+    assert_eq!(audit[1]["seq"], 2);
+    assert_eq!(audit[1]["outcome"], "accepted");
+
+    fs::remove_file(path).unwrap();
+}
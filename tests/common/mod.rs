@@ -0,0 +1,149 @@
+// shared by several test crates, each using only part of it
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+// users the tokens file of an http server knows
+const USERS: u32 = 20;
+
+pub struct Server {
+    child: Child,
+    pub addr: String,
+    tokens: Option<PathBuf>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if let Some(tokens) = &self.tokens {
+            let _ = fs::remove_file(tokens);
+        }
+    }
+}
+
+//bearer token of a user on servers started with start_server
+pub fn token(user_id: u32) -> String {
+    format!("user-{}-token", user_id)
+}
+
+//http server with a token for every test user
+pub fn start_server(extra_args: &[&str]) -> Server {
+    static SERVERS: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!(
+        "probo-tokens-{}-{}.json",
+        std::process::id(),
+        SERVERS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tokens: serde_json::Map<String, Value> =
+        (1..=USERS).map(|user| (token(user), user.into())).collect();
+    fs::write(&path, Value::Object(tokens).to_string()).unwrap();
+    let mut args = vec!["--tokens", path.to_str().unwrap()];
+    args.extend(extra_args);
+    let mut server = start_mode("http", &args);
+    server.tokens = Some(path);
+    server
+}
+
+pub fn start_mode(mode: &str, extra_args: &[&str]) -> Server {
+    // let the os pick a free port, then hand it to the server
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let child = Command::new(env!("CARGO_BIN_EXE_probo-engine"))
+        .args([mode, &addr])
+        .args(extra_args)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(&addr).is_err() {
+        assert!(Instant::now() < deadline, "{} server did not come up", mode);
+        thread::sleep(Duration::from_millis(20));
+    }
+    Server {
+        child,
+        addr,
+        tokens: None,
+    }
+}
+
+pub fn request(server: &Server, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+    let (status, body) = request_text(server, method, path, body);
+    (status, json_body(&body))
+}
+
+//a get sent with an Authorization: Bearer header
+pub fn get_with_token(server: &Server, path: &str, token: &str) -> (u16, Value) {
+    let auth = format!("Authorization: Bearer {}\r\n", token);
+    let (status, body) = send(server, "GET", path, &auth, None);
+    (status, json_body(&body))
+}
+
+//a request sent with the bearer token of the user
+pub fn request_as(
+    server: &Server,
+    user_id: u32,
+    method: &str,
+    path: &str,
+    body: Option<Value>,
+) -> (u16, Value) {
+    let auth = format!("Authorization: Bearer {}\r\n", token(user_id));
+    let (status, body) = send(server, method, path, &auth, body);
+    (status, json_body(&body))
+}
+
+//status and the body as it was sent
+pub fn request_text(
+    server: &Server,
+    method: &str,
+    path: &str,
+    body: Option<Value>,
+) -> (u16, String) {
+    send(server, method, path, "", body)
+}
+
+fn json_body(body: &str) -> Value {
+    if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(body).unwrap()
+    }
+}
+
+fn send(
+    server: &Server,
+    method: &str,
+    path: &str,
+    headers: &str,
+    body: Option<Value>,
+) -> (u16, String) {
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
@@ -0,0 +1,411 @@
+mod common;
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use common::{Server, start_mode};
+
+type Fields = Vec<(u32, String)>;
+
+//a bare bones fix initiator, deliberately independent of the gateway's own codec
+struct Initiator {
+    stream: TcpStream,
+    comp_id: &'static str,
+    next_seq: u64,
+    buf: Vec<u8>,
+}
+
+fn get(message: &Fields, tag: u32) -> Option<&str> {
+    message
+        .iter()
+        .find(|(t, _)| *t == tag)
+        .map(|(_, v)| v.as_str())
+}
+
+fn encode(fields: &[(u32, String)]) -> Vec<u8> {
+    let body: String = fields
+        .iter()
+        .map(|(t, v)| format!("{}={}\x01", t, v))
+        .collect();
+    let mut raw = format!("8=FIX.4.4\x019={}\x01{}", body.len(), body).into_bytes();
+    let checksum = raw.iter().map(|b| *b as u32).sum::<u32>() % 256;
+    raw.extend(format!("10={:03}\x01", checksum).bytes());
+    raw
+}
+
+impl Initiator {
+    fn connect(server: &Server, comp_id: &'static str, next_seq: u64) -> Self {
+        let stream = TcpStream::connect(&server.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        Initiator {
+            stream,
+            comp_id,
+            next_seq,
+            buf: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, msg_type: &str, body: &[(u32, &str)]) {
+        let mut fields = vec![
+            (35, msg_type.to_string()),
+            (49, self.comp_id.to_string()),
+            (56, "PROBO".to_string()),
+            (34, self.next_seq.to_string()),
+            (52, "20260101-00:00:00.000".to_string()),
+        ];
+        fields.extend(body.iter().map(|(t, v)| (*t, v.to_string())));
+        self.stream.write_all(&encode(&fields)).unwrap();
+        self.next_seq += 1;
+    }
+
+    fn recv(&mut self) -> Fields {
+        loop {
+            // a message ends with the checksum field
+            if let Some(end) = self.buf.windows(4).position(|w| w == b"\x0110=") {
+                let end = end + "\x0110=000\x01".len();
+                if self.buf.len() >= end {
+                    let raw: Vec<u8> = self.buf.drain(..end).collect();
+                    let checksum = raw[..end - 7].iter().map(|b| *b as u32).sum::<u32>() % 256;
+                    let text = String::from_utf8(raw).unwrap();
+                    assert!(
+                        text.ends_with(&format!("10={:03}\x01", checksum)),
+                        "{}",
+                        text
+                    );
+                    return text
+                        .split('\x01')
+                        .filter(|f| !f.is_empty())
+                        .map(|f| {
+                            let (tag, value) = f.split_once('=').unwrap();
+                            (tag.parse().unwrap(), value.to_string())
+                        })
+                        .collect();
+                }
+            }
+            let mut chunk = [0u8; 4096];
+            let n = self
+                .stream
+                .read(&mut chunk)
+                .expect("no message from the gateway");
+            assert!(n > 0, "gateway closed the connection");
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    //next message of the type, heartbeats and test requests in between are skipped
+    fn expect(&mut self, msg_type: &str) -> Fields {
+        loop {
+            let message = self.recv();
+            match get(&message, 35).unwrap() {
+                t if t == msg_type => return message,
+                "0" | "1" => continue,
+                other => panic!("expected {} but got {}: {:?}", msg_type, other, message),
+            }
+        }
+    }
+
+    fn logon(&mut self) -> Fields {
+        self.send("A", &[(98, "0"), (108, "30")]);
+        self.expect("A")
+    }
+
+    fn new_order(&mut self, cl_ord_id: &str, symbol: &str, side: &str, price: &str, qty: &str) {
+        self.send(
+            "D",
+            &[
+                (11, cl_ord_id),
+                (55, symbol),
+                (54, side),
+                (60, "20260101-00:00:00.000"),
+                (38, qty),
+                (40, "2"),
+                (44, price),
+            ],
+        );
+    }
+}
+
+fn fixture(name: &str) -> (PathBuf, PathBuf) {
+    let dir = env::temp_dir().join(format!("probo-fix-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let sessions = dir.join("sessions.json");
+    fs::write(&sessions, r#"{"MM1": 7, "MM2": 8}"#).unwrap();
+    (sessions, dir.join("store"))
+}
+
+fn start(sessions: &Path, store: &Path) -> Server {
+    start_mode(
+        "fix",
+        &[
+            "--fix-sessions",
+            sessions.to_str().unwrap(),
+            "--fix-store",
+            store.to_str().unwrap(),
+        ],
+    )
+}
+
+#[test]
+fn orders_are_acknowledged_and_fills_reach_both_sides() {
+    let (sessions, store) = fixture("fills");
+    let server = start(&sessions, &store);
+
+    let mut maker = Initiator::connect(&server, "MM1", 1);
+    let logon = maker.logon();
+    assert_eq!(get(&logon, 56), Some("MM1"));
+    assert_eq!(get(&logon, 108), Some("30"));
+    maker.new_order("a1", "1-YES", "1", "6", "100");
+    let ack = maker.expect("8");
+    assert_eq!(get(&ack, 11), Some("a1"));
+    assert_eq!(get(&ack, 150), Some("0"));
+    assert_eq!(get(&ack, 39), Some("0"));
+    assert_eq!(get(&ack, 151), Some("100"));
+    let order_id = get(&ack, 37).unwrap().to_string();
+
+    let mut taker = Initiator::connect(&server, "MM2", 1);
+    taker.logon();
+    taker.new_order("b1", "1-YES", "2", "6", "40");
+    assert_eq!(get(&taker.expect("8"), 150), Some("0"));
+    let fill = taker.expect("8");
+    assert_eq!(get(&fill, 150), Some("F"));
+    assert_eq!(get(&fill, 39), Some("2"));
+    assert_eq!(get(&fill, 32), Some("40"));
+    assert_eq!(get(&fill, 31), Some("6"));
+
+    let fill = maker.expect("8");
+    assert_eq!(get(&fill, 37), Some(order_id.as_str()));
+    assert_eq!(get(&fill, 11), Some("a1"));
+    assert_eq!(get(&fill, 150), Some("F"));
+    assert_eq!(get(&fill, 39), Some("1"));
+    assert_eq!(get(&fill, 14), Some("40"));
+    assert_eq!(get(&fill, 151), Some("60"));
+
+    // a comp id without a session is not let in
+    let mut stranger = Initiator::connect(&server, "NOBODY", 1);
+    stranger.send("A", &[(98, "0"), (108, "30")]);
+    let mut chunk = [0u8; 16];
+    assert_eq!(stranger.stream.read(&mut chunk).unwrap(), 0);
+
+    drop(server);
+    fs::remove_dir_all(store.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn orders_can_be_replaced_cancelled_and_rejected() {
+    let (sessions, store) = fixture("amend");
+    let server = start(&sessions, &store);
+    let mut client = Initiator::connect(&server, "MM1", 1);
+    client.logon();
+
+    client.new_order("c1", "1-NO", "1", "3", "50");
+    let ack = client.expect("8");
+    assert_eq!(get(&ack, 150), Some("0"));
+
+    client.send(
+        "G",
+        &[
+            (41, "c1"),
+            (11, "c2"),
+            (55, "1-NO"),
+            (54, "1"),
+            (38, "80"),
+            (40, "2"),
+            (44, "3.5"),
+        ],
+    );
+    let replaced = client.expect("8");
+    assert_eq!(get(&replaced, 150), Some("5"));
+    assert_eq!(get(&replaced, 11), Some("c2"));
+    assert_eq!(get(&replaced, 41), Some("c1"));
+    assert_eq!(get(&replaced, 44), Some("3.5"));
+    assert_eq!(get(&replaced, 151), Some("80"));
+
+    client.send("F", &[(41, "c2"), (11, "c3"), (55, "1-NO"), (54, "1")]);
+    let cancelled = client.expect("8");
+    assert_eq!(get(&cancelled, 150), Some("4"));
+    assert_eq!(get(&cancelled, 39), Some("4"));
+    assert_eq!(get(&cancelled, 11), Some("c3"));
+    assert_eq!(get(&cancelled, 41), Some("c2"));
+
+    client.send("F", &[(41, "c2"), (11, "c4"), (55, "1-NO"), (54, "1")]);
+    let reject = client.expect("9");
+    assert_eq!(get(&reject, 434), Some("1"));
+    assert_eq!(get(&reject, 11), Some("c4"));
+
+    client.new_order("d1", "1-MAYBE", "1", "3", "50");
+    let rejected = client.expect("8");
+    assert_eq!(get(&rejected, 150), Some("8"));
+    assert_eq!(get(&rejected, 39), Some("8"));
+    client.new_order("d2", "1-YES", "1", "12", "50");
+    let rejected = client.expect("8");
+    assert_eq!(get(&rejected, 150), Some("8"));
+    assert!(get(&rejected, 58).unwrap().contains("outside"));
+
+    client.send("1", &[(112, "ping")]);
+    let heartbeat = client.recv();
+    assert_eq!(get(&heartbeat, 35), Some("0"));
+    assert_eq!(get(&heartbeat, 112), Some("ping"));
+
+    drop(server);
+    fs::remove_dir_all(store.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn sequence_numbers_survive_restarts_and_gaps_are_filled() {
+    let (sessions, store) = fixture("resend");
+    let server = start(&sessions, &store);
+    let mut client = Initiator::connect(&server, "MM1", 1);
+    assert_eq!(get(&client.logon(), 34), Some("1"));
+    client.new_order("e1", "1-YES", "1", "5", "10");
+    assert_eq!(get(&client.expect("8"), 34), Some("2"));
+    client.send("5", &[]);
+    assert_eq!(get(&client.expect("5"), 34), Some("3"));
+    drop(server);
+
+    let server = start(&sessions, &store);
+    let mut client = Initiator::connect(&server, "MM1", 4);
+    assert_eq!(get(&client.logon(), 34), Some("4"));
+    // the new process has no journal, so the resting order is gone
+    let gone = client.expect("8");
+    assert_eq!(get(&gone, 34), Some("5"));
+    assert_eq!(get(&gone, 150), Some("4"));
+
+    // admin messages come back as a gap fill, the execution report as a possible duplicate
+    client.send("2", &[(7, "1"), (16, "0")]);
+    let gap = client.recv();
+    assert_eq!(get(&gap, 35), Some("4"));
+    assert_eq!(get(&gap, 34), Some("1"));
+    assert_eq!(get(&gap, 123), Some("Y"));
+    assert_eq!(get(&gap, 36), Some("2"));
+    let resent = client.recv();
+    assert_eq!(get(&resent, 35), Some("8"));
+    assert_eq!(get(&resent, 34), Some("2"));
+    assert_eq!(get(&resent, 43), Some("Y"));
+    assert_eq!(get(&resent, 11), Some("e1"));
+    assert!(get(&resent, 122).is_some());
+    let gap = client.recv();
+    assert_eq!(get(&gap, 35), Some("4"));
+    assert_eq!(get(&gap, 34), Some("3"));
+    assert_eq!(get(&gap, 36), Some("5"));
+    let resent = client.recv();
+    assert_eq!(get(&resent, 34), Some("5"));
+    assert_eq!(get(&resent, 43), Some("Y"));
+
+    // skipping a sequence number makes the gateway ask for it
+    client.next_seq += 1;
+    client.send("0", &[]);
+    let request = client.expect("2");
+    assert_eq!(get(&request, 7), Some("6"));
+    client.next_seq = 6;
+    client.send("4", &[(43, "Y"), (123, "Y"), (36, "8")]);
+    client.next_seq = 8;
+    client.send("1", &[(112, "after-gap")]);
+    assert_eq!(get(&client.expect("0"), 112), Some("after-gap"));
+
+    // and a sequence number it has already seen ends the session
+    client.next_seq = 2;
+    client.send("0", &[]);
+    let logout = client.expect("5");
+    assert!(get(&logout, 58).unwrap().contains("too low"));
+
+    drop(server);
+    fs::remove_dir_all(store.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn orders_filled_while_logged_out_are_reported_at_logon() {
+    let (sessions, store) = fixture("reconnect");
+    let server = start(&sessions, &store);
+    let mut maker = Initiator::connect(&server, "MM1", 1);
+    maker.logon();
+    maker.new_order("r1", "1-YES", "1", "6", "100");
+    assert_eq!(get(&maker.expect("8"), 150), Some("0"));
+    maker.new_order("r2", "1-NO", "1", "3", "20");
+    assert_eq!(get(&maker.expect("8"), 150), Some("0"));
+    maker.send("5", &[]);
+    maker.expect("5");
+    drop(maker);
+
+    // filled while the maker is away
+    let mut taker = Initiator::connect(&server, "MM2", 1);
+    taker.logon();
+    taker.new_order("t1", "1-YES", "2", "6", "30");
+    taker.new_order("t2", "1-NO", "2", "3", "20");
+    for _ in 0..2 {
+        assert_eq!(get(&taker.expect("8"), 150), Some("0"));
+        assert_eq!(get(&taker.expect("8"), 150), Some("F"));
+    }
+
+    let mut maker = Initiator::connect(&server, "MM1", 5);
+    maker.logon();
+    let fill = maker.expect("8");
+    assert_eq!(get(&fill, 150), Some("F"));
+    assert_eq!(get(&fill, 11), Some("r1"));
+    assert_eq!(get(&fill, 32), Some("30"));
+    assert_eq!(get(&fill, 151), Some("70"));
+    let fill = maker.expect("8");
+    assert_eq!(get(&fill, 11), Some("r2"));
+    assert_eq!(get(&fill, 39), Some("2"));
+
+    // and the resting one is still known by its ClOrdID
+    taker.new_order("t3", "1-YES", "2", "6", "10");
+    let fill = maker.expect("8");
+    assert_eq!(get(&fill, 11), Some("r1"));
+    assert_eq!(get(&fill, 14), Some("40"));
+    assert_eq!(get(&fill, 151), Some("60"));
+    maker.send("F", &[(41, "r1"), (11, "r3"), (55, "1-YES"), (54, "1")]);
+    let cancelled = maker.expect("8");
+    assert_eq!(get(&cancelled, 150), Some("4"));
+    assert_eq!(get(&cancelled, 41), Some("r1"));
+    assert_eq!(get(&cancelled, 14), Some("40"));
+
+    // a finished order is forgotten, its ClOrdID can be used again
+    maker.new_order("r1", "1-YES", "1", "5", "10");
+    assert_eq!(get(&maker.expect("8"), 150), Some("0"));
+    maker.send("5", &[]);
+    maker.expect("5");
+    let next_seq = maker.next_seq;
+    drop(maker);
+    drop(taker);
+    drop(server);
+
+    // a fresh process without a journal no longer has the order
+    let server = start(&sessions, &store);
+    let mut maker = Initiator::connect(&server, "MM1", next_seq);
+    maker.logon();
+    let gone = maker.expect("8");
+    assert_eq!(get(&gone, 150), Some("4"));
+    assert_eq!(get(&gone, 11), Some("r1"));
+    assert_eq!(get(&gone, 58), Some("order is no longer open"));
+
+    drop(server);
+    fs::remove_dir_all(store.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn a_heartbeat_interval_of_zero_turns_heartbeats_off() {
+    let (sessions, store) = fixture("noheartbeat");
+    let server = start(&sessions, &store);
+    let mut client = Initiator::connect(&server, "MM1", 1);
+    client.send("A", &[(98, "0"), (108, "0")]);
+    assert_eq!(get(&client.expect("A"), 108), Some("0"));
+
+    client
+        .stream
+        .set_read_timeout(Some(Duration::from_millis(2500)))
+        .unwrap();
+    let mut chunk = [0u8; 64];
+    let silent = client.stream.read(&mut chunk).is_err();
+    assert!(silent, "{}", String::from_utf8_lossy(&chunk));
+
+    drop(server);
+    fs::remove_dir_all(store.parent().unwrap()).unwrap();
+}
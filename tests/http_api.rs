@@ -0,0 +1,516 @@
+mod common;
+
+use std::{env, fs, process::Command};
+
+use serde_json::{Value, json};
+
+use common::{Server, get_with_token, request, request_as, request_text, start_server, token};
+
+fn place(server: &Server, user_id: u32, option: &str, side: &str, price: f64, qty: u32) -> Value {
+    let (status, body) = request_as(
+        server,
+        user_id,
+        "POST",
+        "/markets/1/orders",
+        Some(json!({
+            "option": option, "order_type": side, "price": price, "quantity": qty
+        })),
+    );
+    assert_eq!(status, 201, "{}", body);
+    body
+}
+
+#[test]
+fn orders_can_be_placed_queried_amended_and_cancelled() {
+    let server = start_server(&[]);
+
+    let placed = place(&server, 1, "Yes", "Buy", 6.5, 100);
+    assert!(placed["trades"].as_array().unwrap().is_empty());
+    let order_id = placed["order"]["id"].as_u64().unwrap();
+
+    let path = format!("/markets/1/orders/{}", order_id);
+    let (status, order) = request_as(&server, 1, "GET", &path, None);
+    assert_eq!(status, 200);
+    assert_eq!(order["quantity"], 100);
+
+    let (status, orders) = request_as(&server, 1, "GET", "/markets/1/orders", None);
+    assert_eq!(status, 200);
+    assert_eq!(orders.as_array().unwrap().len(), 1);
+
+    let (status, amended) = request_as(
+        &server,
+        1,
+        "PATCH",
+        &path,
+        Some(json!({"price": 6.5, "quantity": 60})),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(amended["order"]["quantity"], 60);
+
+    let (status, price) = request(&server, "GET", "/markets/1/price/Yes", None);
+    assert_eq!(status, 200);
+    assert_eq!(price["best_bid"], 6.5);
+    assert_eq!(price["best_ask"], Value::Null);
+
+    let (status, book) = request(&server, "GET", "/markets/1/book/Yes", None);
+    assert_eq!(status, 200);
+    assert_eq!(book["bids"], json!([{"price": 6.5, "quantity": 60}]));
+
+    // someone else's order can be neither seen nor cancelled
+    assert_eq!(request_as(&server, 2, "GET", &path, None).0, 403);
+    assert_eq!(request_as(&server, 2, "DELETE", &path, None).0, 403);
+
+    let (status, cancelled) = request_as(&server, 1, "DELETE", &path, None);
+    assert_eq!(status, 200);
+    assert_eq!(cancelled["id"], order_id);
+    assert_eq!(cancelled["quantity"], 60);
+    assert_eq!(request_as(&server, 1, "GET", &path, None).0, 404);
+    assert_eq!(request_as(&server, 1, "DELETE", &path, None).0, 404);
+}
+
+#[test]
+fn order_requests_need_a_valid_bearer_token() {
+    let server = start_server(&[]);
+    let order = json!({"option": "Yes", "order_type": "Buy", "price": 6.5, "quantity": 10});
+
+    let (status, body) = request(&server, "POST", "/markets/1/orders", Some(order.clone()));
+    assert_eq!(status, 401);
+    assert_eq!(body["error"], "missing bearer token");
+    assert_eq!(request(&server, "GET", "/markets/1/orders", None).0, 401);
+    assert_eq!(
+        request(&server, "DELETE", "/markets/1/orders/1", None).0,
+        401
+    );
+    let (status, body) = get_with_token(&server, "/markets/1/orders", "forged");
+    assert_eq!(status, 401);
+    assert_eq!(body["error"], "invalid token");
+
+    // a user_id in the body is not who the order is for, the token is
+    let mut claimed = order.clone();
+    claimed["user_id"] = json!(2);
+    let (status, placed) = request_as(&server, 1, "POST", "/markets/1/orders", Some(claimed));
+    assert_eq!(status, 201);
+    assert_eq!(placed["order"]["user_id"], 1);
+    let (_, orders) = request_as(&server, 2, "GET", "/markets/1/orders", None);
+    assert_eq!(orders, json!([]));
+    let (_, orders) = get_with_token(&server, "/markets/1/orders", &token(1));
+    assert_eq!(orders.as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn crossing_orders_trade() {
+    let server = start_server(&[]);
+
+    place(&server, 1, "Yes", "Sell", 6.0, 50);
+    let placed = place(&server, 2, "Yes", "Buy", 6.2, 30);
+    let trades = placed["trades"].as_array().unwrap();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0]["price"], 6.0);
+    assert_eq!(trades[0]["quantity"], 30);
+    assert_eq!(placed["order"]["quantity"], 0);
+
+    let (_, book) = request(&server, "GET", "/markets/1/book/Yes", None);
+    assert_eq!(book["asks"], json!([{"price": 6.0, "quantity": 20}]));
+}
+
+#[test]
+fn trades_carry_ids_time_parties_side_and_match_type() {
+    let server = start_server(&[]);
+
+    place(&server, 1, "Yes", "Sell", 6.0, 10);
+    let direct = place(&server, 2, "Yes", "Buy", 6.0, 10)["trades"][0].clone();
+    place(&server, 3, "No", "Buy", 4.0, 10);
+    let complementary = place(&server, 4, "Yes", "Sell", 6.0, 5)["trades"][0].clone();
+    let mint_buy = place(&server, 5, "Yes", "Buy", 6.0, 5)["trades"][0].clone();
+
+    assert_eq!(direct["id"], 1);
+    assert_eq!(direct["market_id"], 1);
+    assert_eq!(direct["buy_order_id"], 2);
+    assert_eq!(direct["sell_order_id"], 1);
+    assert_eq!(direct["maker_user_id"], 1);
+    assert_eq!(direct["taker_user_id"], 2);
+    assert_eq!(direct["aggressor_side"], "Buy");
+    assert_eq!(direct["match_type"], "Direct");
+
+    // a yes sell against a no bid pairs a sell with a buy across the books
+    assert_eq!(complementary["id"], 2);
+    assert_eq!(complementary["aggressor_side"], "Sell");
+    assert_eq!(complementary["buy_order_id"], 3);
+    assert_eq!(complementary["sell_order_id"], 4);
+    assert_eq!(complementary["match_type"], "Complementary");
+    // two buys across the books create a share pair
+    assert_eq!(mint_buy["id"], 3);
+    assert_eq!(mint_buy["match_type"], "Mint");
+    assert_eq!(mint_buy["maker_user_id"], 3);
+    assert_eq!(mint_buy["taker_user_id"], 5);
+
+    let times: Vec<u64> = [&direct, &complementary, &mint_buy]
+        .iter()
+        .map(|trade| trade["timestamp"].as_u64().unwrap())
+        .collect();
+    assert!(times[0] > 0 && times.windows(2).all(|w| w[0] <= w[1]));
+}
+
+#[test]
+fn engine_errors_map_to_status_codes() {
+    let server = start_server(&[]);
+
+    let order = json!({"option": "Yes", "order_type": "Buy", "price": 12.0, "quantity": 10});
+    let (status, body) = request_as(&server, 1, "POST", "/markets/1/orders", Some(order.clone()));
+    assert_eq!(status, 400);
+    assert!(body["error"].as_str().unwrap().contains("outside"));
+
+    assert_eq!(
+        request_as(&server, 1, "POST", "/markets/9/orders", Some(order)).0,
+        404
+    );
+    assert_eq!(request(&server, "GET", "/markets/9/book/No", None).0, 404);
+    let amend = json!({"price": 5.0, "quantity": 1});
+    assert_eq!(
+        request_as(&server, 1, "PATCH", "/markets/1/orders/77", Some(amend)).0,
+        404
+    );
+}
+
+#[test]
+fn metrics_are_served_in_prometheus_format() {
+    let server = start_server(&[]);
+
+    place(&server, 1, "Yes", "Sell", 6.0, 50);
+    place(&server, 2, "Yes", "Buy", 6.2, 30);
+    place(&server, 3, "Yes", "Buy", 5.5, 10);
+    let order = json!({"option": "No", "order_type": "Buy", "price": 12.0, "quantity": 10});
+    assert_eq!(
+        request_as(&server, 1, "POST", "/markets/1/orders", Some(order)).0,
+        400
+    );
+
+    let (status, metrics) = request_text(&server, "GET", "/metrics", None);
+    assert_eq!(status, 200);
+    for line in [
+        "# TYPE probo_place_order_seconds histogram",
+        "probo_commands_accepted_total{market=\"1\",command=\"place_order\"} 3",
+        "probo_commands_rejected_total{market=\"1\",command=\"place_order\",reason=\"price_out_of_range\"} 1",
+        "probo_trades_total{market=\"1\",outcome=\"yes\"} 1",
+        "probo_traded_shares_total{market=\"1\",outcome=\"yes\"} 30",
+        "probo_place_order_seconds_count{market=\"1\"} 4",
+        "probo_place_order_seconds_bucket{market=\"1\",le=\"+Inf\"} 4",
+        "probo_book_levels{market=\"1\",outcome=\"yes\",side=\"bid\"} 1",
+        "probo_book_quantity{market=\"1\",outcome=\"yes\",side=\"ask\"} 20",
+        "probo_book_spread{market=\"1\",outcome=\"yes\"} 0.5",
+        "probo_journal_lag{market=\"1\"} 0",
+        "probo_shard_queue_length{shard=\"0\"} 0",
+    ] {
+        assert!(
+            metrics.lines().any(|l| l == line),
+            "{} missing in\n{}",
+            line,
+            metrics
+        );
+    }
+    // no spread while the no book is empty
+    assert!(!metrics.contains("probo_book_spread{market=\"1\",outcome=\"no\"}"));
+}
+
+#[test]
+fn level_3_book_shows_users_to_admins_only() {
+    let server = start_server(&["--admins", "9"]);
+
+    place(&server, 1, "Yes", "Buy", 6.5, 100);
+    place(&server, 2, "Yes", "Buy", 6.5, 40);
+    place(&server, 3, "Yes", "Buy", 6.0, 10);
+    place(&server, 4, "Yes", "Sell", 7.0, 25);
+
+    let path = "/markets/1/book/Yes/orders";
+    let (status, book) = request(&server, "GET", path, None);
+    assert_eq!(status, 200);
+    let best = &book["bids"][0];
+    assert_eq!(best["price"], 6.5);
+    assert_eq!(best["quantity"], 140);
+    // time priority within the level
+    let ids: Vec<u64> = best["orders"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|o| o["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(ids, [1, 2]);
+    assert_eq!(best["orders"][1]["quantity"], 40);
+    assert!(best["orders"][0]["timestamp"].as_u64().unwrap() > 0);
+    assert!(best["orders"][0].get("user_id").is_none());
+    assert_eq!(book["bids"][1]["price"], 6.0);
+    assert_eq!(book["asks"][0]["orders"][0]["id"], 4);
+
+    // a trader's token is fine but does not reveal anyone
+    let (status, book) = get_with_token(&server, path, &token(1));
+    assert_eq!(status, 200);
+    assert!(book["bids"][0]["orders"][0].get("user_id").is_none());
+
+    let (status, book) = get_with_token(&server, path, &token(9));
+    assert_eq!(status, 200);
+    assert_eq!(book["bids"][0]["orders"][0]["user_id"], 1);
+    assert_eq!(book["bids"][0]["orders"][1]["user_id"], 2);
+    assert_eq!(book["asks"][0]["orders"][0]["user_id"], 4);
+
+    assert_eq!(get_with_token(&server, path, "forged").0, 401);
+    assert_eq!(
+        request(&server, "GET", "/markets/9/book/Yes/orders", None).0,
+        404
+    );
+}
+
+#[test]
+fn consolidated_view_counts_the_other_outcome_at_complement_prices() {
+    let server = start_server(&[]);
+
+    place(&server, 2, "Yes", "Sell", 8.0, 10);
+    // a no bid at 2.5 is a yes ask at 7.5, a no ask at 3.9 a yes bid at 6.1
+    place(&server, 3, "No", "Buy", 2.5, 20);
+    place(&server, 4, "No", "Sell", 3.9, 5);
+
+    let (status, price) = request(&server, "GET", "/markets/1/price/Yes", None);
+    assert_eq!(status, 200);
+    assert_eq!(price["best_bid"], Value::Null);
+    assert_eq!(price["best_ask"], 8.0);
+    assert_eq!(price["consolidated"]["best_bid"], 6.1);
+    assert_eq!(price["consolidated"]["best_ask"], 7.5);
+    let implied = price["consolidated"]["implied_probability"]
+        .as_f64()
+        .unwrap();
+    assert!((implied - 0.68).abs() < 1e-9, "{}", implied);
+
+    let (_, price) = request(&server, "GET", "/markets/1/price/No", None);
+    assert_eq!(price["consolidated"]["best_bid"], 2.5);
+    assert_eq!(price["consolidated"]["best_ask"], 3.9);
+    assert_eq!(price["consolidated"]["implied_probability"], 0.32);
+
+    // each level counts once and the book is never crossed
+    for option in ["Yes", "No"] {
+        let path = format!("/markets/1/book/{}/consolidated", option);
+        let (status, book) = request(&server, "GET", &path, None);
+        assert_eq!(status, 200);
+        let best_bid = book["bids"][0]["price"].as_f64().unwrap();
+        let best_ask = book["asks"][0]["price"].as_f64().unwrap();
+        assert!(best_bid <= best_ask, "{}", book);
+    }
+    let (_, book) = request(&server, "GET", "/markets/1/book/Yes/consolidated", None);
+    assert_eq!(book["bids"], json!([{"price": 6.1, "quantity": 5}]));
+    assert_eq!(
+        book["asks"],
+        json!([{"price": 7.5, "quantity": 20}, {"price": 8.0, "quantity": 10}])
+    );
+
+    // the best consolidated ask is there to buy, by minting against the no bid
+    let bought = place(&server, 5, "Yes", "Buy", 7.5, 20);
+    assert_eq!(bought["order"]["quantity"], 0, "{}", bought);
+}
+
+#[test]
+fn trades_build_candles_and_a_paged_tape_that_survive_a_restart() {
+    let journal = env::temp_dir().join(format!("probo-history-{}.journal", std::process::id()));
+    let _ = fs::remove_file(&journal);
+    let journal = journal.to_str().unwrap().to_string();
+    let args = ["--journal", journal.as_str(), "--candle-intervals", "1d,1h"];
+
+    {
+        let server = start_server(&args);
+        place(&server, 1, "Yes", "Sell", 6.0, 10);
+        place(&server, 1, "Yes", "Sell", 6.5, 10);
+        place(&server, 2, "Yes", "Buy", 6.0, 4);
+        place(&server, 3, "Yes", "Buy", 6.5, 10);
+    }
+
+    // a fresh process only has the journal to go by
+    let server = start_server(&args);
+    place(&server, 4, "Yes", "Buy", 6.5, 1);
+
+    let (status, candles) = request(&server, "GET", "/markets/1/candles/Yes?interval=1d", None);
+    assert_eq!(status, 200);
+    let candle = &candles[0];
+    assert_eq!(candles.as_array().unwrap().len(), 1, "{}", candles);
+    assert_eq!(candle["open"], 6.0);
+    assert_eq!(candle["high"], 6.5);
+    assert_eq!(candle["low"], 6.0);
+    assert_eq!(candle["close"], 6.5);
+    assert_eq!(candle["volume"], 15);
+    assert_eq!(candle["trades"], 4);
+    let day = 86_400_000_000_000u64;
+    assert_eq!(candle["start"].as_u64().unwrap() % day, 0);
+
+    // 5m is not configured here
+    let path = "/markets/1/candles/Yes?interval=5m";
+    assert_eq!(request(&server, "GET", path, None).0, 400);
+    let path = "/markets/9/candles/Yes?interval=1d";
+    assert_eq!(request(&server, "GET", path, None).0, 404);
+
+    let (status, page) = request(&server, "GET", "/markets/1/trades?limit=3", None);
+    assert_eq!(status, 200);
+    let ids: Vec<u64> = page["trades"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(ids, [4, 3, 2]);
+    assert_eq!(page["next_before"], 2);
+
+    let (_, page) = request(&server, "GET", "/markets/1/trades?limit=3&before=2", None);
+    assert_eq!(page["trades"].as_array().unwrap().len(), 1);
+    assert_eq!(page["trades"][0]["id"], 1);
+    assert_eq!(page["next_before"], Value::Null);
+
+    fs::remove_file(journal).unwrap();
+}
+
+#[test]
+fn tickers_list_every_market_with_24h_statistics() {
+    let server = start_server(&[]);
+
+    place(&server, 1, "Yes", "Sell", 6.0, 10);
+    place(&server, 1, "Yes", "Sell", 7.0, 10);
+    place(&server, 2, "Yes", "Buy", 6.0, 4);
+    place(&server, 3, "Yes", "Buy", 7.0, 8);
+    place(&server, 4, "Yes", "Buy", 5.0, 3);
+
+    let (status, tickers) = request(&server, "GET", "/tickers", None);
+    assert_eq!(status, 200);
+    assert_eq!(tickers.as_array().unwrap().len(), 1);
+    assert_eq!(tickers[0]["market_id"], 1);
+    let yes = &tickers[0]["outcomes"][0];
+    assert_eq!(yes["option"], "Yes");
+    assert_eq!(yes["last_price"], 7.0);
+    assert_eq!(yes["change_24h"], 1.0);
+    assert_eq!(yes["high_24h"], 7.0);
+    assert_eq!(yes["low_24h"], 6.0);
+    assert_eq!(yes["volume_24h"], 12);
+    assert_eq!(yes["open_interest"], 12);
+    assert_eq!(yes["best_bid"], 5.0);
+    assert_eq!(yes["best_ask"], 7.0);
+
+    let no = &tickers[0]["outcomes"][1];
+    assert_eq!(no["option"], "No");
+    assert_eq!(no["last_price"], Value::Null);
+    assert_eq!(no["volume_24h"], 0);
+}
+
+#[test]
+fn a_retried_placement_is_applied_once_even_after_a_restart() {
+    let journal = env::temp_dir().join(format!("probo-retry-{}.journal", std::process::id()));
+    let _ = fs::remove_file(&journal);
+    let journal = journal.to_str().unwrap().to_string();
+    let args = ["--journal", journal.as_str()];
+    let order = json!({
+        "option": "Yes", "order_type": "Buy", "price": 6.5, "quantity": 10, "client_order_id": "c1"
+    });
+
+    let first = {
+        let server = start_server(&args);
+        let (status, first) =
+            request_as(&server, 1, "POST", "/markets/1/orders", Some(order.clone()));
+        assert_eq!(status, 201, "{}", first);
+        let (_, again) = request_as(&server, 1, "POST", "/markets/1/orders", Some(order.clone()));
+        assert_eq!(again["order"], first["order"]);
+        first
+    };
+    let journaled = fs::read_to_string(&journal).unwrap().lines().count();
+
+    // as if the answer got lost in a crash and the client retried against the next process
+    let server = start_server(&args);
+    let (status, retried) =
+        request_as(&server, 1, "POST", "/markets/1/orders", Some(order.clone()));
+    assert_eq!(status, 201, "{}", retried);
+    assert_eq!(retried["order"], first["order"]);
+    let (_, orders) = request_as(&server, 1, "GET", "/markets/1/orders", None);
+    assert_eq!(orders.as_array().unwrap().len(), 1);
+    assert_eq!(
+        fs::read_to_string(&journal).unwrap().lines().count(),
+        journaled
+    );
+
+    // the same id from someone else is a different order
+    let (_, placed) = request_as(&server, 2, "POST", "/markets/1/orders", Some(order));
+    assert_ne!(placed["order"]["id"], first["order"]["id"]);
+
+    fs::remove_file(journal).unwrap();
+}
+
+#[test]
+fn history_is_backfilled_from_before_the_latest_snapshot() {
+    let dir = env::temp_dir().join(format!("probo-history-snapshots-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let journal = dir.join("journal").to_str().unwrap().to_string();
+    let snapshots = dir.join("snapshots").to_str().unwrap().to_string();
+    let args = [
+        "--journal",
+        journal.as_str(),
+        "--snapshots",
+        snapshots.as_str(),
+        "--snapshot-every",
+        "2",
+    ];
+
+    {
+        let server = start_server(&args);
+        place(&server, 1, "Yes", "Sell", 6.0, 10);
+        place(&server, 2, "Yes", "Buy", 6.0, 4);
+        place(&server, 3, "Yes", "Buy", 6.0, 3);
+    }
+    assert!(fs::read_dir(&snapshots).unwrap().next().is_some());
+
+    // the engine starts from the snapshot after entry 2, the tape still begins at trade 1
+    let server = start_server(&args);
+    let (_, page) = request(&server, "GET", "/markets/1/trades", None);
+    let ids: Vec<u64> = page["trades"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(ids, [2, 1]);
+    let (_, candles) = request(&server, "GET", "/markets/1/candles/Yes?interval=1d", None);
+    assert_eq!(candles[0]["volume"], 7);
+    assert_eq!(candles[0]["trades"], 2);
+
+    drop(server);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn malformed_candle_intervals_are_refused_at_startup() {
+    for interval in ["1€", "5", "0m", "9999999999999d"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_probo-engine"))
+            .args(["http", "127.0.0.1:0", "--candle-intervals", interval])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("invalid --candle-intervals"), "{}", stderr);
+    }
+}
+
+#[test]
+fn a_user_sees_their_portfolio_marked_to_the_mid() {
+    let server = start_server(&[]);
+    place(&server, 2, "Yes", "Sell", 6.0, 10);
+    place(&server, 1, "Yes", "Buy", 6.0, 10);
+    place(&server, 3, "Yes", "Buy", 6.5, 1);
+    place(&server, 4, "Yes", "Sell", 7.5, 1);
+
+    let (status, portfolio) = request_as(&server, 1, "GET", "/portfolio/1", None);
+    assert_eq!(status, 200, "{}", portfolio);
+    assert_eq!(
+        portfolio,
+        json!([{
+            "market_id": 1, "option": "Yes", "quantity": 10, "avg_cost": 6.0,
+            "realized_pnl": 0.0, "mark_price": 7.0, "unrealized_pnl": 10.0
+        }])
+    );
+    assert_eq!(
+        request_as(&server, 5, "GET", "/portfolio/5", None).1,
+        json!([])
+    );
+    // only with the user's own token
+    assert_eq!(request_as(&server, 2, "GET", "/portfolio/1", None).0, 403);
+    assert_eq!(request(&server, "GET", "/portfolio/1", None).0, 401);
+}
@@ -0,0 +1,248 @@
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde_json::Value;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("probo-journal-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn engine(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_probo-engine"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+//runs the console over the script and hands back what it printed
+fn console(args: &[&str], script: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_probo-engine"))
+        .arg("repl")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+const TRADING: &str = "\
+    sell yes 6.0 30 user=1
+    buy yes 6.0 10 user=2
+    buy no 3.5 5 user=3
+    buy yes 5.0 15 user=4
+    amend 1 6.2 15
+    sell yes 6.2 5 user=5
+    cancel 4
+    buy yes 6.5 40 user=2
+    portfolio user=2
+    buy no 3.0 10 user=6
+    amend 6 6.4 15
+    sell yes 6.4 5 user=7
+    buy no 3.6 5 user=3
+    buy yes 7.1 5 user=8
+";
+
+//the snapshot the live engine took after journal entry k and the state replaying the first k
+//entries rebuilds are the same bytes
+#[test]
+fn replaying_the_journal_rebuilds_the_same_state() {
+    let dir = temp_dir("determinism");
+    let journal = dir.join("journal.jsonl");
+    let live = dir.join("live");
+    console(
+        &[
+            "--journal",
+            path(&journal),
+            "--snapshots",
+            path(&live),
+            "--snapshot-every",
+            "1",
+        ],
+        TRADING,
+    );
+
+    let entries: Vec<String> = fs::read_to_string(&journal)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    let mut compared = 0;
+    for seq in 1..entries.len() {
+        let name = format!("snapshot-{:020}.json", seq);
+        let Ok(expected) = fs::read(live.join(&name)) else {
+            continue;
+        };
+        let prefix = dir.join(format!("journal-{}.jsonl", seq));
+        fs::write(&prefix, entries[..seq].join("\n") + "\n").unwrap();
+        let replayed = dir.join(format!("replayed-{}", seq));
+        engine(&["replay", path(&prefix), "--write-snapshot", path(&replayed)]);
+        let actual = fs::read(replayed.join(&name)).unwrap();
+        assert!(expected == actual, "state after entry {} differs", seq);
+        compared += 1;
+    }
+    // every entry but the last, whose snapshot would only be taken by the next command
+    assert_eq!(compared, entries.len() - 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+//a crash in the middle of an append leaves half a line, the restart drops it and carries on
+//with the next sequence number
+#[test]
+fn a_torn_last_line_is_dropped_on_restart() {
+    let dir = temp_dir("torn");
+    let journal = dir.join("journal.jsonl");
+    let args = ["--journal", path(&journal)];
+    console(&args, TRADING);
+    let before = fs::read_to_string(&journal).unwrap();
+    let entries = before.lines().count() as u64;
+    let mut file = fs::OpenOptions::new().append(true).open(&journal).unwrap();
+    write!(
+        file,
+        r#"{{"seq":{},"timestamp":17,"command":{{"Place"#,
+        entries + 1
+    )
+    .unwrap();
+    drop(file);
+
+    let output = console(&args, "buy yes 5.0 1 user=9\norders user=2\n");
+    assert!(
+        output.contains("order 6: user 2 Buy 5 Yes at 6.40"),
+        "{}",
+        output
+    );
+
+    let after = fs::read_to_string(&journal).unwrap();
+    assert!(after.starts_with(&before));
+    let seqs: Vec<u64> = after
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<Value>(line).unwrap()["seq"]
+                .as_u64()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(seqs, (1..=entries + 1).collect::<Vec<_>>());
+    assert!(after.ends_with("\n"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+//the cancel used to carry the book side and price, whatever it said the order is taken off
+//the book it really rests in
+#[test]
+fn a_cancel_removes_the_order_wherever_it_rests() {
+    let dir = temp_dir("cancel");
+    let journal = dir.join("journal.jsonl");
+    fs::write(
+        &journal,
+        concat!(
+            r#"{"seq":1,"timestamp":1,"command":{"PlaceOrder":{"user_id":1,"option":"Yes","order_type":"Buy","price":5.0,"quantity":10}}}"#,
+            "\n",
+            r#"{"seq":2,"timestamp":2,"command":{"PlaceOrder":{"user_id":1,"option":"Yes","order_type":"Buy","price":4.0,"quantity":10}}}"#,
+            "\n",
+            r#"{"seq":3,"timestamp":3,"command":{"CancelOrder":{"user_id":1,"option":"No","order_type":"Sell","price":9.0,"order_id":1}}}"#,
+            "\n",
+            r#"{"seq":4,"timestamp":4,"command":{"CancelOrder":{"user_id":1,"order_id":2}}}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let output = engine(&["replay", journal.to_str().unwrap()]);
+    assert!(output.contains("Yes bids: {}, asks: {}"), "{}", output);
+    assert!(output.contains("No bids: {}, asks: {}"), "{}", output);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+//a restart from the latest snapshot replays only the entries after it and ends up where a
+//replay of the whole journal does
+#[test]
+fn recovery_from_a_snapshot_and_the_journal_tail_matches_a_full_replay() {
+    let dir = temp_dir("recover");
+    let journal = dir.join("journal.jsonl");
+    let snapshots = dir.join("snapshots");
+    console(
+        &[
+            "--journal",
+            path(&journal),
+            "--snapshots",
+            path(&snapshots),
+            "--snapshot-every",
+            "5",
+        ],
+        TRADING,
+    );
+    // 13 entries, the snapshots are after entries 5 and 10
+    assert!(snapshots.join(format!("snapshot-{:020}.json", 10)).exists());
+
+    let replayed = engine(&["replay", path(&journal)]);
+    let recovered = engine(&["recover", path(&journal), path(&snapshots)]);
+    let trade_ids = |output: &str| -> Vec<u64> {
+        output
+            .lines()
+            .filter(|line| line.starts_with('{'))
+            .map(|line| {
+                serde_json::from_str::<Value>(line).unwrap()["id"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect()
+    };
+    assert_eq!(trade_ids(&replayed), (1..=7).collect::<Vec<_>>());
+    assert_eq!(trade_ids(&recovered), vec![5, 6, 7]);
+    let books = |output: &str| -> Vec<String> {
+        output
+            .lines()
+            .filter(|line| !line.starts_with('{'))
+            .map(String::from)
+            .collect()
+    };
+    assert_eq!(books(&recovered), books(&replayed));
+
+    // the console picks up from the snapshot as well, with the same orders and ids
+    let script = "orders user=2\nbook yes\nbuy yes 5.0 1 user=9\n";
+    let from_snapshot = console(
+        &[
+            "--journal",
+            path(&journal),
+            "--snapshots",
+            path(&snapshots),
+            "--snapshot-every",
+            "5",
+        ],
+        script,
+    );
+    assert!(from_snapshot.contains("order 6: user 2 Buy 5 Yes at 6.40"));
+    assert!(from_snapshot.contains("order 11: user 9 Buy 1 Yes at 5.00 open"));
+    // the interval counts from the recovered state, one new order is not enough for a snapshot
+    assert!(!snapshots.join(format!("snapshot-{:020}.json", 13)).exists());
+    // the journal now ends with that order, a journal without it replays to the same answers
+    let written = fs::read_to_string(&journal).unwrap();
+    let lines: Vec<&str> = written.lines().collect();
+    let copy = dir.join("copy.jsonl");
+    fs::write(&copy, lines[..lines.len() - 1].join("\n") + "\n").unwrap();
+    let from_journal = console(&["--journal", path(&copy)], script);
+    assert_eq!(from_snapshot, from_journal);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::{env, fs, path::PathBuf, process::Command, thread, time::Duration};
+
+use serde_json::{Value, json};
+
+use common::{Server, request_as, start_server};
+
+fn limits_file(name: &str, config: Value) -> PathBuf {
+    let path = env::temp_dir().join(format!("probo-{}-{}.json", name, std::process::id()));
+    fs::write(&path, config.to_string()).unwrap();
+    path
+}
+
+fn bucket(burst: u32, per_second: f64) -> Value {
+    json!({
+        "new_orders": {"burst": burst, "per_second": per_second},
+        "cancels": {"burst": 100, "per_second": 100.0},
+        "queries": {"burst": 100, "per_second": 100.0}
+    })
+}
+
+fn place(server: &Server, user_id: u32) -> (u16, Value) {
+    request_as(
+        server,
+        user_id,
+        "POST",
+        "/markets/1/orders",
+        Some(json!({
+            "option": "Yes", "order_type": "Buy", "price": 5.0, "quantity": 1
+        })),
+    )
+}
+
+#[test]
+fn a_burst_is_let_through_then_throttled_until_it_refills() {
+    let path = limits_file("refill", json!({"classes": {"Retail": bucket(3, 4.0)}}));
+    let server = start_server(&["--rate-limits", path.to_str().unwrap()]);
+
+    for _ in 0..3 {
+        assert_eq!(place(&server, 1).0, 201);
+    }
+    let (status, body) = place(&server, 1);
+    assert_eq!(status, 429);
+    assert!(body["error"].as_str().unwrap().contains("rate limited"));
+    // every user has buckets of their own
+    assert_eq!(place(&server, 2).0, 201);
+
+    // 4 a second, one token is back after 250ms
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(place(&server, 1).0, 201);
+    assert_eq!(place(&server, 1).0, 429);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn market_makers_get_the_limits_of_their_class() {
+    let path = limits_file(
+        "classes",
+        json!({
+            "classes": {"Retail": bucket(1, 0.1), "MarketMaker": bucket(5, 0.1)},
+            "accounts": {"8": "MarketMaker"}
+        }),
+    );
+    let server = start_server(&[
+        "--rate-limits",
+        path.to_str().unwrap(),
+        "--market-makers",
+        "7",
+    ]);
+
+    assert_eq!(place(&server, 1).0, 201);
+    assert_eq!(place(&server, 1).0, 429);
+    for user_id in [7, 8] {
+        for _ in 0..5 {
+            assert_eq!(place(&server, user_id).0, 201);
+        }
+        assert_eq!(place(&server, user_id).0, 429);
+    }
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn a_limit_that_never_refills_is_refused_at_startup() {
+    let path = limits_file("zero", json!({"classes": {"Retail": bucket(5, 0.0)}}));
+    let output = Command::new(env!("CARGO_BIN_EXE_probo-engine"))
+        .args(["repl", "--rate-limits", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    fs::remove_file(path).unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("invalid --rate-limits: InvalidLimit { class: Retail, kind: NewOrder }"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,307 @@
+use std::{
+    env, fs,
+    process::{Child, Command},
+    thread,
+    time::{Duration, Instant},
+};
+
+use redis::Commands;
+
+// database 15 so the test never touches real queues
+const REDIS_URL: &str = "redis://127.0.0.1/15";
+
+struct Worker(Child);
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn wait_for_response(conn: &mut redis::Connection, request_id: &str) -> serde_json::Value {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let body: Option<String> = conn.get(format!("probo:response:{}", request_id)).unwrap();
+        if let Some(body) = body {
+            return serde_json::from_str(&body).unwrap();
+        }
+        assert!(Instant::now() < deadline, "no response for {}", request_id);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn request(request_id: &str, client_order_id: &str, price: f64) -> String {
+    serde_json::json!({
+        "request_id": request_id,
+        "market_id": 1,
+        "client_order_id": client_order_id,
+        "command": {"PlaceOrder": {
+            "user_id": 7, "option": "Yes", "order_type": "Buy", "price": price, "quantity": 10
+        }}
+    })
+    .to_string()
+}
+
+fn push(conn: &mut redis::Connection, request_id: &str, client_order_id: &str, price: f64) {
+    let request = request(request_id, client_order_id, price);
+    let _: i64 = conn.lpush("probo:orders", request).unwrap();
+}
+
+fn place(
+    conn: &mut redis::Connection,
+    request_id: &str,
+    user_id: u32,
+    side: &str,
+    price: f64,
+    quantity: u32,
+) -> serde_json::Value {
+    let request = serde_json::json!({
+        "request_id": request_id,
+        "market_id": 1,
+        "command": {"PlaceOrder": {
+            "user_id": user_id, "option": "Yes", "order_type": side,
+            "price": price, "quantity": quantity
+        }}
+    });
+    let _: i64 = conn.lpush("probo:orders", request.to_string()).unwrap();
+    wait_for_response(conn, request_id)
+}
+
+fn connect() -> redis::Connection {
+    let mut conn = redis::Client::open(REDIS_URL)
+        .unwrap()
+        .get_connection()
+        .unwrap();
+    let _: () = redis::cmd("FLUSHDB").query(&mut conn).unwrap();
+    conn
+}
+
+fn start_worker(args: &[&str]) -> Worker {
+    Worker(
+        Command::new(env!("CARGO_BIN_EXE_probo-engine"))
+            .args(["worker", REDIS_URL])
+            .args(args)
+            .spawn()
+            .unwrap(),
+    )
+}
+
+#[test]
+#[ignore = "needs a local redis-server, run with --ignored"]
+fn worker_applies_commands_once_per_client_order_id() {
+    let mut conn = redis::Client::open(REDIS_URL)
+        .unwrap()
+        .get_connection()
+        .unwrap();
+    let _: () = redis::cmd("FLUSHDB").query(&mut conn).unwrap();
+
+    let _worker = start_worker(&[]);
+
+    push(&mut conn, "r1", "c1", 6.5);
+    let first = wait_for_response(&mut conn, "r1");
+    assert_eq!(first["status"], "accepted");
+    assert_eq!(first["order"]["quantity"], 10);
+
+    // a retry of the same client order is answered with the original outcome
+    push(&mut conn, "r2", "c1", 6.5);
+    let retry = wait_for_response(&mut conn, "r2");
+    assert_eq!(retry["order"]["id"], first["order"]["id"]);
+
+    push(&mut conn, "r3", "c2", 20.0);
+    let rejected = wait_for_response(&mut conn, "r3");
+    assert_eq!(rejected["status"], "rejected");
+
+    let processing: i64 = conn.llen("probo:orders:processing").unwrap();
+    assert_eq!(processing, 0);
+
+    // accepted + rejected order, rejections do not advance the sequence
+    let events: redis::streams::StreamRangeReply =
+        conn.xrange_all("probo:market:1:events").unwrap();
+    let kinds: Vec<String> = events
+        .ids
+        .iter()
+        .map(|entry| entry.get::<String>("type").unwrap())
+        .filter(|kind| kind != "book_delta")
+        .collect();
+    assert_eq!(kinds, ["order_accepted", "order_rejected"]);
+}
+
+#[test]
+#[ignore = "needs a local redis-server, run with --ignored"]
+fn a_request_left_in_processing_is_redelivered_and_applied_once() {
+    let mut conn = redis::Client::open(REDIS_URL)
+        .unwrap()
+        .get_connection()
+        .unwrap();
+    let _: () = redis::cmd("FLUSHDB").query(&mut conn).unwrap();
+    let journal = env::temp_dir().join(format!("probo-redeliver-{}.journal", std::process::id()));
+    let _ = fs::remove_file(&journal);
+    let journal = journal.to_str().unwrap().to_string();
+
+    let first = {
+        let _worker = start_worker(&["--journal", &journal]);
+        push(&mut conn, "r1", "c1", 6.5);
+        wait_for_response(&mut conn, "r1")
+    };
+    assert_eq!(first["status"], "accepted");
+
+    // as if the worker died after applying r1 but before answering it
+    let _: () = conn.del("probo:response:r1").unwrap();
+    let _: i64 = conn
+        .lpush("probo:orders:processing", request("r1", "c1", 6.5))
+        .unwrap();
+
+    let _worker = start_worker(&["--journal", &journal]);
+    let redelivered = wait_for_response(&mut conn, "r1");
+    assert_eq!(redelivered["status"], "accepted");
+    assert_eq!(redelivered["order"], first["order"]);
+    let processing: i64 = conn.llen("probo:orders:processing").unwrap();
+    assert_eq!(processing, 0);
+    assert_eq!(fs::read_to_string(&journal).unwrap().lines().count(), 1);
+
+    fs::remove_file(journal).unwrap();
+}
+
+#[test]
+#[ignore = "needs a local redis-server, run with --ignored"]
+fn redelivered_requests_go_before_newer_ones() {
+    let mut conn = connect();
+    // left over by a crashed worker, the newest first as brpoplpush leaves them
+    for request_id in ["p1", "p2"] {
+        let _: i64 = conn
+            .lpush(
+                "probo:orders:processing",
+                request(request_id, request_id, 6.5),
+            )
+            .unwrap();
+    }
+    push(&mut conn, "n1", "n1", 6.5);
+    push(&mut conn, "n2", "n2", 6.5);
+
+    let _worker = start_worker(&[]);
+    wait_for_response(&mut conn, "n2");
+    for (order_id, request_id) in ["p1", "p2", "n1", "n2"].iter().enumerate() {
+        let response = wait_for_response(&mut conn, request_id);
+        assert_eq!(response["order"]["id"], order_id + 1, "{}", request_id);
+    }
+}
+
+#[test]
+#[ignore = "needs a local redis-server, run with --ignored"]
+fn trades_depth_and_tickers_go_out_on_the_market_channels() {
+    let mut conn = connect();
+    let mut subscriber = redis::Client::open(REDIS_URL)
+        .unwrap()
+        .get_connection()
+        .unwrap();
+    let mut pubsub = subscriber.as_pubsub();
+    for channel in ["trades", "depth", "ticker"] {
+        pubsub
+            .subscribe(format!("probo:market:1:{}", channel))
+            .unwrap();
+    }
+    pubsub
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let _worker = start_worker(&[]);
+
+    place(&mut conn, "r1", 1, "Sell", 6.0, 10);
+    place(&mut conn, "r2", 2, "Buy", 6.0, 4);
+
+    // the resting ask, then the fill against it
+    let mut trades = Vec::new();
+    let mut depth = Vec::new();
+    let mut last_ticker = serde_json::Value::Null;
+    while trades.is_empty() || last_ticker["last_price"] != 6.0 {
+        let message = pubsub.get_message().unwrap();
+        let body: serde_json::Value =
+            serde_json::from_str(&message.get_payload::<String>().unwrap()).unwrap();
+        match message.get_channel_name() {
+            "probo:market:1:trades" => trades.push(body),
+            "probo:market:1:depth" => depth.push(body),
+            _ if body["option"] == "Yes" => last_ticker = body,
+            _ => {}
+        }
+    }
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0]["price"], 6.0);
+    assert_eq!(trades[0]["quantity"], 4);
+    let ask = serde_json::json!({"option": "Yes", "side": "Sell", "price": 6.0, "quantity": 10});
+    assert_eq!(depth[0], serde_json::json!([ask]));
+    assert_eq!(depth.last().unwrap()[0]["quantity"], 6);
+    assert_eq!(last_ticker["best_ask"], 6.0);
+
+    // late subscribers read the latest ticker from the hash
+    let stored: String = conn.hget("probo:ticker:1", "Yes").unwrap();
+    let stored: serde_json::Value = serde_json::from_str(&stored).unwrap();
+    assert_eq!(stored, last_ticker);
+}
+
+#[test]
+#[ignore = "needs a local redis-server, run with --ignored"]
+fn consumer_groups_read_every_event_in_order_and_resume_after_a_crash() {
+    let mut conn = connect();
+    let _worker = start_worker(&[]);
+    place(&mut conn, "r1", 1, "Sell", 6.0, 10);
+    place(&mut conn, "r2", 2, "Buy", 6.0, 4);
+    place(&mut conn, "r3", 3, "Buy", 20.0, 1);
+
+    let stream = "probo:market:1:events";
+    let _: () = redis::cmd("XGROUP")
+        .arg("CREATE")
+        .arg(stream)
+        .arg("settlement")
+        .arg("0")
+        .query(&mut conn)
+        .unwrap();
+    let read = |conn: &mut redis::Connection, count: usize, from: &str| {
+        let reply: redis::streams::StreamReadReply = redis::cmd("XREADGROUP")
+            .arg("GROUP")
+            .arg("settlement")
+            .arg("c1")
+            .arg("COUNT")
+            .arg(count)
+            .arg("STREAMS")
+            .arg(stream)
+            .arg(from)
+            .query(conn)
+            .unwrap();
+        reply
+            .keys
+            .into_iter()
+            .flat_map(|key| key.ids)
+            .collect::<Vec<_>>()
+    };
+
+    // the consumer takes two events and dies before acking the second
+    let first = read(&mut conn, 2, ">");
+    assert_eq!(first.len(), 2);
+    let _: i64 = conn.xack(stream, "settlement", &[&first[0].id]).unwrap();
+
+    // on restart it gets what it did not ack, then carries on with the rest
+    let pending = read(&mut conn, 100, "0");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].id, first[1].id);
+    let rest = read(&mut conn, 100, ">");
+
+    let events: Vec<(u64, String)> = first
+        .iter()
+        .chain(&rest)
+        .map(|entry| (entry.get("seq").unwrap(), entry.get("type").unwrap()))
+        .collect();
+    let length: usize = conn.xlen(stream).unwrap();
+    assert_eq!(events.len(), length);
+    // state changes count up without gaps, the rejection at the end repeats the last one
+    let (rejected, changes) = events.split_last().unwrap();
+    assert_eq!(rejected.1, "order_rejected");
+    assert_eq!(rejected.0, changes.len() as u64);
+    let seqs: Vec<u64> = changes.iter().map(|(seq, _)| *seq).collect();
+    let expected: Vec<u64> = (1..=changes.len() as u64).collect();
+    assert_eq!(seqs, expected);
+    assert!(
+        changes.iter().any(|(_, kind)| kind == "trade"),
+        "{:?}",
+        events
+    );
+}
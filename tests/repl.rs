@@ -0,0 +1,283 @@
+use std::{
+    env, fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+//feeds the script to the console and hands back what it printed
+fn run(script: &str) -> String {
+    run_with(&[], script)
+}
+
+fn run_with(args: &[&str], script: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_probo-engine"))
+        .args(["repl", "--markets", "2"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn orders_trades_and_depth_are_printed() {
+    let output = run("\
+        buy yes 7.3 100 user=1\n\
+        buy yes 7.2 50 user=1\n\
+        user 2\n\
+        sell yes 7.3 30\n\
+        book yes\n\
+        price yes\n\
+        orders user=1\n");
+    assert!(output.contains("order 1: user 1 Buy 100 Yes at 7.30 open"));
+    assert!(
+        output
+            .contains("trade 1 (Direct): user 2 Sell 30 Yes at 7.30 against user 1 (Yes at 7.30)")
+    );
+    assert!(output.contains("order 3 filled"));
+    assert!(output.contains(
+        "market 1 Yes\n     qty      bid | ask      qty\n      70     7.30 |\n      50     7.20 |\n"
+    ));
+    assert!(output.contains("Yes bid 7.30 ask -"));
+    assert!(
+        output.contains("order 1: user 1 Buy 70 Yes at 7.30\norder 2: user 1 Buy 50 Yes at 7.20\n")
+    );
+}
+
+#[test]
+fn orders_can_be_amended_cancelled_and_markets_resolved() {
+    let output = run("\
+        sell no 3 40 user=5\n\
+        amend 1 3.1 20\n\
+        cancel 1\n\
+        cancel 1 user=5\n\
+        markets\n\
+        market 2\n\
+        buy no 2 10 user=6\n\
+        resolve no market=1\n\
+        buy no 2 10 user=6 market=1\n\
+        price no\n\
+        quit\n\
+        markets\n");
+    assert!(output.contains("order 1: user 5 Sell 20 No at 3.10 open"));
+    assert!(output.contains("cancelled order 1: user 5 Sell 20 No at 3.10"));
+    assert!(output.contains("error: order 1 is not open"));
+    assert!(output.contains("market 1 on shard 0 *\nmarket 2 on shard 1\n"));
+    assert!(output.contains("order 1: user 6 Buy 10 No at 2.00 open"));
+    assert!(output.contains("market 1 resolved No"));
+    assert!(output.contains("error: market is not open for trading"));
+    assert!(output.contains("No bid 2.00 ask -"));
+    // nothing after quit runs
+    assert_eq!(output.matches("on shard").count(), 2);
+}
+
+#[test]
+fn mistakes_are_explained() {
+    let output = run("\
+        buy yes 7.3 100\n\
+        buy maybe 7.3 100 user=1\n\
+        buy yes 7.3 lots user=1\n\
+        cancel 9\n\
+        book yes market=7\n\
+        launch\n");
+    assert!(output.contains("error: say who it is for with user=<id>, try help"));
+    assert!(output.contains("error: expected yes or no, try help"));
+    assert!(output.contains("error: lots is not a valid quantity, try help"));
+    assert!(output.contains("error: order 9 was not placed here"));
+    assert!(output.contains("error: market 7 does not exist"));
+    assert!(output.contains("error: unknown command launch, try help"));
+}
+
+#[test]
+fn orders_have_to_fit_the_balance_across_markets() {
+    let path = env::temp_dir().join(format!("probo-repl-balances-{}.json", std::process::id()));
+    fs::write(&path, r#"{"1": 100.0}"#).unwrap();
+    let output = run_with(
+        &["--balances", path.to_str().unwrap()],
+        "\
+        buy yes 5.0 12 user=1\n\
+        buy yes 4.0 10 user=1 market=2\n\
+        buy yes 5.0 1 user=1\n\
+        cancel 1\n\
+        buy yes 5.0 1 user=1\n",
+    );
+    fs::remove_file(&path).unwrap();
+    assert!(output.contains("order 1: user 1 Buy 12 Yes at 5.00 open"));
+    assert!(output.contains("order 1: user 1 Buy 10 Yes at 4.00 open"));
+    assert!(output.contains("error: order needs 5 of balance, only 0 is available"));
+    // cancelling releases what the order held
+    assert!(output.contains("order 2: user 1 Buy 1 Yes at 5.00 open"));
+}
+
+#[test]
+fn a_market_keeps_its_book_when_it_moves_to_another_shard() {
+    let output = run("\
+        sell yes 6.0 10 user=1 market=2\n\
+        move 2 0\n\
+        markets\n\
+        book yes market=2\n\
+        buy yes 6.0 4 user=2 market=2\n\
+        move 2 5\n\
+        move 9 0\n");
+    assert!(output.contains("moved market 2 to shard 0\n"));
+    assert!(output.contains("market 1 on shard 0 *\nmarket 2 on shard 0\n"));
+    assert!(output.contains(
+        "market 2 Yes\n     qty      bid | ask      qty\n                  | 6.00     10\n"
+    ));
+    assert!(output.contains("trade 1 (Direct): user 2 Buy 4 Yes at 6.00 against user 1"));
+    assert!(output.contains("error: shard 5 does not exist"));
+    assert!(output.contains("error: market 9 does not exist"));
+}
+
+#[test]
+fn holds_on_the_balance_move_with_the_market() {
+    let path = env::temp_dir().join(format!("probo-repl-move-{}.json", std::process::id()));
+    fs::write(&path, r#"{"1": 100.0}"#).unwrap();
+    let output = run_with(
+        &["--balances", path.to_str().unwrap()],
+        "\
+        buy yes 5.0 12 user=1 market=2\n\
+        move 2 0\n\
+        buy yes 5.0 10 user=1\n\
+        cancel 1 market=2\n\
+        buy yes 5.0 10 user=1\n",
+    );
+    fs::remove_file(&path).unwrap();
+    assert!(output.contains("moved market 2 to shard 0"));
+    assert!(output.contains("error: order needs 50 of balance, only 40 is available"));
+    assert!(output.contains("order 1: user 1 Buy 10 Yes at 5.00 open"));
+}
+
+#[test]
+fn rate_limits_count_across_markets() {
+    let path = env::temp_dir().join(format!("probo-repl-limits-{}.json", std::process::id()));
+    let limit = r#"{"burst": 2, "per_second": 0.1}"#;
+    let config = format!(
+        r#"{{"classes": {{"Retail": {{"new_orders": {limit}, "cancels": {limit}, "queries": {limit}}}}}}}"#
+    );
+    fs::write(&path, config).unwrap();
+    let output = run_with(
+        &["--rate-limits", path.to_str().unwrap()],
+        "\
+        buy yes 5.0 1 user=1\n\
+        buy yes 5.0 1 user=1 market=2\n\
+        buy yes 5.0 1 user=1\n",
+    );
+    fs::remove_file(&path).unwrap();
+    assert!(output.contains("order 1: user 1 Buy 1 Yes at 5.00 open\norder 1:"));
+    assert!(
+        output.contains("error: rate limited, retry after"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn a_market_is_settled_only_once() {
+    let path = env::temp_dir().join(format!("probo-repl-resolve-{}.json", std::process::id()));
+    fs::write(&path, r#"{"1": 100.0, "2": 100.0}"#).unwrap();
+    let output = run_with(
+        &["--balances", path.to_str().unwrap()],
+        "\
+        buy yes 5.0 10 user=1\n\
+        buy no 5.0 10 user=2\n\
+        resolve yes\n\
+        resolve yes\n\
+        resolve no\n\
+        buy yes 5.0 31 user=1 market=2\n\
+        buy yes 5.0 11 user=2 market=2\n",
+    );
+    fs::remove_file(&path).unwrap();
+    assert_eq!(output.matches("market 1 resolved").count(), 1, "{}", output);
+    assert_eq!(
+        output
+            .matches("error: market is not open for trading")
+            .count(),
+        2
+    );
+    // the yes buyer was paid 50 and the no buyer lost 50, once
+    assert!(output.contains("error: order needs 155 of balance, only 150 is available"));
+    assert!(output.contains("error: order needs 55 of balance, only 50 is available"));
+}
+
+#[test]
+fn holds_and_payouts_come_back_after_a_restart() {
+    let dir = env::temp_dir().join(format!("probo-repl-restart-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let balances = dir.join("balances.json");
+    fs::write(&balances, r#"{"1": 100.0, "2": 100.0, "3": 100.0}"#).unwrap();
+    let (journal, snapshots) = (dir.join("journal"), dir.join("snapshots"));
+    let args = [
+        "--balances",
+        balances.to_str().unwrap(),
+        "--journal",
+        journal.to_str().unwrap(),
+        "--snapshots",
+        snapshots.to_str().unwrap(),
+        "--snapshot-every",
+        "1",
+    ];
+    run_with(
+        &args,
+        "\
+        buy yes 5.0 10 user=1\n\
+        buy no 5.0 10 user=2\n\
+        buy yes 4.0 10 user=3\n",
+    );
+    // the fills come back from the snapshot, the resting order from the journal tail
+    let output = run_with(
+        &args,
+        "\
+        buy yes 5.0 11 user=1 market=2\n\
+        buy yes 5.0 13 user=3 market=2\n\
+        resolve yes\n",
+    );
+    assert!(output.contains("error: order needs 55 of balance, only 50 is available"));
+    assert!(output.contains("error: order needs 65 of balance, only 60 is available"));
+    let output = run_with(
+        &args,
+        "\
+        buy yes 5.0 31 user=1 market=2\n\
+        buy yes 5.0 11 user=2 market=2\n\
+        buy yes 5.0 21 user=3 market=2\n",
+    );
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.contains("error: order needs 155 of balance, only 150 is available"));
+    assert!(output.contains("error: order needs 55 of balance, only 50 is available"));
+    assert!(output.contains("error: order needs 105 of balance, only 100 is available"));
+}
+
+#[test]
+fn every_market_is_audited() {
+    let path = env::temp_dir().join(format!("probo-repl-audit-{}.jsonl", std::process::id()));
+    let _ = fs::remove_file(&path);
+    run_with(
+        &["--audit", path.to_str().unwrap()],
+        "\
+        buy yes 5.0 1 user=1\n\
+        buy yes 5.0 1 user=1 market=2\n\
+        market 3\n\
+        buy yes 5.0 1 user=1\n",
+    );
+    let markets: Vec<u64> = fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<serde_json::Value>(line).unwrap()["market_id"]
+                .as_u64()
+                .unwrap()
+        })
+        .collect();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(markets, [1, 2, 3]);
+}
@@ -0,0 +1,62 @@
+use std::{env, fs, path::PathBuf, process::Command};
+
+fn run(args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_probo-engine"))
+        .arg("scenario")
+        .args(args)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    (output.status.success(), stdout)
+}
+
+//every scenario under scenarios/ still produces its expected transcript,
+//after an intended change rerun with `probo-engine scenario scenarios/*.scenario --update`
+#[test]
+fn golden_scenarios_match() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+    let mut scenarios: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "scenario"))
+        .map(|path| path.to_str().unwrap().to_string())
+        .collect();
+    scenarios.sort();
+    assert!(!scenarios.is_empty());
+
+    let args: Vec<&str> = scenarios.iter().map(String::as_str).collect();
+    let (passed, stdout) = run(&args);
+    assert!(passed, "{}", stdout);
+    let ok = stdout
+        .lines()
+        .filter(|line| line.starts_with("ok "))
+        .count();
+    assert_eq!(ok, scenarios.len(), "{}", stdout);
+}
+
+#[test]
+fn a_changed_outcome_fails_with_a_diff() {
+    let dir = env::temp_dir().join(format!("probo-scenario-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("cross.scenario");
+    fs::write(&script, "sell yes 7.0 20 user=1\nbuy yes 7.0 5 user=2\n").unwrap();
+    let path = script.to_str().unwrap();
+
+    let (passed, stdout) = run(&[path, "--update"]);
+    assert!(passed, "{}", stdout);
+    assert!(run(&[path]).0);
+
+    // the trade is expected bigger than it is
+    let expected = dir.join("cross.expected");
+    let golden = fs::read_to_string(&expected).unwrap();
+    fs::write(&expected, golden.replace("Buy 5 Yes", "Buy 15 Yes")).unwrap();
+    let (passed, stdout) = run(&[path]);
+    assert!(!passed);
+    assert!(stdout.contains(&format!("FAILED {}", path)), "{}", stdout);
+    assert!(stdout.contains("- trade 1 (Direct): user 2 Buy 15 Yes at 7.00 against user 1"));
+    assert!(stdout.contains("+ trade 1 (Direct): user 2 Buy 5 Yes at 7.00 against user 1"));
+    // unchanged lines far from the change are left out
+    assert!(!stdout.contains("final books"), "{}", stdout);
+
+    fs::remove_dir_all(dir).unwrap();
+}
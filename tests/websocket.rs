@@ -0,0 +1,215 @@
+mod common;
+
+use std::{
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use serde_json::{Value, json};
+use tungstenite::{Message, WebSocket, stream::MaybeTlsStream};
+
+use common::{Server, request, request_as, start_server, token};
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+fn connect(server: &Server) -> Socket {
+    let (mut socket, _) = tungstenite::connect(format!("ws://{}/ws", server.addr)).unwrap();
+    if let MaybeTlsStream::Plain(stream) = socket.get_mut() {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+    }
+    socket
+}
+
+fn send(socket: &mut Socket, message: Value) {
+    socket.send(Message::text(message.to_string())).unwrap();
+}
+
+//next message of the given type, anything else in between is skipped
+fn next_of_type(socket: &mut Socket, kind: &str) -> Value {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        assert!(Instant::now() < deadline, "no {} message", kind);
+        let Ok(message) = socket.read() else {
+            continue;
+        };
+        let Message::Text(text) = message else {
+            continue;
+        };
+        let value: Value = serde_json::from_str(text.as_str()).unwrap();
+        if value["type"] == kind {
+            return value;
+        }
+    }
+}
+
+fn place(server: &Server, user_id: u32, side: &str, price: f64, quantity: u32) {
+    let (status, body) = request_as(
+        server,
+        user_id,
+        "POST",
+        "/markets/1/orders",
+        Some(json!({
+            "option": "Yes", "order_type": side, "price": price, "quantity": quantity
+        })),
+    );
+    assert_eq!(status, 201, "{}", body);
+}
+
+#[test]
+fn depth_snapshot_is_followed_by_incremental_updates() {
+    let server = start_server(&[]);
+    place(&server, 1, "Buy", 6.0, 10);
+
+    let mut socket = connect(&server);
+    send(
+        &mut socket,
+        json!({"op": "subscribe", "channel": "depth", "market_id": 1}),
+    );
+    let snapshot = next_of_type(&mut socket, "depth_snapshot");
+    assert_eq!(snapshot["books"][0]["option"], "Yes");
+    assert_eq!(
+        snapshot["books"][0]["bids"],
+        json!([{"price": 6.0, "quantity": 10}])
+    );
+    // update ids are the book_seq of the last level 2 delta in them
+    let (_, book) = request(&server, "GET", "/markets/1/book", None);
+    assert_eq!(snapshot["update_id"], book["book_seq"]);
+
+    place(&server, 1, "Buy", 6.0, 5);
+    let update = next_of_type(&mut socket, "depth_update");
+    let (_, book) = request(&server, "GET", "/markets/1/book", None);
+    assert_eq!(update["update_id"], book["book_seq"]);
+    assert_eq!(
+        update["changes"],
+        json!([{"option": "Yes", "side": "Buy", "price": 6.0, "quantity": 15}])
+    );
+}
+
+#[test]
+fn trades_and_ticker_are_public() {
+    let server = start_server(&[]);
+    let mut socket = connect(&server);
+    for channel in ["trades", "ticker"] {
+        send(
+            &mut socket,
+            json!({"op": "subscribe", "channel": channel, "market_id": 1}),
+        );
+        next_of_type(&mut socket, "subscribed");
+    }
+
+    place(&server, 1, "Sell", 6.4, 10);
+    place(&server, 2, "Buy", 6.4, 4);
+    let trade = next_of_type(&mut socket, "trade");
+    assert_eq!(trade["trade"]["price"], 6.4);
+    assert_eq!(trade["trade"]["quantity"], 4);
+    let ticker = next_of_type(&mut socket, "ticker");
+    assert_eq!(ticker["ticker"]["option"], "Yes");
+    assert_eq!(ticker["ticker"]["last_price"], 6.4);
+    assert_eq!(ticker["ticker"]["best_ask"], 6.4);
+}
+
+#[test]
+fn private_order_feed_needs_a_token_and_only_shows_own_orders() {
+    let server = start_server(&[]);
+
+    let mut socket = connect(&server);
+    let orders = json!({"op": "subscribe", "channel": "orders", "market_id": 1});
+    send(&mut socket, orders.clone());
+    next_of_type(&mut socket, "error");
+
+    send(&mut socket, json!({"op": "auth", "token": "wrong"}));
+    next_of_type(&mut socket, "error");
+    send(&mut socket, json!({"op": "auth", "token": token(2)}));
+    let authenticated = next_of_type(&mut socket, "authenticated");
+    assert_eq!(authenticated["user_id"], 2);
+    send(&mut socket, orders);
+    next_of_type(&mut socket, "subscribed");
+
+    place(&server, 1, "Sell", 6.0, 10);
+    place(&server, 2, "Buy", 6.0, 3);
+    let mut kinds = Vec::new();
+    while kinds.len() < 2 {
+        let event = next_of_type(&mut socket, "order")["event"]["event"].take();
+        let owner = event["user_id"]
+            .as_u64()
+            .or(event["order"]["user_id"].as_u64());
+        assert_eq!(owner, Some(2), "{}", event);
+        kinds.push(event["type"].as_str().unwrap().to_string());
+    }
+    assert_eq!(kinds, ["order_accepted", "order_filled"]);
+}
+
+#[test]
+fn book_deltas_apply_on_top_of_a_sequenced_snapshot() {
+    let server = start_server(&[]);
+    place(&server, 1, "Sell", 6.5, 10);
+
+    // subscribe first, then take the snapshot, so no delta falls in between
+    let mut socket = connect(&server);
+    send(
+        &mut socket,
+        json!({"op": "subscribe", "channel": "book_deltas", "market_id": 1}),
+    );
+    next_of_type(&mut socket, "subscribed");
+    let (status, snapshot) = request(&server, "GET", "/markets/1/book", None);
+    assert_eq!(status, 200);
+    assert_eq!(snapshot["book_seq"], 1);
+    assert_eq!(
+        snapshot["books"][0]["asks"],
+        json!([{"price": 6.5, "quantity": 10}])
+    );
+
+    place(&server, 2, "Buy", 6.0, 7);
+    place(&server, 3, "Buy", 6.5, 10);
+    let mut deltas = Vec::new();
+    for _ in 0..2 {
+        let delta = next_of_type(&mut socket, "book_delta");
+        deltas.push((
+            delta["book_seq"].as_u64().unwrap(),
+            delta["side"].as_str().unwrap().to_string(),
+            delta["price"].as_f64().unwrap(),
+            delta["quantity"].as_u64().unwrap(),
+        ));
+    }
+    // a resting bid, then the ask level taken out by the cross
+    assert_eq!(
+        deltas,
+        [
+            (2, "Buy".to_string(), 6.0, 7),
+            (3, "Sell".to_string(), 6.5, 0)
+        ]
+    );
+
+    let (_, snapshot) = request(&server, "GET", "/markets/1/book", None);
+    assert_eq!(snapshot["book_seq"], 3);
+    assert_eq!(snapshot["books"][0]["asks"], json!([]));
+    assert_eq!(
+        snapshot["books"][0]["bids"],
+        json!([{"price": 6.0, "quantity": 7}])
+    );
+}
+
+#[test]
+fn markets_are_followed_from_their_first_subscription() {
+    let server = start_server(&[]);
+    let mut socket = connect(&server);
+    send(
+        &mut socket,
+        json!({"op": "subscribe", "channel": "ticker", "market_id": 9}),
+    );
+    let error = next_of_type(&mut socket, "error");
+    assert_eq!(error["message"], "market 9 does not exist");
+
+    // a market nobody asked about yet starts from the current book
+    place(&server, 1, "Sell", 6.4, 10);
+    send(
+        &mut socket,
+        json!({"op": "subscribe", "channel": "ticker", "market_id": 1}),
+    );
+    let ticker = next_of_type(&mut socket, "ticker");
+    assert_eq!(ticker["ticker"]["option"], "Yes");
+    assert_eq!(ticker["ticker"]["best_ask"], 6.4);
+    assert_eq!(ticker["ticker"]["last_price"], Value::Null);
+}
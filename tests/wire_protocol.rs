@@ -0,0 +1,277 @@
+mod common;
+// the client library is std only, so the tests drive the server through it directly
+#[allow(dead_code)]
+#[path = "../src/wire.rs"]
+mod wire;
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use common::{Server, start_mode};
+use wire::{Client, Outcome, RejectCode, Request, Response, Side, read_frame};
+
+fn tokens_file(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("probo-wire-{}-{}.json", name, std::process::id()));
+    fs::write(&path, r#"{"maker-token": 7, "taker-token": 8}"#).unwrap();
+    path
+}
+
+fn start(tokens: &Path) -> Server {
+    start_mode("binary", &["--tokens", tokens.to_str().unwrap()])
+}
+
+fn connect(server: &Server, token: &str) -> Client {
+    Client::connect(&server.addr, token).unwrap().0
+}
+
+#[test]
+fn frames_have_a_fixed_layout() {
+    let new_order = Request::NewOrder {
+        client_id: 1,
+        market_id: 2,
+        outcome: Outcome::No,
+        side: Side::Sell,
+        price: 650,
+        quantity: 100,
+    };
+    assert_eq!(
+        new_order.encode(),
+        [
+            0, 23, 0x10, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 1, 1, 0, 0, 2, 138, 0, 0, 0, 100
+        ]
+    );
+    let fill = Response::Fill {
+        market_id: 1,
+        order_id: 3,
+        price: 600,
+        quantity: 40,
+        remaining: 60,
+    };
+    assert_eq!(
+        fill.encode(),
+        [
+            0, 25, 0x92, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 2, 88, 0, 0, 0, 40, 0, 0, 0, 60
+        ]
+    );
+
+    let requests = [
+        Request::Login {
+            token: "maker-token".to_string(),
+        },
+        new_order,
+        Request::Cancel {
+            client_id: u64::MAX,
+            market_id: 1,
+            order_id: 9,
+        },
+        Request::Amend {
+            client_id: 4,
+            market_id: 1,
+            order_id: 9,
+            price: 1,
+            quantity: u32::MAX,
+        },
+    ];
+    for request in requests {
+        let frame = request.encode();
+        assert_eq!(
+            frame.len(),
+            u16::from_be_bytes([frame[0], frame[1]]) as usize + 2
+        );
+        assert_eq!(Request::decode(&frame[2..]).unwrap(), request);
+    }
+    let responses = [
+        Response::LoggedIn { user_id: 7 },
+        Response::Ack {
+            client_id: 1,
+            market_id: 1,
+            order_id: 2,
+            price: 650,
+            quantity: 0,
+        },
+        Response::Reject {
+            client_id: 1,
+            code: RejectCode::InsufficientBalance,
+        },
+        fill,
+    ];
+    for response in responses {
+        let frame = response.encode();
+        assert_eq!(read_frame(&mut &frame[..]).unwrap(), &frame[2..]);
+        assert_eq!(Response::decode(&frame[2..]).unwrap(), response);
+    }
+
+    // a body of the wrong size or an unknown type never decodes
+    assert!(Request::decode(&[0x11, 0, 0]).is_err());
+    assert!(Request::decode(&[0x7f]).is_err());
+    assert!(Response::decode(&[0x91, 0, 0, 0, 0, 0, 0, 0, 1, 99]).is_err());
+}
+
+#[test]
+fn orders_are_acked_filled_amended_and_cancelled() {
+    let tokens = tokens_file("orders");
+    let server = start(&tokens);
+    let (mut maker, user_id) = Client::connect(&server.addr, "maker-token").unwrap();
+    assert_eq!(user_id, 7);
+    let mut taker = connect(&server, "taker-token");
+
+    let id = maker
+        .new_order(1, Outcome::Yes, Side::Buy, 600, 100)
+        .unwrap();
+    let Response::Ack {
+        client_id,
+        market_id,
+        order_id,
+        price,
+        quantity,
+    } = maker.wait(id).unwrap()
+    else {
+        panic!("quote rejected");
+    };
+    assert_eq!((client_id, market_id, price, quantity), (id, 1, 600, 100));
+
+    let id = taker
+        .new_order(1, Outcome::Yes, Side::Sell, 600, 40)
+        .unwrap();
+    assert!(matches!(
+        taker.wait(id).unwrap(),
+        Response::Ack { quantity: 0, .. }
+    ));
+    assert!(matches!(
+        taker.recv().unwrap(),
+        Response::Fill {
+            price: 600,
+            quantity: 40,
+            remaining: 0,
+            ..
+        }
+    ));
+    assert_eq!(
+        maker.recv().unwrap(),
+        Response::Fill {
+            market_id: 1,
+            order_id,
+            price: 600,
+            quantity: 40,
+            remaining: 60,
+        }
+    );
+
+    let id = maker.amend(1, order_id, 610, 50).unwrap();
+    assert!(matches!(
+        maker.wait(id).unwrap(),
+        Response::Ack {
+            price: 610,
+            quantity: 50,
+            ..
+        }
+    ));
+    // only the owner may touch an order
+    let id = taker.cancel(1, order_id).unwrap();
+    assert_eq!(
+        taker.wait(id).unwrap(),
+        Response::Reject {
+            client_id: id,
+            code: RejectCode::NotOrderOwner
+        }
+    );
+    let id = maker.cancel(1, order_id).unwrap();
+    assert!(matches!(
+        maker.wait(id).unwrap(),
+        Response::Ack { quantity: 0, .. }
+    ));
+    let id = maker.cancel(1, order_id).unwrap();
+    assert_eq!(
+        maker.wait(id).unwrap(),
+        Response::Reject {
+            client_id: id,
+            code: RejectCode::UnknownOrder
+        }
+    );
+
+    fs::remove_file(tokens).unwrap();
+}
+
+#[test]
+fn requests_are_rejected_with_codes() {
+    let tokens = tokens_file("rejects");
+    let server = start(&tokens);
+    assert!(Client::connect(&server.addr, "wrong-token").is_err());
+
+    let mut client = connect(&server, "maker-token");
+    let pipelined = [
+        client.new_order(1, Outcome::Yes, Side::Buy, 1200, 10),
+        client.new_order(1, Outcome::No, Side::Buy, 300, 0),
+        client.new_order(9, Outcome::No, Side::Buy, 300, 10),
+        client.amend(1, 77, 300, 10),
+    ];
+    let codes = [
+        RejectCode::PriceOutOfRange,
+        RejectCode::ZeroQuantity,
+        RejectCode::UnknownMarket,
+        RejectCode::UnknownOrder,
+    ];
+    // answers come back in request order
+    for (id, code) in pipelined.into_iter().zip(codes) {
+        assert_eq!(
+            client.recv().unwrap(),
+            Response::Reject {
+                client_id: id.unwrap(),
+                code
+            }
+        );
+    }
+
+    // orders before a login, then garbage that ends the connection
+    let mut raw = TcpStream::connect(&server.addr).unwrap();
+    raw.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let order = Request::Cancel {
+        client_id: 5,
+        market_id: 1,
+        order_id: 1,
+    };
+    raw.write_all(&order.encode()).unwrap();
+    assert_eq!(
+        Response::decode(&read_frame(&mut raw).unwrap()).unwrap(),
+        Response::Reject {
+            client_id: 5,
+            code: RejectCode::NotLoggedIn
+        }
+    );
+    raw.write_all(&[0, 3, 0x7f, 1, 2]).unwrap();
+    assert_eq!(
+        Response::decode(&read_frame(&mut raw).unwrap()).unwrap(),
+        Response::Reject {
+            client_id: 0,
+            code: RejectCode::Malformed
+        }
+    );
+    assert_eq!(raw.read(&mut [0u8; 8]).unwrap(), 0);
+
+    fs::remove_file(tokens).unwrap();
+}
+
+#[test]
+fn bench_runs_against_the_server() {
+    let tokens = tokens_file("bench");
+    // quoting bots run on the market maker limits
+    let server = start_mode(
+        "binary",
+        &["--tokens", tokens.to_str().unwrap(), "--market-makers", "7"],
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_probo-engine"))
+        .args(["wire-bench", &server.addr, "maker-token", "--rounds", "20"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("60 round trips"), "{}", stdout);
+    assert_eq!(stdout.matches("filled at 500").count(), 2, "{}", stdout);
+
+    fs::remove_file(tokens).unwrap();
+}